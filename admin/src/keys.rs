@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use rsweb_crypto::ed25519::KeyStore;
+
+pub fn list(key_store: &KeyStore) {
+    for key in key_store.keys() {
+        println!(
+            "{}  {:<11}  created {}{}",
+            key.kid(),
+            format!("{:?}", key.state()),
+            key.created_at(),
+            key.demoted_at()
+                .map(|t| format!(", demoted {}", t))
+                .unwrap_or_default()
+        );
+    }
+}
+
+pub async fn rotate(key_store: &KeyStore, grace: Duration) {
+    let key = match key_store.rotate(grace).await {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Failed to rotate keyring: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Rotated keyring, new active key: {}", key.kid());
+    println!("Running instances pick up the new key on their next keyring reload");
+}
+
+pub async fn retire(key_store: &KeyStore, kid: &str) {
+    if let Err(e) = key_store.retire(kid).await {
+        eprintln!("Failed to retire key {}: {}", kid, e);
+        std::process::exit(1);
+    }
+
+    println!("Retired key {}", kid);
+}
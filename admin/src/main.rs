@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use dotenvy::dotenv;
+use rsweb_crypto::ed25519::KeyStore;
+
+mod keys;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Manage the token signing keyring
+    #[command(subcommand)]
+    Keys(KeysCommand),
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// List all keys and their state
+    List,
+    /// Generate a new active key and demote the current one to verify-only
+    Rotate {
+        /// Seconds a demoted key keeps verifying before it is retired, at
+        /// least the access token lifetime (the default)
+        #[arg(long)]
+        grace_secs: Option<u64>,
+    },
+    /// Retire a verify-only key immediately
+    Retire { kid: String },
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
+    let cli = Cli::parse();
+    let config = match rsweb_config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let key_store = match KeyStore::from_config(&config.keys).await {
+        Ok(key_store) => key_store,
+        Err(e) => {
+            eprintln!("Failed to open keyring: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match cli.command {
+        Commands::Keys(KeysCommand::List) => keys::list(&key_store),
+        Commands::Keys(KeysCommand::Rotate { grace_secs }) => {
+            // Tokens signed by the demoted key have to expire before it goes
+            let lifetime = config.tokens.access_token_lifetime;
+            let grace = grace_secs.map_or(lifetime, Duration::from_secs);
+            if grace < lifetime {
+                eprintln!(
+                    "The grace period must be at least the access token lifetime ({} seconds)",
+                    lifetime.as_secs()
+                );
+                std::process::exit(1);
+            }
+
+            keys::rotate(&key_store, grace).await
+        }
+        Commands::Keys(KeysCommand::Retire { kid }) => keys::retire(&key_store, &kid).await,
+    }
+}
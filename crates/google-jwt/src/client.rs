@@ -1,42 +1,107 @@
+use crate::AsyncKeyProvider;
 use crate::error::GoogleError;
+use crate::provider::ProviderConfig;
 use crate::token::IdPayload;
 use crate::token::Token;
 use crate::unverified_token::UnverifiedToken;
-use crate::AsyncKeyProvider;
-use crate::GoogleKeyProvider;
+use crate::validation::ValidationOptions;
+use crate::{GoogleKeyProvider, JwksKeyProvider};
 use serde::Deserialize;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
 
 pub type ClientAsync = GenericClientAsync<GoogleKeyProvider>;
 
 pub struct GenericClientAsync<T> {
-    client_id: String,
-    key_provider: Arc<Mutex<T>>,
-    check_expiration: bool,
+    provider: ProviderConfig,
+    key_provider: Arc<T>,
+    validation: ValidationOptions,
 }
 
+// Google client for the given client id
 impl<KP: Default> GenericClientAsync<KP> {
     pub fn new(client_id: &str) -> Self {
         Self {
-            client_id: client_id.to_owned(),
-            key_provider: Arc::new(Mutex::new(KP::default())),
-            check_expiration: true,
+            provider: ProviderConfig::google(client_id),
+            key_provider: Arc::new(KP::default()),
+            validation: ValidationOptions::default(),
         }
     }
 }
 
+impl GenericClientAsync<JwksKeyProvider> {
+    // Client for any OpenID Connect provider, keys come from its jwks_uri
+    pub fn for_provider(provider: ProviderConfig) -> Self {
+        let key_provider = JwksKeyProvider::new(&provider.jwks_uri);
+        Self::new_with_provider(provider, key_provider)
+    }
+
+    pub async fn discover(
+        name: &str,
+        issuer: &str,
+        audiences: Vec<String>,
+    ) -> Result<Self, GoogleError> {
+        Ok(Self::for_provider(
+            ProviderConfig::discover(name, issuer, audiences).await?,
+        ))
+    }
+}
+
 impl<KP> GenericClientAsync<KP> {
-    pub fn new_with_provider(client_id: &str, provider: KP) -> Self {
+    pub fn new_with_provider(provider: ProviderConfig, key_provider: KP) -> Self {
+        Self::new_with_shared_keys(provider, Arc::new(key_provider))
+    }
+
+    // Clients for the same provider can share one key cache
+    pub fn new_with_shared_keys(provider: ProviderConfig, key_provider: Arc<KP>) -> Self {
         Self {
-            client_id: client_id.to_string(),
-            key_provider: Arc::new(Mutex::new(provider)),
-            check_expiration: true,
+            provider,
+            key_provider,
+            validation: ValidationOptions::default(),
         }
     }
 
+    pub fn provider(&self) -> &ProviderConfig {
+        &self.provider
+    }
+
+    pub fn validation(&self) -> &ValidationOptions {
+        &self.validation
+    }
+
+    pub fn with_validation(mut self, validation: ValidationOptions) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.validation.leeway = leeway;
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.validation.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_hosted_domains(mut self, hosted_domains: Vec<String>) -> Self {
+        self.validation.hosted_domains = hosted_domains;
+        self
+    }
+
+    pub fn require_email_verified(mut self) -> Self {
+        self.validation.require_email_verified = true;
+        self
+    }
+
+    // Replaces the client ids tokens may be issued to
+    pub fn with_audiences(mut self, audiences: Vec<String>) -> Self {
+        self.provider.audiences = audiences;
+        self
+    }
+
     pub fn unsafe_ignore_expiration(mut self) -> Self {
-        self.check_expiration = false;
+        self.validation.check_expiration = false;
         self
     }
 }
@@ -50,10 +115,15 @@ impl<KP: AsyncKeyProvider> GenericClientAsync<KP> {
         for<'a> P: Deserialize<'a> + Send + Sync,
     {
         let unverified_token =
-            UnverifiedToken::<P>::validate(token_string, self.check_expiration, &self.client_id)?;
+            UnverifiedToken::<P>::validate(token_string, &self.provider, &self.validation)?;
 
-        let mut key_provider = self.key_provider.lock().await;
-        unverified_token.verify_async(&mut *key_provider).await
+        unverified_token
+            .verify_async(self.key_provider.as_ref())
+            .await
+    }
+
+    pub async fn prefetch_keys_async(&self) -> Result<(), GoogleError> {
+        Ok(self.key_provider.prefetch_async().await?)
     }
 
     pub async fn verify_id_token_async(
@@ -63,3 +133,163 @@ impl<KP: AsyncKeyProvider> GenericClientAsync<KP> {
         self.verify_token_with_payload_async(token_string).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_MAX_TTL;
+    use crate::KeyProviderError;
+    use crate::test_support::{MockJwksServer, encode, key_set, sign_token, sign_token_hs256};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn claims(audience: &str) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        format!(
+            r#"{{"iss":"https://sso.example.com","sub":"1234","aud":"{}","iat":{},"exp":{},"email":"user@example.com"}}"#,
+            audience,
+            now,
+            now + 300
+        )
+    }
+
+    #[tokio::test]
+    async fn test_verify_against_jwks_endpoint() {
+        let server = MockJwksServer::start().await;
+        let client = ClientAsync::for_provider(ProviderConfig::new(
+            "sso",
+            "https://sso.example.com",
+            &server.url,
+            vec!["web".to_string()],
+        ));
+
+        let token = client
+            .verify_id_token_async(&sign_token("main", &claims("web")))
+            .await
+            .unwrap();
+        assert_eq!(token.claims.subject, "1234");
+        assert_eq!(token.payload.email.as_deref(), Some("user@example.com"));
+
+        assert_eq!(
+            client
+                .verify_id_token_async(&sign_token("main", &claims("mobile")))
+                .await
+                .err(),
+            Some(GoogleError::InvalidAudience)
+        );
+        assert_eq!(server.hits(), 1);
+
+        // A key the cached set doesn't have is looked up again, but not more
+        // often than the minimum TTL allows
+        let rotating = ClientAsync::new_with_provider(
+            client.provider().clone(),
+            JwksKeyProvider::new(&server.url).with_ttl(Duration::ZERO, DEFAULT_MAX_TTL),
+        );
+        assert!(
+            rotating
+                .verify_id_token_async(&sign_token("main", &claims("web")))
+                .await
+                .is_ok()
+        );
+        assert_eq!(server.hits(), 2);
+
+        server.respond(
+            200,
+            Some("public, max-age=3600"),
+            &key_set().replace(r#""kid":"main""#, r#""kid":"rotated""#),
+        );
+        assert_eq!(
+            client
+                .verify_id_token_async(&sign_token("rotated", &claims("web")))
+                .await
+                .err(),
+            Some(GoogleError::InvalidToken("missing json web key"))
+        );
+        assert_eq!(server.hits(), 2);
+        assert!(
+            rotating
+                .verify_id_token_async(&sign_token("rotated", &claims("web")))
+                .await
+                .is_ok()
+        );
+        assert_eq!(server.hits(), 3);
+
+        // Accepting more audiences and restricting to a hosted domain
+        let client = ClientAsync::for_provider(client.provider().clone())
+            .with_audiences(vec!["web".to_string(), "mobile".to_string()])
+            .with_hosted_domains(vec!["example.com".to_string()]);
+        assert_eq!(
+            client
+                .verify_id_token_async(&sign_token("main", &claims("mobile")))
+                .await
+                .err(),
+            Some(GoogleError::HostedDomainMismatch)
+        );
+
+        // The endpoint being down is reported as such
+        let unreachable = ClientAsync::for_provider(ProviderConfig::new(
+            "sso",
+            "https://sso.example.com",
+            "http://127.0.0.1:1/certs",
+            vec!["web".to_string()],
+        ));
+        assert!(matches!(
+            unreachable
+                .verify_id_token_async(&sign_token("main", &claims("web")))
+                .await,
+            Err(GoogleError::RetrieveKeyFailure(KeyProviderError::Http(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_symmetric_keys() {
+        let server = MockJwksServer::start().await;
+        let provider = ProviderConfig::new(
+            "sso",
+            "https://sso.example.com",
+            &server.url,
+            vec!["web".to_string()],
+        );
+        let secret = b"secret-shared-with-the-issuer";
+
+        // A secret key in the fetched set would let anyone reading the set
+        // sign tokens, it is never used
+        server.respond(
+            200,
+            None,
+            &format!(
+                r#"{{"keys":[{{"kty":"oct","kid":"shared","alg":"HS256","k":"{}"}}]}}"#,
+                encode(secret)
+            ),
+        );
+        let client = ClientAsync::for_provider(provider.clone());
+        assert_eq!(
+            client
+                .verify_id_token_async(&sign_token_hs256("shared", secret, &claims("web")))
+                .await
+                .err(),
+            Some(GoogleError::InvalidToken("missing json web key"))
+        );
+
+        // Configured locally it is
+        let client = ClientAsync::new_with_provider(
+            provider,
+            JwksKeyProvider::new(&server.url).with_symmetric_key("shared", secret),
+        );
+        assert!(
+            client
+                .verify_id_token_async(&sign_token_hs256("shared", secret, &claims("web")))
+                .await
+                .is_ok()
+        );
+        assert_eq!(
+            client
+                .verify_id_token_async(&sign_token_hs256("shared", b"guessed", &claims("web")))
+                .await
+                .err(),
+            Some(GoogleError::InvalidToken("invalid token"))
+        );
+    }
+}
@@ -11,15 +11,42 @@ pub enum GoogleError {
     // #[error("Failed to deserialize data: {0}")]
     SerdeError(String),
 
-    // #[error("Failed to retrieve the key")]
-    RetrieveKeyFailure,
+    // #[error("Failed to retrieve the key: {0}")]
+    RetrieveKeyFailure(KeyProviderError),
+
+    // #[error("Failed to fetch the discovery document")]
+    DiscoveryFailure,
 
     // #[error("Unsupported algorithm: {0:?}")]
     UnsupportedAlgorithm(Algorithm),
 
+    // #[error("Algorithm {0:?} doesn't match the key")]
+    AlgorithmMismatch(Algorithm),
+
+    // #[error("Token is not meant for this client")]
+    InvalidAudience,
+
+    // #[error("Token comes from another issuer")]
+    InvalidIssuer,
+
     // #[error("JWT token has expired")]
     Expired,
 
+    // #[error("Token is not valid yet")]
+    NotYetValid,
+
+    // #[error("Token was issued in the future")]
+    IssuedInFuture,
+
+    // #[error("Token was issued too long ago")]
+    TooOld,
+
+    // #[error("Account doesn't belong to an allowed hosted domain")]
+    HostedDomainMismatch,
+
+    // #[error("Email address is not verified")]
+    EmailNotVerified,
+
     // #[error("Mutex poisoned")]
     MutexPoisoned,
 }
@@ -30,11 +57,24 @@ impl std::fmt::Display for GoogleError {
             GoogleError::InvalidToken(msg) => write!(f, "Invalid token: {}", msg),
             GoogleError::Base64Error(e) => e.fmt(f),
             GoogleError::SerdeError(msg) => write!(f, "Failed to deserialize data: {}", msg),
-            GoogleError::RetrieveKeyFailure => write!(f, "Failed to retrieve the key"),
+            GoogleError::RetrieveKeyFailure(e) => write!(f, "Failed to retrieve the key: {}", e),
+            GoogleError::DiscoveryFailure => write!(f, "Failed to fetch the discovery document"),
             GoogleError::UnsupportedAlgorithm(algo) => {
                 write!(f, "Unsupported algorithm: {:?}", algo)
             }
+            GoogleError::AlgorithmMismatch(algo) => {
+                write!(f, "Algorithm {:?} doesn't match the key", algo)
+            }
+            GoogleError::InvalidAudience => write!(f, "Token is not meant for this client"),
+            GoogleError::InvalidIssuer => write!(f, "Token comes from another issuer"),
             GoogleError::Expired => write!(f, "JWT token has expired"),
+            GoogleError::NotYetValid => write!(f, "Token is not valid yet"),
+            GoogleError::IssuedInFuture => write!(f, "Token was issued in the future"),
+            GoogleError::TooOld => write!(f, "Token was issued too long ago"),
+            GoogleError::HostedDomainMismatch => {
+                write!(f, "Account doesn't belong to an allowed hosted domain")
+            }
+            GoogleError::EmailNotVerified => write!(f, "Email address is not verified"),
             GoogleError::MutexPoisoned => write!(f, "Mutex poisoned"),
         }
     }
@@ -53,3 +93,33 @@ impl From<serde_json::Error> for GoogleError {
         GoogleError::SerdeError(err.to_string())
     }
 }
+
+impl From<KeyProviderError> for GoogleError {
+    fn from(err: KeyProviderError) -> Self {
+        GoogleError::RetrieveKeyFailure(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyProviderError {
+    // #[error("Request failed: {0}")]
+    Http(String),
+
+    // #[error("Unexpected status code: {0}")]
+    Status(u16),
+
+    // #[error("Invalid key set: {0}")]
+    InvalidKeySet(String),
+}
+
+impl std::fmt::Display for KeyProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyProviderError::Http(msg) => write!(f, "Request failed: {}", msg),
+            KeyProviderError::Status(status) => write!(f, "Unexpected status code: {}", status),
+            KeyProviderError::InvalidKeySet(msg) => write!(f, "Invalid key set: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for KeyProviderError {}
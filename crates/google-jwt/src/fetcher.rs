@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+
+use crate::error::KeyProviderError;
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+// GET requests for key sets and discovery documents, taking them behind a
+// trait lets applications bring their own client and tests answer in process
+#[async_trait]
+pub trait HttpFetcher: Send + Sync {
+    async fn get(&self, url: &str) -> Result<HttpResponse, KeyProviderError>;
+}
+
+// Callers wait on key downloads, so a provider that stops answering must not
+// hold them forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct ReqwestFetcher {
+    client: reqwest::Client,
+}
+
+impl Default for ReqwestFetcher {
+    fn default() -> Self {
+        Self::new(
+            reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        )
+    }
+}
+
+impl ReqwestFetcher {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl HttpFetcher for ReqwestFetcher {
+    async fn get(&self, url: &str) -> Result<HttpResponse, KeyProviderError> {
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| KeyProviderError::Http(e.to_string()))?;
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| KeyProviderError::Http(e.to_string()))?;
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
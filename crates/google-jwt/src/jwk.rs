@@ -1,4 +1,5 @@
-use serde::Deserialize;
+use ring::signature::{self, RsaParameters, UnparsedPublicKey};
+use serde::{Deserialize, Deserializer};
 
 use crate::base64_decode;
 use crate::error::GoogleError;
@@ -6,6 +7,7 @@ use crate::structure::Algorithm;
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct JsonWebKeySet {
+    #[serde(deserialize_with = "supported_keys")]
     keys: Vec<JsonWebKey>,
 }
 
@@ -15,38 +17,283 @@ impl JsonWebKeySet {
     }
 }
 
+// Key sets can hold keys for other uses (e.g. RSA-OAEP encryption) or of types
+// this crate doesn't know, those are left out instead of failing the whole set.
+// So are secret (oct) keys, see KeyParameters::Oct.
+fn supported_keys<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<JsonWebKey>, D::Error> {
+    let keys = Vec::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(keys
+        .into_iter()
+        .filter(|key| key.get("use").and_then(|usage| usage.as_str()) != Some("enc"))
+        .filter_map(|key| serde_json::from_value(key).ok())
+        .collect())
+}
+
+// Key material by key type (RFC 7518 section 6, RFC 8037 for OKP)
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kty")]
+pub enum KeyParameters {
+    #[serde(rename = "RSA")]
+    Rsa { n: String, e: String },
+    #[serde(rename = "EC")]
+    Ec { crv: String, x: String, y: String },
+    #[serde(rename = "OKP")]
+    Okp { crv: String, x: String },
+    // A key that can verify HS* tokens can also sign them, so it is never
+    // taken from a fetched key set: anyone who can read the set could issue
+    // tokens. Secret keys only come from local configuration, see
+    // JsonWebKey::symmetric.
+    #[serde(skip)]
+    Oct { k: Vec<u8> },
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct JsonWebKey {
-    #[serde(rename = "alg")]
-    algorithm: Algorithm,
+    // Optional, many providers (e.g. Microsoft) leave it out
+    #[serde(rename = "alg", default)]
+    algorithm: Option<Algorithm>,
     #[serde(rename = "kid")]
     id: String,
-    n: String,
-    e: String,
+    #[serde(flatten)]
+    parameters: KeyParameters,
+}
+
+fn decode(value: &str, error: &'static str) -> Result<Vec<u8>, GoogleError> {
+    base64_decode(value).map_err(|_| GoogleError::InvalidToken(error))
 }
 
 impl JsonWebKey {
+    // Secret key shared with the issuer, for HS256, HS384 and HS512
+    pub fn symmetric(key_id: &str, secret: &[u8]) -> Self {
+        Self {
+            algorithm: None,
+            id: key_id.to_string(),
+            parameters: KeyParameters::Oct { k: secret.to_vec() },
+        }
+    }
+
     pub fn get_id(&self) -> String {
         self.id.clone()
     }
 
-    pub fn verify(&self, message: &[u8], signature_bytes: &[u8]) -> Result<(), GoogleError> {
-        match self.algorithm {
-            Algorithm::RS256 => {
-                let n: Vec<u8> = base64_decode(&self.n)
-                    .map_err(|_| GoogleError::InvalidToken("unable decode JWK n (modulus)"))?;
-                let e: Vec<u8> = base64_decode(&self.e)
-                    .map_err(|_| GoogleError::InvalidToken("unable decode JWK e (exponent)"))?;
-                let pubkey = ring::signature::RsaPublicKeyComponents { n, e };
-                let res = pubkey.verify(
-                    &ring::signature::RSA_PKCS1_2048_8192_SHA256,
+    // The algorithm comes from the token header and has to fit the key, a key
+    // that declares its algorithm only verifies that one. Otherwise a token
+    // could e.g. claim HS256 and pass with the public RSA key as HMAC secret.
+    pub fn verify(
+        &self,
+        algorithm: Algorithm,
+        message: &[u8],
+        signature_bytes: &[u8],
+    ) -> Result<(), GoogleError> {
+        if self.algorithm.is_some_and(|alg| alg != algorithm) {
+            return Err(GoogleError::AlgorithmMismatch(algorithm));
+        }
+
+        let verified = match (&self.parameters, algorithm) {
+            (KeyParameters::Rsa { n, e }, _) => {
+                let parameters: &RsaParameters = match algorithm {
+                    Algorithm::RS256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+                    Algorithm::RS384 => &signature::RSA_PKCS1_2048_8192_SHA384,
+                    Algorithm::RS512 => &signature::RSA_PKCS1_2048_8192_SHA512,
+                    Algorithm::PS256 => &signature::RSA_PSS_2048_8192_SHA256,
+                    Algorithm::PS384 => &signature::RSA_PSS_2048_8192_SHA384,
+                    Algorithm::PS512 => &signature::RSA_PSS_2048_8192_SHA512,
+                    _ => return Err(GoogleError::AlgorithmMismatch(algorithm)),
+                };
+                let n = decode(n, "unable decode JWK n (modulus)")?;
+                let e = decode(e, "unable decode JWK e (exponent)")?;
+                signature::RsaPublicKeyComponents { n, e }
+                    .verify(parameters, message, signature_bytes)
+                    .is_ok()
+            }
+            (KeyParameters::Ec { .. }, Algorithm::ES512) => {
+                return Err(GoogleError::UnsupportedAlgorithm(algorithm));
+            }
+            (KeyParameters::Ec { crv, x, y }, Algorithm::ES256 | Algorithm::ES384) => {
+                // JWS carries ECDSA signatures as fixed size r || s
+                let (curve, parameters) = match algorithm {
+                    Algorithm::ES256 => ("P-256", &signature::ECDSA_P256_SHA256_FIXED),
+                    _ => ("P-384", &signature::ECDSA_P384_SHA384_FIXED),
+                };
+                if crv != curve {
+                    return Err(GoogleError::AlgorithmMismatch(algorithm));
+                }
+                let x = decode(x, "unable decode JWK x coordinate")?;
+                let y = decode(y, "unable decode JWK y coordinate")?;
+                let point = [&[0x04], x.as_slice(), y.as_slice()].concat();
+                UnparsedPublicKey::new(parameters, point)
+                    .verify(message, signature_bytes)
+                    .is_ok()
+            }
+            (KeyParameters::Okp { crv, x }, Algorithm::EdDSA) if crv == "Ed25519" => {
+                let x = decode(x, "unable decode JWK x (public key)")?;
+                UnparsedPublicKey::new(&signature::ED25519, x)
+                    .verify(message, signature_bytes)
+                    .is_ok()
+            }
+            (KeyParameters::Oct { k }, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) => {
+                let hmac_algorithm = match algorithm {
+                    Algorithm::HS256 => ring::hmac::HMAC_SHA256,
+                    Algorithm::HS384 => ring::hmac::HMAC_SHA384,
+                    _ => ring::hmac::HMAC_SHA512,
+                };
+                ring::hmac::verify(
+                    &ring::hmac::Key::new(hmac_algorithm, k),
                     message,
                     signature_bytes,
-                );
-                res.map_err(|_err| GoogleError::InvalidToken("invalid token"))?;
-                Ok(())
+                )
+                .is_ok()
             }
-            _ => Err(GoogleError::UnsupportedAlgorithm(self.algorithm)),
+            _ => return Err(GoogleError::AlgorithmMismatch(algorithm)),
+        };
+
+        if !verified {
+            return Err(GoogleError::InvalidToken("invalid token"));
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Generated with Python's cryptography package, every signature is over
+    // MESSAGE
+    const MESSAGE: &[u8] = b"eyJhbGciOiJub25lIn0.eyJzdWIiOiJ0ZXN0In0";
+    const RSA_KEY: &str = r#"{"kty":"RSA","kid":"rsa","use":"sig","n":"vFqvI8z8_MNYowfAZI_owGKrBBvVfpd6tM_4_TnON9cIMlb9m3gN9Wy2Oj0VIKfPQ8VA5WzZxeyUmpbEL51Sv7c7WQDrJWbv1f9rv4RRZe6jQJDkClSUzFrcvdbeVkC8lZXtfwStEZCPoQA7B2QqjuFFAAdMYtbs3R1frU2NHCzMd06Sj_ctuU4YPb16ndv0r2HbtISH9droMv3-w5lBTZnqZqJus3Xo98fKaL63v9OLF1WO52S4BkeNT2i2P9aOhX3aVjwnd-QMuUVTRORAGiNYqZVEAjJMw08-_tsJz1KoWgSWgFO-9iqRqz5WwqsNPPlDygVGVRLHljk1mJlgdw","e":"AQAB"}"#;
+    const RS256_SIGNATURE: &str = "dqkbpajJx-Isiv0ZVnaHwZrEPRt3jq-ud8yt_zoPW1b7OcSJkOPHXmRsYr4ZgT4lLTUqwd2YW6wBm0gYwYTQOLltpgAV1zv_pWWK5LT9UMa_6mg-uZB1gCBd-ItG1Bc2IuNvMMld3wSEUsVM3OWC9_qG-Hp3kMCTS-1SgooYHU-gpMfg0_S29TzYPfqv9uvf3f4FgBQHV1Vw1UbgMcaASY_XUMsKagGwlmAHMVzufBJlpZ2Z1Z412cLrqyNBGXWqaAqDoldRGSkFRwd_lxnlz80J67oGFCfxmBl420wt8qwmWUQCMHQQ9lJS1Ss5L8XZ_0gcwc9TxJ35T1W5XvBklg";
+    const RS384_SIGNATURE: &str = "ZlKjbsDAoTnUtURP25z4Q8uu4stBHWMQxr-fI9WLhTJnP8ANaoaDFKLz9Nw5niTfNgMxOQKbGtGdfrD-7Hsc37QHhgC0h2rOO5YbRTtTbyrUzHNKn-St1XMFRHGUxAYlS8yCex8vi9vbcfVrJiitN6jRubOYIIe_ThXB3UWp-4PkZIjxeubO4-Lgtg6NGzSYSE-ub912f-LQ6_SEZPgaLAihVWI9XwqYRW4FTE9Wl_-1YpCC7Z3dx6kRl7AKCYJFxcvDF8A0FQeMXSiQHWr8iiBfzd5ZIHOR6viHyh3gWTlrEHtxrpx67GpflCZN5KMMvd1YZJDJ9-On0u5T_TZlOQ";
+    const RS512_SIGNATURE: &str = "t_bpo5MlwdZjR012KcHP5ll-lVwJUfSr9nE26ryHhM2cbS1Uc3XmamaylZeE7_NjtaJMSq3FpR2ZzQ_sKqJWhCz_Q9Da9P22SuyCLiwB4Szcr9A9ahf4b4QFOsJhn5TFVzjfgbhUrXNwRp5e8hlqxp9pQKvEChiMQjDt9j8ZE-el8PG9X6AV9rEGrJ9I8lu3DwTwsL53TDkjPEcgqZzBDLz1JEl7bVVfcnPeLL0uM0bbpuvYtFRqb8mzHo_iK8tRCVjK98G4Dq9K0fZ1uGGsA4i6CfJrI4Q2R9luYcVz8Z3TpFErRTq0Vtxma491SVYYzjF-u0YoziioB3TjVtdbrQ";
+    const PS256_SIGNATURE: &str = "r_KBAVEjjwEV3Izpam4r-qIqQOJAcxAiEdwxyEKikBB1JwQLtulmK-E8keGNDy5lZ17Qzb-3bUwLXzWYsyDu1w0YDFFF4aPRWdiAID7b_hqepeqoaQC8msRLCY9V6XEn5O5ALB-QnwkxTMUNuL2eG7L4WvSrVgppXFUQpAXX-SyTN27rlOC3bSb0Wkwc42zVg_ZCX_hC_6ncaYJeW4JbNBrT3T0LsHMViLplwtL76lS8V6wOIqzw0sXnaCLNAkib8jU42C0hXFF1NUceBxPBkXKztEXCjdBsFDJEMskeeInjJGjky48JsoJiiZOiTdDDqqTbwE6hUlq3lzkXttzfRg";
+    const PS384_SIGNATURE: &str = "qeK4HsNjzkppD8ZenkEnDyUSjaw-WDtbyifAT4Kp8ktO72fgXrgTmskv864fs4Ma8LJKxffgJugtjFJ9O1xb0diUoEgFRKOW_Ob8VV42M8P6X2Hp6wyydumjxJJ4JUJjtZRgu43Dy6JAgftjZwMC8F-9nUTlc2HLrnPS8QUWlexbNdsFJeX0YKK0ucWx7HkeRThyCiDR1WQ_mC6TQwllC6KigZioaOcOG0evhpVoVXMdFoTl47SNoJEW-r0QlbxnMFanjPK_1_eZnDbR6IaHPKCq2cf8MYmEvb9hCZG4W8zj8Wk0FdbMBdvM2r02LIOL_CtKU0z7-U2i3t-yRxFn4A";
+    const PS512_SIGNATURE: &str = "UMUu6mQHePoQcPXnsEMyfjvn6ppSDy5T_ieLsko_XvS6sTb2wtDOZ7y2MqlCRuTspNZNwESpToKsWs-e0L5Yps1lFY7qhcPL3jPn3IdUurPFi1rSirBiG4xST3IaK6GzgnDM_hOG40H-oc_IsEVIbGOW_-DZ8rXKprcG5TCKQNvDcDH0JxRr6KRY5PwoxRC-uVNcEjLQxXcIPR2j6EjaE8UzN-vYZ28XyRCt3nYUeNuXSAYr6ZFdZEUMioTPcY7aJQ0mju_eSPFLN-9lhn2nikutZv2hCdUyv34_YlN97odkOz5IYhGenOmGWTz7zcRkFc_a1mPOw9z3neEteGe_0Q";
+    const EC256_KEY: &str = r#"{"kty":"EC","kid":"ec","crv":"P-256","x":"bEw7lT6okEUEgmV_CSZkcajnK-9Ul7xCuCpjiRqSLAk","y":"0h5PDkhTTZylhpmAxiKmWO49tYL5b0C7s1e5QtN20yU"}"#;
+    const ES256_SIGNATURE: &str =
+        "LyMG_j_oT_dENrYti7JJqiaQGzsjjoJQeOrwsP3okZZubbw5yLHe2QMVEGKDKUT1Cd8mhQ6TPVAKfbHfSpsKpw";
+    const EC384_KEY: &str = r#"{"kty":"EC","kid":"ec","crv":"P-384","x":"KAaeHFFpyQLeKnF3AzdcpBvdHABUwCs-VLakQ-OGcwHsC_alDc7o4DlwcfA7fl-b","y":"1_WNzkM2myiGsLZRnM0doPk2z3Q7COn0aaFlFFnsYAdaVtBnWO7dfP0VFwJB6sqw"}"#;
+    const ES384_SIGNATURE: &str = "eFwmIEyi3a4_ps1iq0QI-_N9FWrC4CC98PWzKkiQfTOC3605R_mT08Tx_p1ZhkwYKbHpsX1YNmTyS8ZTpjMQZcCEbiExQKX1Xx0w4T_98ixELGh97IWYIJxC6b7QZlwc";
+    const OKP_KEY: &str = r#"{"kty":"OKP","kid":"okp","crv":"Ed25519","x":"8Sb7Hy12BA6QVM4LnBR2C44XhM6ADwbCdr9kL5na8Fo"}"#;
+    const EDDSA_SIGNATURE: &str =
+        "lhmH5TeeKsouFtKLdcm1YezjztjuIVYBlpzwjWZnhbQNfxTqgyk_6ZLykFmz-McRZs7ljDs7yNikB-Kgyr80Ag";
+    const OCT_SECRET: &str =
+        "iBsbNuSjXMGHXwsIUFTbAVXcobP_RWnRE9r_sKkYFYs9LzGps9W-dyXhp9CmmF4Hyl6UCAYzaZg0LfogJ1OA9g";
+    const HS256_SIGNATURE: &str = "S0LO34eOFxf3CH2jIsfa6YGh3r7Mejuh2z3rAaMuycM";
+    const HS384_SIGNATURE: &str =
+        "mGEO6SDLPTqmK9QjDS6X7krl3feftKoKFirCIIWa_t6Gfpqt-ixZcLPMOkY4KnX7";
+    const HS512_SIGNATURE: &str =
+        "OCdRKH0MePm1LIMa_MVHx-IJcYLUA0wWkvnNdi5A_zabsQN6q1faIx_8AByS7G_1YsoEyBrFmtUbvGiRGEtw_A";
+    fn key(json: &str) -> JsonWebKey {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn oct_key() -> JsonWebKey {
+        JsonWebKey::symmetric("oct", &base64_decode(OCT_SECRET).unwrap())
+    }
+
+    fn check(key: &JsonWebKey, algorithm: Algorithm, signature: &str) {
+        let signature = base64_decode(signature).unwrap();
+        assert_eq!(key.verify(algorithm, MESSAGE, &signature), Ok(()));
+        assert_eq!(
+            key.verify(
+                algorithm,
+                b"eyJhbGciOiJub25lIn0.eyJzdWIiOiJhZG1pbiJ9",
+                &signature
+            ),
+            Err(GoogleError::InvalidToken("invalid token"))
+        );
+    }
+
+    #[test]
+    fn test_rsa() {
+        let rsa = key(RSA_KEY);
+        check(&rsa, Algorithm::RS256, RS256_SIGNATURE);
+        check(&rsa, Algorithm::RS384, RS384_SIGNATURE);
+        check(&rsa, Algorithm::RS512, RS512_SIGNATURE);
+        check(&rsa, Algorithm::PS256, PS256_SIGNATURE);
+        check(&rsa, Algorithm::PS384, PS384_SIGNATURE);
+        check(&rsa, Algorithm::PS512, PS512_SIGNATURE);
+
+        // A signature only passes with the algorithm it was made with
+        let signature = base64_decode(RS256_SIGNATURE).unwrap();
+        assert!(rsa.verify(Algorithm::RS512, MESSAGE, &signature).is_err());
+    }
+
+    #[test]
+    fn test_ec() {
+        check(&key(EC256_KEY), Algorithm::ES256, ES256_SIGNATURE);
+        check(&key(EC384_KEY), Algorithm::ES384, ES384_SIGNATURE);
+
+        let signature = base64_decode(ES256_SIGNATURE).unwrap();
+        assert_eq!(
+            key(EC384_KEY).verify(Algorithm::ES256, MESSAGE, &signature),
+            Err(GoogleError::AlgorithmMismatch(Algorithm::ES256))
+        );
+        assert_eq!(
+            key(EC256_KEY).verify(Algorithm::ES512, MESSAGE, &signature),
+            Err(GoogleError::UnsupportedAlgorithm(Algorithm::ES512))
+        );
+    }
+
+    #[test]
+    fn test_eddsa() {
+        check(&key(OKP_KEY), Algorithm::EdDSA, EDDSA_SIGNATURE);
+    }
+
+    #[test]
+    fn test_hmac() {
+        let oct = oct_key();
+        check(&oct, Algorithm::HS256, HS256_SIGNATURE);
+        check(&oct, Algorithm::HS384, HS384_SIGNATURE);
+        check(&oct, Algorithm::HS512, HS512_SIGNATURE);
+    }
+
+    #[test]
+    fn test_algorithm_confusion() {
+        let signature = base64_decode(RS256_SIGNATURE).unwrap();
+
+        // The key declares RS256
+        let pinned = key(&RSA_KEY.replace(r#""kty":"RSA""#, r#""kty":"RSA","alg":"RS256""#));
+        assert_eq!(pinned.verify(Algorithm::RS256, MESSAGE, &signature), Ok(()));
+        assert_eq!(
+            pinned.verify(Algorithm::PS256, MESSAGE, &signature),
+            Err(GoogleError::AlgorithmMismatch(Algorithm::PS256))
+        );
+
+        // Symmetric algorithms never use asymmetric keys and the other way round
+        let rsa = key(RSA_KEY);
+        assert_eq!(
+            rsa.verify(Algorithm::HS256, MESSAGE, &signature),
+            Err(GoogleError::AlgorithmMismatch(Algorithm::HS256))
+        );
+        assert_eq!(
+            oct_key().verify(Algorithm::RS256, MESSAGE, &signature),
+            Err(GoogleError::AlgorithmMismatch(Algorithm::RS256))
+        );
+        assert_eq!(
+            key(OKP_KEY).verify(Algorithm::ES256, MESSAGE, &signature),
+            Err(GoogleError::AlgorithmMismatch(Algorithm::ES256))
+        );
+    }
+
+    #[test]
+    fn test_key_set_skips_unsupported() {
+        let set: JsonWebKeySet = serde_json::from_str(&format!(
+            r#"{{"keys":[
+                {{"kty":"RSA","kid":"enc","alg":"RSA-OAEP","use":"enc","n":"AQAB","e":"AQAB"}},
+                {{"kty":"OKP","kid":"enc-okp","use":"enc","crv":"Ed25519","x":"AQAB"}},
+                {{"kty":"oct","kid":"oct","alg":"HS256","k":"{}"}},
+                {{"kty":"XYZ","kid":"other"}},
+                {}
+            ]}}"#,
+            OCT_SECRET, OKP_KEY
+        ))
+        .unwrap();
+
+        assert!(set.get_key("enc").is_none());
+        assert!(set.get_key("enc-okp").is_none());
+        assert!(set.get_key("oct").is_none());
+        assert!(set.get_key("other").is_none());
+        assert_eq!(set.get_key("okp").unwrap().get_id(), "okp");
     }
 }
@@ -1,65 +1,299 @@
+use crate::error::KeyProviderError;
+use crate::fetcher::{HttpFetcher, ReqwestFetcher};
 use crate::jwk::JsonWebKey;
 use crate::jwk::JsonWebKeySet;
+use crate::provider::GOOGLE_CERT_URL;
 use async_trait::async_trait;
 use headers::{Header, HeaderMap};
 use reqwest::header::CACHE_CONTROL;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
 
-const GOOGLE_CERT_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+// Bounds for how long a downloaded key set is used, whatever the endpoint's
+// Cache-Control says. Responses without max-age are kept for the minimum.
+pub const DEFAULT_MIN_TTL: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
 
 #[async_trait]
-pub trait AsyncKeyProvider {
-    async fn get_key_async(&mut self, key_id: &str) -> Result<Option<JsonWebKey>, ()>;
+pub trait AsyncKeyProvider: Send + Sync {
+    async fn get_key_async(&self, key_id: &str) -> Result<Option<JsonWebKey>, KeyProviderError>;
+
+    // Loads the keys ahead of the first verification, if the provider caches
+    async fn prefetch_async(&self) -> Result<(), KeyProviderError> {
+        Ok(())
+    }
 }
 
-pub struct GoogleKeyProvider {
-    cached: Option<JsonWebKeySet>,
+struct CachedKeys {
+    keys: JsonWebKeySet,
     expiration_time: Instant,
+    // Last time the endpoint was asked, whether that worked or not
+    checked_at: Instant,
+}
+
+// Keys of one provider, fetched from its JWKS endpoint and cached for as
+// long as the response allows
+pub struct JwksKeyProvider {
+    jwks_uri: String,
+    fetcher: Arc<dyn HttpFetcher>,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    cached: RwLock<Option<CachedKeys>>,
+    // Configured here instead of fetched, see JsonWebKey::symmetric
+    local_keys: Vec<JsonWebKey>,
+    // Held while downloading, callers that find the cache expired meanwhile
+    // wait for that download instead of starting their own
+    refresh: Mutex<()>,
 }
 
-impl Default for GoogleKeyProvider {
+pub type GoogleKeyProvider = JwksKeyProvider;
+
+impl Default for JwksKeyProvider {
     fn default() -> Self {
+        Self::new(GOOGLE_CERT_URL)
+    }
+}
+
+impl JwksKeyProvider {
+    pub fn new(jwks_uri: &str) -> Self {
         Self {
-            cached: None,
-            expiration_time: Instant::now(),
+            jwks_uri: jwks_uri.to_owned(),
+            fetcher: Arc::new(ReqwestFetcher::default()),
+            min_ttl: DEFAULT_MIN_TTL,
+            max_ttl: DEFAULT_MAX_TTL,
+            cached: RwLock::new(None),
+            local_keys: Vec::new(),
+            refresh: Mutex::new(()),
+        }
+    }
+
+    pub fn with_fetcher(mut self, fetcher: Arc<dyn HttpFetcher>) -> Self {
+        self.fetcher = fetcher;
+        self
+    }
+
+    // Accepts HS256, HS384 and HS512 tokens signed with the given secret
+    pub fn with_symmetric_key(mut self, key_id: &str, secret: &[u8]) -> Self {
+        self.local_keys.push(JsonWebKey::symmetric(key_id, secret));
+        self
+    }
+
+    pub fn with_ttl(mut self, min_ttl: Duration, max_ttl: Duration) -> Self {
+        self.min_ttl = min_ttl;
+        self.max_ttl = max_ttl.max(min_ttl);
+        self
+    }
+
+    pub fn jwks_uri(&self) -> &str {
+        &self.jwks_uri
+    }
+
+    fn ttl(&self, headers: &HeaderMap) -> Duration {
+        headers::CacheControl::decode(&mut headers.get_all(CACHE_CONTROL).iter())
+            .ok()
+            .and_then(|cache_control| cache_control.max_age())
+            .unwrap_or(self.min_ttl)
+            .clamp(self.min_ttl, self.max_ttl)
+    }
+
+    async fn download_keys_async(&self) -> Result<(JsonWebKeySet, Duration), KeyProviderError> {
+        let response = self.fetcher.get(&self.jwks_uri).await?;
+        if !(200..300).contains(&response.status) {
+            return Err(KeyProviderError::Status(response.status));
+        }
+        let key_set = serde_json::from_str(&response.body)
+            .map_err(|e| KeyProviderError::InvalidKeySet(e.to_string()))?;
+
+        Ok((key_set, self.ttl(&response.headers)))
+    }
+
+    // None when the key set has to be downloaded first: there is none, it has
+    // expired, or it lacks the key. A key id the set doesn't know may be a key
+    // the provider just rotated in, but unknown ids are also what anyone can
+    // put in a token, so for those the set is downloaded again at most once
+    // per minimum TTL.
+    async fn cached_key(&self, key_id: &str) -> Option<Option<JsonWebKey>> {
+        let cached = self.cached.read().await;
+        let cached = cached.as_ref()?;
+        let now = Instant::now();
+        if cached.expiration_time <= now {
+            return None;
+        }
+
+        match cached.keys.get_key(key_id) {
+            Some(key) => Some(Some(key)),
+            None if cached.checked_at + self.min_ttl > now => Some(None),
+            None => None,
         }
     }
 }
 
-impl GoogleKeyProvider {
-    fn process_response(&mut self, headers: &HeaderMap, text: &str) -> Result<&JsonWebKeySet, ()> {
-        let mut expiration_time = None;
-        let x = headers.get_all(CACHE_CONTROL);
-        if let Ok(cache_header) = headers::CacheControl::decode(&mut x.iter()) {
-            if let Some(max_age) = cache_header.max_age() {
-                expiration_time = Some(Instant::now() + max_age);
-            }
+#[async_trait]
+impl AsyncKeyProvider for JwksKeyProvider {
+    async fn get_key_async(&self, key_id: &str) -> Result<Option<JsonWebKey>, KeyProviderError> {
+        if let Some(key) = self.local_keys.iter().find(|key| key.get_id() == key_id) {
+            return Ok(Some(key.clone()));
         }
-        let key_set = serde_json::from_str(text).map_err(|_| ())?;
-        if let Some(expiration_time) = expiration_time {
-            self.cached = Some(key_set);
-            self.expiration_time = expiration_time;
+        if let Some(key) = self.cached_key(key_id).await {
+            return Ok(key);
+        }
+
+        let _refresh = self.refresh.lock().await;
+        if let Some(key) = self.cached_key(key_id).await {
+            return Ok(key);
+        }
+
+        match self.download_keys_async().await {
+            Ok((keys, ttl)) => {
+                let key = keys.get_key(key_id);
+                let now = Instant::now();
+                *self.cached.write().await = Some(CachedKeys {
+                    keys,
+                    expiration_time: now + ttl,
+                    checked_at: now,
+                });
+                Ok(key)
+            }
+            // Expired keys beat no keys while the endpoint is failing, the
+            // next download is tried after the minimum TTL
+            Err(e) => match self.cached.write().await.as_mut() {
+                Some(cached) => {
+                    let now = Instant::now();
+                    cached.expiration_time = cached.expiration_time.max(now + self.min_ttl);
+                    cached.checked_at = now;
+                    Ok(cached.keys.get_key(key_id))
+                }
+                None => Err(e),
+            },
         }
-        Ok(self.cached.as_ref().unwrap())
     }
 
-    async fn download_keys_async(&mut self) -> Result<&JsonWebKeySet, ()> {
-        let result = reqwest::get(GOOGLE_CERT_URL).await.map_err(|_| ())?;
-        self.process_response(
-            &result.headers().clone(),
-            &result.text().await.map_err(|_| ())?,
-        )
+    async fn prefetch_async(&self) -> Result<(), KeyProviderError> {
+        // Any lookup fills the cache
+        self.get_key_async("").await.map(|_| ())
     }
 }
 
-#[async_trait]
-impl AsyncKeyProvider for GoogleKeyProvider {
-    async fn get_key_async(&mut self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
-        if let Some(ref cached_keys) = self.cached {
-            if self.expiration_time > Instant::now() {
-                return Ok(cached_keys.get_key(key_id));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockJwksServer, key_set};
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_ttl_bounds() {
+        let provider = JwksKeyProvider::new("http://127.0.0.1/certs")
+            .with_ttl(Duration::from_secs(60), Duration::from_secs(3600));
+        let ttl = |cache_control: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(value) = cache_control {
+                headers.insert(CACHE_CONTROL, HeaderValue::from_static(value));
             }
+            provider.ttl(&headers)
+        };
+
+        assert_eq!(ttl(None), Duration::from_secs(60));
+        assert_eq!(ttl(Some("no-cache")), Duration::from_secs(60));
+        assert_eq!(ttl(Some("public, max-age=5")), Duration::from_secs(60));
+        assert_eq!(ttl(Some("public, max-age=600")), Duration::from_secs(600));
+        assert_eq!(ttl(Some("max-age=86400")), Duration::from_secs(3600));
+    }
+
+    #[tokio::test]
+    async fn test_cached_keys() {
+        let server = MockJwksServer::start().await;
+        let provider = JwksKeyProvider::new(&server.url);
+
+        assert!(provider.get_key_async("main").await.unwrap().is_some());
+        assert!(provider.get_key_async("other").await.unwrap().is_none());
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch() {
+        let server = MockJwksServer::start().await;
+        let provider = JwksKeyProvider::new(&server.url);
+
+        provider.prefetch_async().await.unwrap();
+        assert_eq!(server.hits(), 1);
+        assert!(provider.get_key_async("main").await.unwrap().is_some());
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shared_refresh() {
+        let server = MockJwksServer::start().await;
+        let provider = Arc::new(JwksKeyProvider::new(&server.url));
+
+        let lookups: Vec<_> = (0..16)
+            .map(|_| {
+                let provider = provider.clone();
+                tokio::spawn(async move { provider.get_key_async("main").await })
+            })
+            .collect();
+        for lookup in lookups {
+            assert!(lookup.await.unwrap().unwrap().is_some());
+        }
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_key_refresh() {
+        let server = MockJwksServer::start().await;
+        let provider = JwksKeyProvider::new(&server.url)
+            .with_ttl(Duration::from_millis(200), Duration::from_secs(3600));
+        assert!(provider.get_key_async("main").await.unwrap().is_some());
+
+        // The provider rotates to a new key while the cached set is still fresh
+        server.respond(
+            200,
+            Some("public, max-age=3600"),
+            &key_set().replace(r#""kid":"main""#, r#""kid":"rotated""#),
+        );
+        assert!(provider.get_key_async("rotated").await.unwrap().is_none());
+        assert_eq!(server.hits(), 1);
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(provider.get_key_async("rotated").await.unwrap().is_some());
+        assert_eq!(server.hits(), 2);
+
+        // Made up key ids don't get a download each
+        for _ in 0..8 {
+            assert!(provider.get_key_async("made-up").await.unwrap().is_none());
         }
-        Ok(self.download_keys_async().await?.get_key(key_id))
+        assert_eq!(server.hits(), 2);
+
+        // Nor do they while the endpoint is failing
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        server.respond(503, None, "unavailable");
+        assert!(provider.get_key_async("made-up").await.unwrap().is_none());
+        assert!(provider.get_key_async("made-up").await.unwrap().is_none());
+        assert!(provider.get_key_async("rotated").await.unwrap().is_some());
+        assert_eq!(server.hits(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_stale_keys_on_failure() {
+        let server = MockJwksServer::start().await;
+        let provider = JwksKeyProvider::new(&server.url).with_ttl(Duration::ZERO, Duration::ZERO);
+        assert!(provider.get_key_async("main").await.unwrap().is_some());
+
+        server.respond(503, None, "unavailable");
+        assert!(provider.get_key_async("main").await.unwrap().is_some());
+        assert_eq!(server.hits(), 2);
+
+        // Without keys from earlier the failure comes through
+        let fresh = JwksKeyProvider::new(&server.url);
+        assert_eq!(
+            fresh.get_key_async("main").await.unwrap_err(),
+            KeyProviderError::Status(503)
+        );
+
+        server.respond(200, None, "<html>");
+        assert!(matches!(
+            fresh.get_key_async("main").await,
+            Err(KeyProviderError::InvalidKeySet(_))
+        ));
     }
 }
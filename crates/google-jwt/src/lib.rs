@@ -1,15 +1,23 @@
 mod client;
 mod error;
+mod fetcher;
 mod jwk;
 mod key_provider;
+mod provider;
 mod structure;
+#[cfg(test)]
+mod test_support;
 mod token;
 mod unverified_token;
+mod validation;
 
 pub use crate::token::{IdPayload, RequiredClaims, Token};
 pub use client::*;
-pub use error::GoogleError;
+pub use error::{GoogleError, KeyProviderError};
+pub use fetcher::*;
 pub use key_provider::*;
+pub use provider::*;
+pub use validation::*;
 
 fn base64_decode(input: &str) -> Result<Vec<u8>, base64::DecodeError> {
     use base64::Engine as _;
@@ -0,0 +1,222 @@
+use serde::Deserialize;
+
+use crate::error::GoogleError;
+use crate::fetcher::{HttpFetcher, ReqwestFetcher};
+
+pub const GOOGLE_ISSUER: &str = "https://accounts.google.com";
+pub const GOOGLE_CERT_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+pub const GOOGLE_AUTHORIZATION_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+pub const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+// The subset of an OpenID Connect discovery document needed to verify ID
+// tokens, the endpoints are kept for clients running the code flow
+#[derive(Deserialize, Clone, Debug)]
+pub struct DiscoveryDocument {
+    pub issuer: String,
+    pub jwks_uri: String,
+    pub authorization_endpoint: Option<String>,
+    pub token_endpoint: Option<String>,
+}
+
+// Where ID tokens of a provider come from and who they are meant for, the
+// endpoints are only needed for the authorization code flow
+#[derive(Clone, Debug)]
+pub struct ProviderConfig {
+    pub name: String,
+    pub issuer: String,
+    pub jwks_uri: String,
+    pub audiences: Vec<String>,
+    pub authorization_endpoint: Option<String>,
+    pub token_endpoint: Option<String>,
+}
+
+impl ProviderConfig {
+    pub fn new(name: &str, issuer: &str, jwks_uri: &str, audiences: Vec<String>) -> Self {
+        Self {
+            name: name.to_owned(),
+            issuer: issuer.to_owned(),
+            jwks_uri: jwks_uri.to_owned(),
+            audiences,
+            authorization_endpoint: None,
+            token_endpoint: None,
+        }
+    }
+
+    pub fn with_endpoints(mut self, authorization_endpoint: &str, token_endpoint: &str) -> Self {
+        self.authorization_endpoint = Some(authorization_endpoint.to_owned());
+        self.token_endpoint = Some(token_endpoint.to_owned());
+        self
+    }
+
+    // Google's endpoints are well known, so it doesn't need a discovery round
+    pub fn google(client_id: &str) -> Self {
+        Self::new(
+            "google",
+            GOOGLE_ISSUER,
+            GOOGLE_CERT_URL,
+            vec![client_id.to_owned()],
+        )
+        .with_endpoints(GOOGLE_AUTHORIZATION_URL, GOOGLE_TOKEN_URL)
+    }
+
+    pub fn from_discovery(
+        name: &str,
+        document: &DiscoveryDocument,
+        audiences: Vec<String>,
+    ) -> Self {
+        Self {
+            authorization_endpoint: document.authorization_endpoint.clone(),
+            token_endpoint: document.token_endpoint.clone(),
+            ..Self::new(name, &document.issuer, &document.jwks_uri, audiences)
+        }
+    }
+
+    // Fetches {issuer}/.well-known/openid-configuration, the document has to
+    // name the same issuer it was fetched from (OpenID Connect Discovery 4.3)
+    pub async fn discover(
+        name: &str,
+        issuer: &str,
+        audiences: Vec<String>,
+    ) -> Result<Self, GoogleError> {
+        Self::discover_with(&ReqwestFetcher::default(), name, issuer, audiences).await
+    }
+
+    pub async fn discover_with(
+        fetcher: &dyn HttpFetcher,
+        name: &str,
+        issuer: &str,
+        audiences: Vec<String>,
+    ) -> Result<Self, GoogleError> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        );
+        let response = fetcher
+            .get(&url)
+            .await
+            .map_err(|_| GoogleError::DiscoveryFailure)?;
+        if !(200..300).contains(&response.status) {
+            return Err(GoogleError::DiscoveryFailure);
+        }
+        let document: DiscoveryDocument = serde_json::from_str(&response.body)?;
+
+        if document.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+            return Err(GoogleError::InvalidToken("discovery issuer mismatch"));
+        }
+
+        Ok(Self::from_discovery(name, &document, audiences))
+    }
+
+    // Google also issues tokens with the bare host as issuer
+    pub fn accepts_issuer(&self, issuer: &str) -> bool {
+        issuer == self.issuer
+            || (self.issuer == GOOGLE_ISSUER
+                && Some(issuer) == GOOGLE_ISSUER.strip_prefix("https://"))
+    }
+
+    pub fn accepts_audience(&self, audiences: &[String]) -> bool {
+        audiences.iter().any(|aud| self.audiences.contains(aud))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockJwksServer;
+
+    #[test]
+    fn test_google_issuers() {
+        let google = ProviderConfig::google("client");
+        assert!(google.accepts_issuer("https://accounts.google.com"));
+        assert!(google.accepts_issuer("accounts.google.com"));
+        assert!(!google.accepts_issuer("https://appleid.apple.com"));
+
+        let apple = ProviderConfig::new(
+            "apple",
+            "https://appleid.apple.com",
+            "https://appleid.apple.com/auth/keys",
+            vec!["com.example.web".to_string()],
+        );
+        assert!(apple.accepts_issuer("https://appleid.apple.com"));
+        assert!(!apple.accepts_issuer("appleid.apple.com"));
+    }
+
+    #[test]
+    fn test_audiences() {
+        let provider = ProviderConfig::new(
+            "keycloak",
+            "https://sso.example.com/realms/main",
+            "https://sso.example.com/realms/main/protocol/openid-connect/certs",
+            vec!["web".to_string(), "mobile".to_string()],
+        );
+        assert!(provider.accepts_audience(&["mobile".to_string()]));
+        assert!(provider.accepts_audience(&["account".to_string(), "web".to_string()]));
+        assert!(!provider.accepts_audience(&["account".to_string()]));
+        assert!(!provider.accepts_audience(&[]));
+    }
+
+    #[test]
+    fn test_discovery_document() {
+        let document: DiscoveryDocument = serde_json::from_str(
+            r#"{
+                "issuer": "https://login.microsoftonline.com/tenant/v2.0",
+                "jwks_uri": "https://login.microsoftonline.com/tenant/discovery/v2.0/keys",
+                "authorization_endpoint": "https://login.microsoftonline.com/tenant/oauth2/v2.0/authorize",
+                "response_types_supported": ["code", "id_token"]
+            }"#,
+        )
+        .unwrap();
+
+        let provider = ProviderConfig::from_discovery("microsoft", &document, vec!["app".into()]);
+        assert_eq!(
+            provider.issuer,
+            "https://login.microsoftonline.com/tenant/v2.0"
+        );
+        assert_eq!(
+            provider.jwks_uri,
+            "https://login.microsoftonline.com/tenant/discovery/v2.0/keys"
+        );
+        assert!(provider.authorization_endpoint.is_some());
+        assert!(provider.token_endpoint.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_discover() {
+        let server = MockJwksServer::start().await;
+        let issuer = server.url.trim_end_matches("/certs").to_string();
+        let fetcher = ReqwestFetcher::default();
+
+        server.respond(
+            200,
+            None,
+            &format!(r#"{{"issuer":"{}","jwks_uri":"{}"}}"#, issuer, server.url),
+        );
+        let provider = ProviderConfig::discover_with(&fetcher, "sso", &issuer, vec![])
+            .await
+            .unwrap();
+        assert_eq!(provider.jwks_uri, server.url);
+
+        // A document for another issuer is refused
+        server.respond(
+            200,
+            None,
+            &format!(
+                r#"{{"issuer":"https://evil.example.com","jwks_uri":"{}"}}"#,
+                server.url
+            ),
+        );
+        assert!(
+            ProviderConfig::discover_with(&fetcher, "sso", &issuer, vec![])
+                .await
+                .is_err()
+        );
+
+        server.respond(404, None, "");
+        assert_eq!(
+            ProviderConfig::discover_with(&fetcher, "sso", &issuer, vec![])
+                .await
+                .err(),
+            Some(GoogleError::DiscoveryFailure)
+        );
+    }
+}
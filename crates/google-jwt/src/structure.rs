@@ -11,10 +11,16 @@ pub enum Algorithm {
     ES256,
     ES384,
     ES512,
+    PS256,
+    PS384,
+    PS512,
+    EdDSA,
 }
 
 #[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
 pub struct Header {
+    #[serde(rename = "alg")]
+    pub algorithm: Algorithm,
     #[serde(rename = "kid")]
     pub key_id: String,
 }
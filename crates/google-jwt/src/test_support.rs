@@ -0,0 +1,129 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// Seed of the Ed25519 key the mock server publishes with kid "main"
+const SIGNING_SEED: &[u8; 32] = b"ed25519-seed-for-google-jwt-test";
+
+struct MockResponse {
+    status: u16,
+    cache_control: Option<String>,
+    body: String,
+}
+
+// JWKS endpoint on a local port, answers every request with the current
+// response and counts them
+pub struct MockJwksServer {
+    pub url: String,
+    hits: Arc<AtomicUsize>,
+    response: Arc<Mutex<MockResponse>>,
+}
+
+impl MockJwksServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/certs", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let response = Arc::new(Mutex::new(MockResponse {
+            status: 200,
+            cache_control: Some("public, max-age=3600".to_string()),
+            body: key_set(),
+        }));
+
+        let server = Self {
+            url,
+            hits: hits.clone(),
+            response: response.clone(),
+        };
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buffer[..n]),
+                    }
+                }
+                hits.fetch_add(1, Ordering::SeqCst);
+
+                let reply = {
+                    let response = response.lock().unwrap();
+                    let cache_control = response
+                        .cache_control
+                        .as_ref()
+                        .map(|value| format!("Cache-Control: {}\r\n", value))
+                        .unwrap_or_default();
+                    format!(
+                        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        response.status,
+                        cache_control,
+                        response.body.len(),
+                        response.body
+                    )
+                };
+                let _ = stream.write_all(reply.as_bytes()).await;
+            }
+        });
+
+        server
+    }
+
+    pub fn respond(&self, status: u16, cache_control: Option<&str>, body: &str) {
+        *self.response.lock().unwrap() = MockResponse {
+            status,
+            cache_control: cache_control.map(str::to_string),
+            body: body.to_string(),
+        };
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+}
+
+pub fn encode(data: &[u8]) -> String {
+    use base64::Engine as _;
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(data)
+}
+
+fn signing_key() -> Ed25519KeyPair {
+    Ed25519KeyPair::from_seed_unchecked(SIGNING_SEED).unwrap()
+}
+
+pub fn key_set() -> String {
+    format!(
+        r#"{{"keys":[{{"kty":"OKP","kid":"main","alg":"EdDSA","use":"sig","crv":"Ed25519","x":"{}"}}]}}"#,
+        encode(signing_key().public_key().as_ref())
+    )
+}
+
+fn signed_body(algorithm: &str, key_id: &str, claims: &str) -> String {
+    let header = format!(r#"{{"alg":"{}","kid":"{}"}}"#, algorithm, key_id);
+    format!(
+        "{}.{}",
+        encode(header.as_bytes()),
+        encode(claims.as_bytes())
+    )
+}
+
+// EdDSA token signed with the mock server's key
+pub fn sign_token(key_id: &str, claims: &str) -> String {
+    let signed_body = signed_body("EdDSA", key_id, claims);
+    let signature = signing_key().sign(signed_body.as_bytes());
+
+    format!("{}.{}", signed_body, encode(signature.as_ref()))
+}
+
+// HS256 token signed with the given secret
+pub fn sign_token_hs256(key_id: &str, secret: &[u8], claims: &str) -> String {
+    let signed_body = signed_body("HS256", key_id, claims);
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret);
+    let signature = ring::hmac::sign(&key, signed_body.as_bytes());
+
+    format!("{}.{}", signed_body, encode(signature.as_ref()))
+}
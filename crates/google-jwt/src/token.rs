@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
 pub struct Token<P> {
     pub claims: RequiredClaims,
@@ -19,22 +19,28 @@ pub struct RequiredClaims {
     #[serde(rename = "sub")]
     pub subject: String,
 
-    #[serde(rename = "aud")]
-    pub audience: String,
+    // A single string or a list, providers like Keycloak add their own
+    // audiences next to the client id
+    #[serde(rename = "aud", deserialize_with = "one_or_many")]
+    pub audience: Vec<String>,
 
-    #[serde(rename = "azp")]
-    pub android_audience: String,
+    #[serde(rename = "azp", default)]
+    pub android_audience: Option<String>,
 
     #[serde(rename = "iat")]
     pub issued_at: u64,
 
     #[serde(rename = "exp")]
     pub expires_at: u64,
+
+    #[serde(rename = "nbf", default)]
+    pub not_before: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct IdPayload {
     pub email: Option<String>,
+    #[serde(default, deserialize_with = "bool_or_string")]
     pub email_verified: Option<bool>,
     #[serde(default)]
     pub name: String,
@@ -45,4 +51,73 @@ pub struct IdPayload {
     pub locale: Option<String>,
     #[serde(rename = "hd")]
     pub domain: Option<String>,
+    // Echo of the nonce sent with an authorization request
+    pub nonce: Option<String>,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(audience) => vec![audience],
+        OneOrMany::Many(audiences) => audiences,
+    })
+}
+
+// Apple sends boolean claims as "true"/"false" strings
+pub(crate) fn bool_or_string<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<bool>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+
+    Ok(match Option::<BoolOrString>::deserialize(deserializer)? {
+        Some(BoolOrString::Bool(b)) => Some(b),
+        Some(BoolOrString::String(s)) => s.parse().ok(),
+        None => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claims_formats() {
+        let google: RequiredClaims = serde_json::from_str(
+            r#"{"iss":"https://accounts.google.com","sub":"1","aud":"client","azp":"client","iat":1,"exp":2}"#,
+        )
+        .unwrap();
+        assert_eq!(google.audience, vec!["client"]);
+        assert_eq!(google.android_audience.as_deref(), Some("client"));
+
+        let keycloak: RequiredClaims = serde_json::from_str(
+            r#"{"iss":"https://sso.example.com/realms/main","sub":"2","aud":["web","account"],"iat":1,"exp":2}"#,
+        )
+        .unwrap();
+        assert_eq!(keycloak.audience, vec!["web", "account"]);
+        assert!(keycloak.android_audience.is_none());
+    }
+
+    #[test]
+    fn test_email_verified_formats() {
+        let payload = |json| {
+            serde_json::from_str::<IdPayload>(json)
+                .unwrap()
+                .email_verified
+        };
+        assert_eq!(payload(r#"{"email_verified":true}"#), Some(true));
+        assert_eq!(payload(r#"{"email_verified":"true"}"#), Some(true));
+        assert_eq!(payload(r#"{"email_verified":"false"}"#), Some(false));
+        assert_eq!(payload(r#"{}"#), None);
+    }
 }
@@ -2,8 +2,10 @@ use serde::Deserialize;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::key_provider::AsyncKeyProvider;
+use crate::provider::ProviderConfig;
+use crate::validation::{IdentityClaims, ValidationOptions};
 use crate::{
-    base64_decode, jwk::JsonWebKey, structure::Header, GoogleError, RequiredClaims, Token,
+    GoogleError, RequiredClaims, Token, base64_decode, jwk::JsonWebKey, structure::Header,
 };
 
 pub struct UnverifiedToken<P> {
@@ -20,8 +22,8 @@ where
 {
     pub fn validate(
         token_string: &str,
-        check_expiration: bool,
-        client_id: &str,
+        provider: &ProviderConfig,
+        options: &ValidationOptions,
     ) -> Result<Self, GoogleError> {
         let mut segments = token_string.split('.');
         let encoded_header = segments
@@ -39,23 +41,12 @@ where
         let signature = base64_decode(encoded_signature)?;
         let payload = base64_decode(encoded_payload)?;
         let claims: RequiredClaims = serde_json::from_slice(&payload)?;
-        if claims.audience != client_id {
-            return Err(GoogleError::InvalidToken("invalid audience"));
-        }
-        let issuer = claims.issuer.clone();
-        if issuer != "https://accounts.google.com" && issuer != "accounts.google.com" {
-            return Err(GoogleError::InvalidToken("invalid issuer"));
-        }
+        let identity: IdentityClaims = serde_json::from_slice(&payload)?;
         let current_timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        if check_expiration && claims.expires_at < current_timestamp {
-            return Err(GoogleError::Expired);
-        }
-        if claims.issued_at > claims.expires_at {
-            return Err(GoogleError::InvalidToken("invalid expires at"));
-        }
+        options.check(provider, &claims, &identity, current_timestamp)?;
         let json_payload: P = serde_json::from_slice(&payload)?;
         Ok(Self {
             claims,
@@ -68,20 +59,20 @@ where
 }
 
 impl<P> UnverifiedToken<P> {
-    pub async fn verify_async<KP: AsyncKeyProvider>(
+    pub async fn verify_async<KP: AsyncKeyProvider + ?Sized>(
         self,
-        key_provider: &mut KP,
+        key_provider: &KP,
     ) -> Result<Token<P>, GoogleError> {
         let key_id = self.header.key_id.clone();
-        self.verify_with_key(key_provider.get_key_async(&key_id).await)
+        self.verify_with_key(key_provider.get_key_async(&key_id).await?)
     }
-    fn verify_with_key(self, key: Result<Option<JsonWebKey>, ()>) -> Result<Token<P>, GoogleError> {
-        let key = match key {
-            Ok(Some(key)) => key,
-            Ok(None) => return Err(GoogleError::InvalidToken("missing json web key")),
-            Err(_) => return Err(GoogleError::RetrieveKeyFailure),
-        };
-        key.verify(self.signed_body.as_bytes(), &self.signature)?;
+    fn verify_with_key(self, key: Option<JsonWebKey>) -> Result<Token<P>, GoogleError> {
+        let key = key.ok_or(GoogleError::InvalidToken("missing json web key"))?;
+        key.verify(
+            self.header.algorithm,
+            self.signed_body.as_bytes(),
+            &self.signature,
+        )?;
         Ok(Token::new(self.claims, self.json_payload))
     }
 }
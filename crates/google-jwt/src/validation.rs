@@ -0,0 +1,241 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::GoogleError;
+use crate::provider::ProviderConfig;
+use crate::token::{RequiredClaims, bool_or_string};
+
+// Clock skew between us and the provider tolerated by default
+pub const DEFAULT_LEEWAY: Duration = Duration::from_secs(60);
+
+// What a token has to satisfy besides a valid signature
+#[derive(Clone, Debug)]
+pub struct ValidationOptions {
+    // Tolerance applied to exp, nbf and iat
+    pub leeway: Duration,
+    pub check_expiration: bool,
+    // Refuses tokens issued longer ago, e.g. to demand a recent sign in
+    pub max_age: Option<Duration>,
+    // Google Workspace domains (hd claim) allowed to sign in, any when empty
+    pub hosted_domains: Vec<String>,
+    pub require_email_verified: bool,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        Self {
+            leeway: DEFAULT_LEEWAY,
+            check_expiration: true,
+            max_age: None,
+            hosted_domains: Vec::new(),
+            require_email_verified: false,
+        }
+    }
+}
+
+// Claims some of the options look at, read from the payload whatever type the
+// caller deserializes it into
+#[derive(Deserialize, Default, Debug)]
+pub(crate) struct IdentityClaims {
+    #[serde(rename = "hd")]
+    domain: Option<String>,
+    #[serde(default, deserialize_with = "bool_or_string")]
+    email_verified: Option<bool>,
+}
+
+impl ValidationOptions {
+    pub(crate) fn check(
+        &self,
+        provider: &ProviderConfig,
+        claims: &RequiredClaims,
+        identity: &IdentityClaims,
+        now: u64,
+    ) -> Result<(), GoogleError> {
+        let leeway = self.leeway.as_secs();
+
+        if !provider.accepts_audience(&claims.audience) {
+            return Err(GoogleError::InvalidAudience);
+        }
+        if !provider.accepts_issuer(&claims.issuer) {
+            return Err(GoogleError::InvalidIssuer);
+        }
+        if self.check_expiration && claims.expires_at.saturating_add(leeway) < now {
+            return Err(GoogleError::Expired);
+        }
+        if claims.issued_at > claims.expires_at {
+            return Err(GoogleError::InvalidToken("invalid expires at"));
+        }
+        if claims.issued_at > now.saturating_add(leeway) {
+            return Err(GoogleError::IssuedInFuture);
+        }
+        if claims
+            .not_before
+            .is_some_and(|not_before| not_before > now.saturating_add(leeway))
+        {
+            return Err(GoogleError::NotYetValid);
+        }
+        if let Some(max_age) = self.max_age
+            && claims
+                .issued_at
+                .saturating_add(max_age.as_secs())
+                .saturating_add(leeway)
+                < now
+        {
+            return Err(GoogleError::TooOld);
+        }
+
+        if !self.hosted_domains.is_empty()
+            && !identity.domain.as_deref().is_some_and(|domain| {
+                self.hosted_domains
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+            })
+        {
+            return Err(GoogleError::HostedDomainMismatch);
+        }
+        if self.require_email_verified && identity.email_verified != Some(true) {
+            return Err(GoogleError::EmailNotVerified);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn provider() -> ProviderConfig {
+        ProviderConfig::google("web")
+    }
+
+    fn claims(issued_at: u64, expires_at: u64, not_before: Option<u64>) -> RequiredClaims {
+        RequiredClaims {
+            issuer: "https://accounts.google.com".to_string(),
+            subject: "1".to_string(),
+            audience: vec!["web".to_string()],
+            android_audience: None,
+            issued_at,
+            expires_at,
+            not_before,
+        }
+    }
+
+    fn identity(json: &str) -> IdentityClaims {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn check(options: &ValidationOptions, claims: &RequiredClaims) -> Result<(), GoogleError> {
+        options.check(&provider(), claims, &IdentityClaims::default(), NOW)
+    }
+
+    #[test]
+    fn test_time_claims() {
+        let options = ValidationOptions::default();
+        assert_eq!(check(&options, &claims(NOW - 10, NOW + 10, None)), Ok(()));
+
+        // Within the leeway either side
+        assert_eq!(check(&options, &claims(NOW - 100, NOW - 30, None)), Ok(()));
+        assert_eq!(
+            check(&options, &claims(NOW + 30, NOW + 90, Some(NOW + 30))),
+            Ok(())
+        );
+
+        assert_eq!(
+            check(&options, &claims(NOW - 100, NOW - 61, None)),
+            Err(GoogleError::Expired)
+        );
+        assert_eq!(
+            check(&options, &claims(NOW + 61, NOW + 120, None)),
+            Err(GoogleError::IssuedInFuture)
+        );
+        assert_eq!(
+            check(&options, &claims(NOW, NOW + 120, Some(NOW + 61))),
+            Err(GoogleError::NotYetValid)
+        );
+
+        let strict = ValidationOptions {
+            leeway: Duration::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(
+            check(&strict, &claims(NOW - 100, NOW - 1, None)),
+            Err(GoogleError::Expired)
+        );
+        let lenient = ValidationOptions {
+            check_expiration: false,
+            ..Default::default()
+        };
+        assert_eq!(check(&lenient, &claims(NOW - 100, NOW - 90, None)), Ok(()));
+    }
+
+    #[test]
+    fn test_max_age() {
+        let options = ValidationOptions {
+            leeway: Duration::ZERO,
+            max_age: Some(Duration::from_secs(300)),
+            ..Default::default()
+        };
+        assert_eq!(
+            check(&options, &claims(NOW - 300, NOW + 3000, None)),
+            Ok(())
+        );
+        assert_eq!(
+            check(&options, &claims(NOW - 301, NOW + 3000, None)),
+            Err(GoogleError::TooOld)
+        );
+    }
+
+    #[test]
+    fn test_audience_and_issuer() {
+        let options = ValidationOptions::default();
+        let mut other_client = claims(NOW, NOW + 10, None);
+        other_client.audience = vec!["mobile".to_string()];
+        assert_eq!(
+            check(&options, &other_client),
+            Err(GoogleError::InvalidAudience)
+        );
+
+        let mut other_issuer = claims(NOW, NOW + 10, None);
+        other_issuer.issuer = "https://appleid.apple.com".to_string();
+        assert_eq!(
+            check(&options, &other_issuer),
+            Err(GoogleError::InvalidIssuer)
+        );
+    }
+
+    #[test]
+    fn test_identity_claims() {
+        let options = ValidationOptions {
+            hosted_domains: vec!["example.com".to_string()],
+            require_email_verified: true,
+            ..Default::default()
+        };
+        let claims = claims(NOW, NOW + 10, None);
+        let check = |json| options.check(&provider(), &claims, &identity(json), NOW);
+
+        assert_eq!(
+            check(r#"{"hd":"Example.com","email_verified":true}"#),
+            Ok(())
+        );
+        assert_eq!(
+            check(r#"{"hd":"other.com","email_verified":true}"#),
+            Err(GoogleError::HostedDomainMismatch)
+        );
+        assert_eq!(
+            check(r#"{"email_verified":true}"#),
+            Err(GoogleError::HostedDomainMismatch)
+        );
+        assert_eq!(
+            check(r#"{"hd":"example.com","email_verified":"false"}"#),
+            Err(GoogleError::EmailNotVerified)
+        );
+        assert_eq!(
+            check(r#"{"hd":"example.com"}"#),
+            Err(GoogleError::EmailNotVerified)
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use rsweb_auth::claims::{AuthSession, ClientInfo};
+use rsweb_auth::errors::AuthError;
+use rsweb_auth::mfa;
+use rsweb_state::AppState;
+use serde::{Deserialize, Serialize};
+use warp::Filter;
+
+use crate::filters::{BadRequest, Conflict, Unauthorized, client_info};
+
+#[derive(Debug, Deserialize)]
+pub struct ChallengeBody {
+    mfa_token: String,
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CodeBody {
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Enrolment {
+    secret: String,
+    uri: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+pub fn challenge_filter()
+-> impl Filter<Extract = (ChallengeBody, ClientInfo), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16)
+        .and(warp::body::json())
+        .and(client_info())
+}
+
+pub fn code_filter() -> impl Filter<Extract = (CodeBody,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+// Second login step, exchanges the token from signin and a TOTP or recovery
+// code for a session. A 401 means the challenge is gone and the user has to
+// sign in again.
+pub async fn handle_challenge(
+    state: AppState,
+    body: ChallengeBody,
+    client_info: ClientInfo,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let essentials = match mfa::complete_challenge(&state, &body.mfa_token, &body.code).await {
+        Ok(essentials) => essentials,
+        Err(AuthError::InvalidCode) => return Err(warp::reject::custom(BadRequest)),
+        Err(_) => return Err(warp::reject::custom(Unauthorized)),
+    };
+
+    super::start_session(&state, &essentials, &client_info).await
+}
+
+// Generates a secret to scan, replacing an earlier one that wasn't confirmed
+pub async fn handle_setup(
+    state: AppState,
+    auth_session: AuthSession,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let claims = &auth_session.claims;
+    let enrolment = match mfa::begin_enrolment(&state, claims.uid, &claims.email).await {
+        Ok(enrolment) => enrolment,
+        Err(AuthError::MfaAlreadyEnabled) => {
+            return Err(warp::reject::custom(Conflict(
+                "Two-factor authentication already enabled",
+            )));
+        }
+        Err(_) => return Err(warp::reject::custom(BadRequest)),
+    };
+
+    let reply = warp::reply::json(&Enrolment {
+        secret: enrolment.secret,
+        uri: enrolment.uri,
+    });
+    Ok(super::with_updated_cookies(&state, reply, &auth_session))
+}
+
+pub async fn handle_confirm(
+    state: AppState,
+    auth_session: AuthSession,
+    body: CodeBody,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let recovery_codes =
+        match mfa::confirm_enrolment(&state, auth_session.claims.uid, &body.code).await {
+            Ok(codes) => codes,
+            Err(AuthError::MfaAlreadyEnabled) => {
+                return Err(warp::reject::custom(Conflict(
+                    "Two-factor authentication already enabled",
+                )));
+            }
+            Err(_) => return Err(warp::reject::custom(BadRequest)),
+        };
+
+    let reply = warp::reply::json(&RecoveryCodes { recovery_codes });
+    Ok(super::with_updated_cookies(&state, reply, &auth_session))
+}
+
+pub async fn handle_disable(
+    state: AppState,
+    auth_session: AuthSession,
+    body: CodeBody,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if mfa::disable(&state, auth_session.claims.uid, &body.code)
+        .await
+        .is_err()
+    {
+        return Err(warp::reject::custom(BadRequest));
+    }
+
+    let reply = warp::reply::with_status(warp::reply(), warp::http::StatusCode::NO_CONTENT);
+    Ok(super::with_updated_cookies(&state, reply, &auth_session))
+}
@@ -1,2 +1,67 @@
+use rsweb_auth::claims::{AuthSession, ClientInfo, refresh_tokens};
+use rsweb_database::user::UserEssentials;
+use rsweb_state::AppState;
+use serde::Serialize;
+use warp::reply::Reply;
+
+use crate::filters::BadRequest;
+
+pub mod mfa;
+pub mod oauth;
+pub mod password;
+pub mod sessions;
 pub mod signin;
+pub mod signout;
 pub mod signup;
+pub mod verification;
+pub mod webauthn;
+pub mod well_known;
+
+// Adds the session cookies to a reply if authenticating the request refreshed
+// the tokens
+pub(crate) fn with_updated_cookies(
+    state: &AppState,
+    reply: impl warp::Reply,
+    auth_session: &AuthSession,
+) -> warp::reply::Response {
+    let mut response = reply.into_response();
+
+    if let Some((at, rt)) = &auth_session.updated_tokens {
+        let headers = response.headers_mut();
+        headers.extend(rsweb_auth::cookies::session_cookies(state, at, rt));
+    }
+
+    response
+}
+
+// Reply asking for a second factor before signing the user in
+#[derive(Debug, Serialize)]
+pub(crate) struct MfaRequired {
+    pub(crate) mfa_token: String,
+}
+
+// Signs the user in, replying with fresh session cookies
+pub(crate) async fn start_session(
+    state: &AppState,
+    essentials: &UserEssentials,
+    client_info: &ClientInfo,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let at = match rsweb_auth::claims::Claims::from_user_essentials(essentials)
+        .await
+        .create_token(state)
+        .await
+    {
+        Ok(at) => at,
+        Err(_) => return Err(warp::reject::custom(BadRequest)),
+    };
+    let rt = match refresh_tokens::create(state, essentials.id, client_info).await {
+        Ok(rt) => rt,
+        Err(_) => return Err(warp::reject::custom(BadRequest)),
+    };
+
+    let mut response = warp::reply().into_response();
+    let headers = response.headers_mut();
+    headers.extend(rsweb_auth::cookies::session_cookies(state, &at, &rt));
+
+    Ok(response)
+}
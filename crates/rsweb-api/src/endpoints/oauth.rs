@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use rsweb_auth::claims::ClientInfo;
+use rsweb_auth::oauth::{self, HttpClient};
+use rsweb_state::AppState;
+use serde::Deserialize;
+use warp::{
+    Filter,
+    http::{StatusCode, header},
+    reply::Reply,
+};
+
+use crate::filters::{BadRequest, client_info};
+
+const STATE_COOKIE: &str = "oauth_state";
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+pub fn callback_filter(
+    http: Arc<dyn HttpClient>,
+) -> impl Filter<
+    Extract = (
+        CallbackQuery,
+        Option<String>,
+        ClientInfo,
+        Arc<dyn HttpClient>,
+    ),
+    Error = warp::Rejection,
+> + Clone {
+    warp::query::<CallbackQuery>()
+        .and(warp::cookie::optional(STATE_COOKIE))
+        .and(client_info())
+        .and(warp::any().map(move || http.clone()))
+}
+
+// The state also goes into a cookie so a callback only completes in the
+// browser that started the flow. It has to be Lax, the callback is a cross
+// site navigation coming from the provider.
+fn state_cookie(value: &str, max_age: u64) -> String {
+    format!(
+        "{}={}; HttpOnly; Secure; SameSite=Lax; Path=/auth/; Max-Age={}",
+        STATE_COOKIE, value, max_age
+    )
+}
+
+fn redirect(location: &str) -> warp::reply::Response {
+    let mut response = warp::reply::with_status(warp::reply(), StatusCode::FOUND).into_response();
+    response
+        .headers_mut()
+        .insert(header::LOCATION, location.parse().unwrap());
+    response
+}
+
+// Failed callbacks end up back at the login page
+fn login_failed(reason: &str) -> warp::reply::Response {
+    let mut response = redirect(&format!("/login?error={}", reason));
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, state_cookie("", 0).parse().unwrap());
+    response
+}
+
+pub async fn handle_start(
+    provider: String,
+    app_state: AppState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let authorization = match oauth::start(&app_state, &provider).await {
+        Ok(authorization) => authorization,
+        Err(e) => {
+            eprintln!("Failed to start {} sign in: {}", provider, e);
+            return Err(warp::reject::custom(BadRequest));
+        }
+    };
+
+    let mut response = redirect(&authorization.url);
+    response.headers_mut().append(
+        header::SET_COOKIE,
+        state_cookie(&authorization.state, oauth::STATE_LIFETIME)
+            .parse()
+            .unwrap(),
+    );
+
+    Ok(response)
+}
+
+pub async fn handle_callback(
+    provider: String,
+    app_state: AppState,
+    query: CallbackQuery,
+    cookie_state: Option<String>,
+    client_info: ClientInfo,
+    http: Arc<dyn HttpClient>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if query.error.is_some() {
+        return Ok(login_failed("denied"));
+    }
+    let (code, state) = match (query.code, query.state) {
+        (Some(code), Some(state)) if cookie_state.as_deref() == Some(state.as_str()) => {
+            (code, state)
+        }
+        _ => return Ok(login_failed("state")),
+    };
+
+    let id_token = match oauth::finish(&app_state, http.as_ref(), &provider, &code, &state).await {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("Failed {} sign in: {}", provider, e);
+            return Ok(login_failed("provider"));
+        }
+    };
+
+    let essentials =
+        match super::signin::essentials_for_identity(&app_state, &provider, &id_token).await {
+            Ok(essentials) => essentials,
+            Err(_) => return Ok(login_failed("account")),
+        };
+
+    let mut response = match rsweb_auth::mfa::is_enabled(&app_state, essentials.id).await {
+        Ok(false) => {
+            let mut response = super::start_session(&app_state, &essentials, &client_info).await?;
+            *response.body_mut() = CONTINUE_PAGE.into();
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                "text/html; charset=utf-8".parse().unwrap(),
+            );
+            response
+        }
+        // The challenge page picks the token up from the fragment, which
+        // never reaches the server logs
+        Ok(true) => match rsweb_auth::mfa::create_challenge(&app_state, &essentials).await {
+            Ok(mfa_token) => redirect(&format!("/login/mfa#mfa_token={}", mfa_token)),
+            Err(_) => return Ok(login_failed("provider")),
+        },
+        Err(_) => return Ok(login_failed("provider")),
+    };
+
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, state_cookie("", 0).parse().unwrap());
+    Ok(response)
+}
+
+// Session cookies are SameSite=Strict, so a redirect straight out of the
+// provider's navigation would arrive without them. The page moves on from
+// this site instead.
+const CONTINUE_PAGE: &str = r#"<!DOCTYPE html><html><head><meta http-equiv="refresh" content="0; url=/"><title>Signing in</title></head><body><a href="/">Continue</a></body></html>"#;
@@ -0,0 +1,71 @@
+use rsweb_auth::errors::AuthError;
+use rsweb_auth::password_reset;
+use rsweb_state::AppState;
+use serde::Deserialize;
+use warp::{Filter, reply::Reply};
+
+use crate::filters::{BadRequest, hash_rejection};
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotBody {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetBody {
+    token: String,
+    password: String,
+}
+
+pub fn forgot_filter() -> impl Filter<Extract = (ForgotBody,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+pub fn reset_filter() -> impl Filter<Extract = (ResetBody,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+// Always answers the same way and sends the mail in the background, so
+// neither the response nor its timing tells whether the account exists
+pub async fn handle_forgot(
+    state: AppState,
+    body: ForgotBody,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let email = body.email.trim().to_string();
+    if !email.contains('@') {
+        return Err(warp::reject::custom(BadRequest));
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = password_reset::request(&state, &email).await {
+            eprintln!("Failed to send password reset: {}", e);
+        }
+    });
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        warp::http::StatusCode::ACCEPTED,
+    ))
+}
+
+pub async fn handle_reset(
+    state: AppState,
+    body: ResetBody,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if body.password.len() < 6 || body.password.len() > 64 {
+        return Err(warp::reject::custom(BadRequest));
+    }
+
+    match password_reset::reset(&state, &body.token, body.password).await {
+        Ok(_) => {}
+        Err(AuthError::CryptoError(e)) => return Err(hash_rejection(e)),
+        Err(_) => return Err(warp::reject::custom(BadRequest)),
+    }
+
+    // Every session was ended, including the one of this browser if any
+    let mut response = warp::reply().into_response();
+    let headers = response.headers_mut();
+    headers.extend(rsweb_auth::cookies::clear_session_cookies());
+
+    Ok(response)
+}
@@ -0,0 +1,83 @@
+use rsweb_auth::claims::{AuthSession, refresh_tokens};
+use rsweb_database::user::SessionDetails;
+use rsweb_state::AppState;
+use rsweb_utils::primitive_to_iso8601_string;
+use serde::Serialize;
+use warp::Filter;
+
+use crate::filters::{BadRequest, cookies::with_auth};
+
+#[derive(Debug, Serialize)]
+pub struct Session {
+    id: String,
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+    signed_in_at: String,
+    last_used_at: String,
+    current: bool,
+}
+
+impl From<SessionDetails> for Session {
+    fn from(details: SessionDetails) -> Self {
+        Session {
+            id: details.family_id,
+            user_agent: details.user_agent,
+            ip_address: details.ip_address,
+            signed_in_at: primitive_to_iso8601_string(details.signed_in_at),
+            last_used_at: primitive_to_iso8601_string(details.last_used_at),
+            current: details.current,
+        }
+    }
+}
+
+pub fn filter(
+    state: AppState,
+) -> impl Filter<Extract = (AuthSession, Option<String>), Error = warp::Rejection> + Clone {
+    with_auth(state).and(warp::cookie::optional("refresh_token"))
+}
+
+pub async fn handle_list(
+    state: AppState,
+    auth_session: AuthSession,
+    refresh_token: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // A refresh token rotated by this request still belongs to the same family
+    let sessions = match refresh_tokens::list_sessions(
+        &state,
+        auth_session.claims.uid,
+        refresh_token.as_deref(),
+    )
+    .await
+    {
+        Ok(sessions) => sessions,
+        Err(_) => return Err(warp::reject::custom(BadRequest)),
+    };
+
+    let sessions: Vec<Session> = sessions.into_iter().map(Session::from).collect();
+    let reply = warp::reply::json(&sessions);
+    Ok(super::with_updated_cookies(&state, reply, &auth_session))
+}
+
+// Signs out a single device by deleting its refresh token family
+pub async fn handle_revoke(
+    id: String,
+    state: AppState,
+    auth_session: AuthSession,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let uid = auth_session.claims.uid;
+
+    match refresh_tokens::revoke_session(&state, uid, &id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(warp::reject::not_found()),
+        Err(_) => return Err(warp::reject::custom(BadRequest)),
+    }
+
+    // Access tokens are not tied to a session, so cut off all of them. The
+    // remaining devices silently refresh while the revoked one can no longer.
+    if let Err(e) = rsweb_auth::revocation::revoke_user_tokens(&state, uid).await {
+        eprintln!("Failed to revoke access tokens: {}", e);
+    }
+
+    let reply = warp::reply::with_status(warp::reply(), warp::http::StatusCode::NO_CONTENT);
+    Ok(super::with_updated_cookies(&state, reply, &auth_session))
+}
@@ -1,51 +1,38 @@
-use std::time::Duration;
-
-use google_jwt::ClientAsync;
-use rsweb_auth::google_client_id;
+use google_jwt::{IdPayload, Token};
+use rsweb_auth::{claims::ClientInfo, oidc};
 use rsweb_database::user::{UserEssentials, UserService};
-use rsweb_utils::format_expiry;
+use rsweb_state::AppState;
 use serde::Deserialize;
 use warp::{Filter, reply::Reply};
 
-use crate::filters::BadRequest;
+use crate::filters::{BadRequest, client_info, hash_rejection};
 
 #[derive(Debug, Deserialize)]
 pub struct LoginBody {
     email: Option<String>,
     password: Option<String>,
     credential: Option<String>,
+    // Identity provider that issued the credential, Google when omitted
+    provider: Option<String>,
 }
 
-pub fn filter() -> impl Filter<Extract = (LoginBody,), Error = warp::Rejection> + Clone {
-    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+pub fn filter() -> impl Filter<Extract = (LoginBody, ClientInfo), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16)
+        .and(warp::body::json())
+        .and(client_info())
 }
 
-pub async fn handle(body: LoginBody) -> Result<impl warp::Reply, warp::Rejection> {
+pub async fn handle(
+    state: AppState,
+    body: LoginBody,
+    client_info: ClientInfo,
+) -> Result<impl warp::Reply, warp::Rejection> {
     let essentials: UserEssentials;
 
-    // Credential then google login
+    // Credential then identity provider login
     if let Some(credential) = body.credential {
-        let client = ClientAsync::new(&google_client_id().unwrap());
-        let id_token = match client.verify_id_token_async(&credential).await {
-            Ok(token) => token,
-            Err(_) => return Err(warp::reject::custom(BadRequest)),
-        };
-
-        let details = match UserService::get_google_user_details(&id_token.claims.subject).await {
-            Ok(d) => d,
-            Err(_) => return Err(warp::reject::custom(BadRequest)),
-        };
-
-        if details.banned {
-            return Err(warp::reject::custom(BadRequest));
-        }
-
-        essentials = UserEssentials {
-            id: details.id,
-            email: details.email,
-            role: details.role,
-            handle: details.handle,
-        }
+        let provider = body.provider.as_deref().unwrap_or(oidc::GOOGLE);
+        essentials = identity_essentials(&state, provider, &credential).await?;
     } else {
         let email = match body.email {
             Some(email) => email,
@@ -57,11 +44,11 @@ pub async fn handle(body: LoginBody) -> Result<impl warp::Reply, warp::Rejection
             None => return Err(warp::reject::custom(BadRequest)),
         };
 
-        if !email.contains('@') || password.len() < 6 && password.len() > 64 {
+        if !email.contains('@') || password.len() < 6 || password.len() > 64 {
             return Err(warp::reject::custom(BadRequest));
         }
 
-        let details = match UserService::get_user_details(&email).await {
+        let details = match UserService::get_user_details(&state, &email).await {
             Ok(d) => d,
             Err(_) => return Err(warp::reject::custom(BadRequest)),
         };
@@ -75,18 +62,22 @@ pub async fn handle(body: LoginBody) -> Result<impl warp::Reply, warp::Rejection
             None => return Err(warp::reject::custom(BadRequest)),
         };
 
-        let stored_salt = match details.password_salt {
-            Some(salt) => salt,
-            None => return Err(warp::reject::custom(BadRequest)),
-        };
-
-        match rsweb_crypto::hash::cmp_password_hash(&password, &stored_pwd, &stored_salt) {
+        match state
+            .hasher
+            .verify_password_async(password.clone(), stored_pwd.clone(), details.password_salt)
+            .await
+        {
             Ok(b) => {
                 if !b {
                     return Err(warp::reject::custom(BadRequest));
                 }
             }
-            Err(_) => return Err(warp::reject::custom(BadRequest)),
+            Err(e) => return Err(hash_rejection(e)),
+        }
+
+        // Upgrade legacy or outdated hashes while the plaintext is at hand
+        if state.hasher.needs_rehash(&stored_pwd) {
+            rehash_password(&state, details.id, password).await;
         }
 
         essentials = UserEssentials {
@@ -94,52 +85,92 @@ pub async fn handle(body: LoginBody) -> Result<impl warp::Reply, warp::Rejection
             email: details.email,
             role: details.role,
             handle: details.handle,
+            email_verified: details.email_verified_at.is_some(),
         }
     }
 
-    let at = rsweb_auth::claims::Claims::from_user_essentials(&essentials)
-        .await
-        .create_token()
-        .await;
-    let rt = match rsweb_auth::claims::refresh_tokens::create(essentials.id).await {
-        Ok(rt) => rt,
+    // With a second factor the password only earns a short-lived challenge,
+    // the session is started once the code checks out
+    match rsweb_auth::mfa::is_enabled(&state, essentials.id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            let mfa_token = match rsweb_auth::mfa::create_challenge(&state, &essentials).await {
+                Ok(token) => token,
+                Err(_) => return Err(warp::reject::custom(BadRequest)),
+            };
+            return Ok(warp::reply::json(&super::MfaRequired { mfa_token }).into_response());
+        }
         Err(_) => return Err(warp::reject::custom(BadRequest)),
+    }
+
+    super::start_session(&state, &essentials, &client_info).await
+}
+
+async fn identity_essentials(
+    state: &AppState,
+    provider: &str,
+    credential: &str,
+) -> Result<UserEssentials, warp::Rejection> {
+    match oidc::verify_id_token(state, provider, credential).await {
+        Ok(id_token) => essentials_for_identity(state, provider, &id_token).await,
+        Err(_) => Err(warp::reject::custom(BadRequest)),
+    }
+}
+
+// Looks up the user a verified ID token of the provider belongs to
+pub(crate) async fn essentials_for_identity(
+    state: &AppState,
+    provider: &str,
+    id_token: &Token<IdPayload>,
+) -> Result<UserEssentials, warp::Rejection> {
+    let details =
+        match UserService::get_identity_user_details(state, provider, &id_token.claims.subject)
+            .await
+        {
+            Ok(d) => d,
+            Err(_) => return Err(warp::reject::custom(BadRequest)),
+        };
+
+    if details.banned {
+        return Err(warp::reject::custom(BadRequest));
+    }
+
+    // Accounts created before the provider vouched for the address, or that
+    // never followed their link, are verified on the next provider login
+    let mut email_verified = details.email_verified_at.is_some();
+    if !email_verified
+        && id_token.payload.email_verified == Some(true)
+        && id_token.payload.email.as_deref() == Some(details.email.as_str())
+    {
+        match UserService::mark_email_verified(state, details.id, &details.email).await {
+            Ok(n) => email_verified = n > 0,
+            Err(e) => eprintln!("Failed to verify email of user {}: {}", details.id, e),
+        }
+    }
+
+    Ok(UserEssentials {
+        id: details.id,
+        email: details.email,
+        role: details.role,
+        handle: details.handle,
+        email_verified,
+    })
+}
+
+// Failing to upgrade the hash does not fail the login, it is retried next time
+async fn rehash_password(state: &AppState, user_id: i32, password: String) {
+    let hash = match state.hasher.hash_password_async(password).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("Failed to rehash password of user {}: {}", user_id, e);
+            return;
+        }
     };
 
-    let res = warp::reply();
-
-    // Set auth token expiration (30 minutes)
-    let auth_expires = format_expiry(Duration::from_secs(30 * 60));
-    // Set refresh token expiration (2 weeks)
-    let refresh_expires = format_expiry(Duration::from_secs(14 * 24 * 60 * 60));
-
-    let mut cookies = warp::http::header::HeaderMap::new();
-    cookies.append(
-        "Set-Cookie",
-        format!(
-            "auth_token={}; HttpOnly; Secure; SameSite=Strict; Path=/; Expires={}; Max-Age={}",
-            at,
-            auth_expires,
-            30 * 60
-        )
-        .parse()
-        .unwrap(),
-    );
-    cookies.append(
-        "Set-Cookie",
-        format!(
-            "refresh_token={}; HttpOnly; Secure; SameSite=Strict; Path=/; Expires={}; Max-Age={}",
-            rt,
-            refresh_expires,
-            14 * 24 * 60 * 60
-        )
-        .parse()
-        .unwrap(),
-    );
-
-    let mut response = res.into_response();
-    let headers = response.headers_mut();
-    headers.extend(cookies);
-
-    Ok(response)
+    if let Err(e) = UserService::update_user_password(state, user_id, &hash).await {
+        eprintln!(
+            "Failed to store rehashed password of user {}: {}",
+            user_id, e
+        );
+    }
 }
@@ -0,0 +1,65 @@
+use rsweb_auth::claims::{AuthSession, Claims, refresh_tokens};
+use rsweb_state::AppState;
+use warp::{Filter, reply::Reply};
+
+use crate::filters::{InternalError, cookies::with_auth};
+
+pub fn filter(
+    state: AppState,
+) -> impl Filter<Extract = (AuthSession, Option<String>, Option<String>), Error = warp::Rejection> + Clone
+{
+    with_auth(state)
+        .and(warp::cookie::optional("auth_token"))
+        .and(warp::cookie::optional("refresh_token"))
+}
+
+// Ends the current session, the client is signed out even if revoking fails
+pub async fn handle(
+    state: AppState,
+    auth_session: AuthSession,
+    auth_token: Option<String>,
+    refresh_token: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(auth_token) = auth_token
+        && let Ok(token) = Claims::decode(&state, &auth_token).await
+        && let Err(e) = rsweb_auth::revocation::revoke_token(&state, &token).await
+    {
+        eprintln!("Failed to revoke access token: {}", e);
+    }
+
+    if let Some(refresh_token) = refresh_token
+        && let Err(e) =
+            refresh_tokens::revoke(&state, auth_session.claims.uid, &refresh_token).await
+    {
+        eprintln!("Failed to revoke refresh token: {}", e);
+    }
+
+    let mut response = warp::reply().into_response();
+    let headers = response.headers_mut();
+    headers.extend(rsweb_auth::cookies::clear_session_cookies());
+
+    Ok(response)
+}
+
+// Ends every session of the user, including outstanding access tokens
+pub async fn handle_all(
+    state: AppState,
+    auth_session: AuthSession,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let uid = auth_session.claims.uid;
+
+    if let Err(e) = refresh_tokens::revoke_all(&state, uid).await {
+        eprintln!("Failed to revoke refresh tokens: {}", e);
+        return Err(warp::reject::custom(InternalError));
+    }
+    if let Err(e) = rsweb_auth::revocation::revoke_user_tokens(&state, uid).await {
+        eprintln!("Failed to revoke access tokens: {}", e);
+        return Err(warp::reject::custom(InternalError));
+    }
+
+    let mut response = warp::reply().into_response();
+    let headers = response.headers_mut();
+    headers.extend(rsweb_auth::cookies::clear_session_cookies());
+
+    Ok(response)
+}
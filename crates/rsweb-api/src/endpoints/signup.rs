@@ -1,10 +1,10 @@
-use google_jwt::ClientAsync;
-use rsweb_auth::{self, google_client_id};
-use rsweb_database::user::UserEssentials;
+use rsweb_auth::{self, claims::ClientInfo, oidc};
+use rsweb_database::user::{UserEssentials, UserService};
+use rsweb_state::AppState;
 use serde::Deserialize;
-use warp::{Filter, reply::Reply};
+use warp::Filter;
 
-use crate::filters::BadRequest;
+use crate::filters::{BadRequest, Conflict, InternalError, client_info, hash_rejection};
 
 #[derive(Debug, Deserialize)]
 pub struct SignupBody {
@@ -12,25 +12,79 @@ pub struct SignupBody {
     email: Option<String>,
     password: Option<String>,
     credential: Option<String>,
+    // Identity provider that issued the credential, Google when omitted
+    provider: Option<String>,
 }
 
-pub fn filter() -> impl Filter<Extract = (SignupBody,), Error = warp::Rejection> + Clone {
-    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+pub fn filter() -> impl Filter<Extract = (SignupBody, ClientInfo), Error = warp::Rejection> + Clone
+{
+    warp::body::content_length_limit(1024 * 16)
+        .and(warp::body::json())
+        .and(client_info())
 }
 
-pub async fn handle(body: SignupBody) -> Result<impl warp::Reply, warp::Rejection> {
+pub async fn handle(
+    state: AppState,
+    body: SignupBody,
+    client_info: ClientInfo,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let username = body.username.trim().to_string();
+    if username.is_empty() || username.len() > 64 {
+        return Err(warp::reject::custom(BadRequest));
+    }
+
     let essentials: UserEssentials;
 
-    // Credential then google login
+    // Credential then identity provider registration
     if let Some(credential) = body.credential {
-        let client = ClientAsync::new(&google_client_id().unwrap());
-        let id_token = match client.verify_id_token_async(&credential).await {
+        let provider = body.provider.as_deref().unwrap_or(oidc::GOOGLE);
+        let id_token = match oidc::verify_id_token(&state, provider, &credential).await {
             Ok(token) => token,
             Err(_) => return Err(warp::reject::custom(BadRequest)),
         };
 
-        // TODO: Here we need to register the (google) user
-        todo!()
+        let email = match id_token.payload.email {
+            Some(email) => email,
+            None => return Err(warp::reject::custom(BadRequest)),
+        };
+
+        match UserService::identity_exists(&state, provider, &id_token.claims.subject).await {
+            Ok(false) => {}
+            Ok(true) => return Err(warp::reject::custom(Conflict("Account already exists"))),
+            Err(e) => {
+                eprintln!("Failed to look up identity: {}", e);
+                return Err(warp::reject::custom(InternalError));
+            }
+        }
+        ensure_available(&state, &username, &email).await?;
+
+        // The provider vouches for the address, so there is no link to follow
+        let email_verified = id_token.payload.email_verified == Some(true);
+        let id = match UserService::insert_user_identity(
+            &state,
+            provider,
+            &id_token.claims.subject,
+            &email,
+            &username,
+            email_verified,
+        )
+        .await
+        {
+            Ok(id) => id,
+            Err(e) => return Err(insert_rejection(e)),
+        };
+
+        if !email_verified {
+            send_verification(&state, id, &email, &username).await;
+        }
+
+        essentials = UserEssentials {
+            id,
+            email,
+            handle: username,
+            role: "user".to_string(),
+            email_verified,
+        }
     } else {
         let email = match body.email {
             Some(email) => email,
@@ -42,48 +96,82 @@ pub async fn handle(body: SignupBody) -> Result<impl warp::Reply, warp::Rejectio
             None => return Err(warp::reject::custom(BadRequest)),
         };
 
-        if !email.contains('@') || password.len() < 6 && password.len() > 64 {
+        if !rsweb_mail::is_valid_address(&email) || password.len() < 6 || password.len() > 64 {
             return Err(warp::reject::custom(BadRequest));
         }
 
-        // TODO: Here we need to register the (email & pwd) user
-        todo!()
+        ensure_available(&state, &username, &email).await?;
+
+        let hash = match state.hasher.hash_password_async(password).await {
+            Ok(hash) => hash,
+            Err(e) => return Err(hash_rejection(e)),
+        };
+
+        let id = match UserService::insert_user_email(&state, &email, &hash, &username).await {
+            Ok(id) => id,
+            Err(e) => return Err(insert_rejection(e)),
+        };
+
+        send_verification(&state, id, &email, &username).await;
+
+        essentials = UserEssentials {
+            id,
+            email,
+            handle: username,
+            role: "user".to_string(),
+            email_verified: false,
+        }
+    }
+
+    super::start_session(&state, &essentials, &client_info).await
+}
+
+// The account is usable without verification, so a failure to queue the
+// mail doesn't fail the signup, the user can ask for a new link later
+async fn send_verification(state: &AppState, user_id: i32, email: &str, username: &str) {
+    if let Err(e) = rsweb_auth::email_verification::send(state, user_id, email, username).await {
+        eprintln!(
+            "Failed to queue verification mail for user {}: {}",
+            user_id, e
+        );
     }
+}
 
-    let at = rsweb_auth::claims::Claims::from_user_essentials(&essentials)
-        .await
-        .create_token()
-        .await;
-    let rt = match rsweb_auth::claims::refresh_tokens::create(essentials.id).await {
-        Ok(rt) => rt,
-        Err(_) => return Err(warp::reject::custom(BadRequest)),
-    };
-
-    let res = warp::reply();
-
-    let mut cookies = warp::http::header::HeaderMap::new();
-    cookies.append(
-        "Set-Cookie",
-        format!(
-            "auth_token={}; HttpOnly; Secure; SameSite=Strict; Path=/",
-            at
-        )
-        .parse()
-        .unwrap(),
-    );
-    cookies.append(
-        "Set-Cookie",
-        format!(
-            "refresh_token={}; HttpOnly; Secure; SameSite=Strict; Path=/",
-            rt
-        )
-        .parse()
-        .unwrap(),
-    );
+async fn ensure_available(
+    state: &AppState,
+    username: &str,
+    email: &str,
+) -> Result<(), warp::Rejection> {
+    match UserService::handle_exists(state, username).await {
+        Ok(false) => {}
+        Ok(true) => return Err(warp::reject::custom(Conflict("Username already taken"))),
+        Err(e) => {
+            eprintln!("Failed to look up username: {}", e);
+            return Err(warp::reject::custom(InternalError));
+        }
+    }
 
-    let mut response = res.into_response();
-    let headers = response.headers_mut();
-    headers.extend(cookies);
+    match UserService::email_exists(state, email).await {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(warp::reject::custom(Conflict("Email already registered"))),
+        Err(e) => {
+            eprintln!("Failed to look up email: {}", e);
+            Err(warp::reject::custom(InternalError))
+        }
+    }
+}
 
-    Ok(response)
+// The up-front checks can race with a concurrent signup, in which case the
+// unique constraints on the users table still catch the duplicate. Anything
+// else is the database failing, not the request.
+fn insert_rejection(e: Box<dyn std::error::Error + Send + Sync>) -> warp::Rejection {
+    match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            warp::reject::custom(Conflict("Account already exists"))
+        }
+        _ => {
+            eprintln!("Failed to insert user: {}", e);
+            warp::reject::custom(InternalError)
+        }
+    }
 }
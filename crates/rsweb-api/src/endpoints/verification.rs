@@ -0,0 +1,29 @@
+use rsweb_auth::claims::AuthSession;
+use rsweb_state::AppState;
+
+use crate::filters::{BadRequest, Conflict};
+
+// Sends a fresh verification link to the address of the signed in user
+pub async fn handle_resend(
+    state: AppState,
+    auth_session: AuthSession,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let claims = &auth_session.claims;
+    if !claims.unverified {
+        return Err(warp::reject::custom(Conflict("Email already verified")));
+    }
+
+    if let Err(e) =
+        rsweb_auth::email_verification::send(&state, claims.uid, &claims.email, &claims.username)
+            .await
+    {
+        eprintln!(
+            "Failed to queue verification mail for user {}: {}",
+            claims.uid, e
+        );
+        return Err(warp::reject::custom(BadRequest));
+    }
+
+    let reply = warp::reply::with_status(warp::reply(), warp::http::StatusCode::ACCEPTED);
+    Ok(super::with_updated_cookies(&state, reply, &auth_session))
+}
@@ -0,0 +1,169 @@
+use rsweb_auth::claims::{AuthSession, ClientInfo};
+use rsweb_auth::webauthn::{self, AuthenticationResponse, RegistrationResponse};
+use rsweb_database::user::WebauthnCredential;
+use rsweb_state::AppState;
+use rsweb_utils::primitive_to_iso8601_string;
+use serde::{Deserialize, Serialize};
+use warp::{Filter, reply::Reply};
+
+use crate::filters::{BadRequest, Unauthorized, client_info};
+
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterBody {
+    credential: RegistrationResponse,
+    name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Passkey {
+    id: i32,
+    name: String,
+    created_at: String,
+    last_used_at: Option<String>,
+}
+
+impl From<WebauthnCredential> for Passkey {
+    fn from(credential: WebauthnCredential) -> Self {
+        Passkey {
+            id: credential.id,
+            name: credential.name,
+            created_at: primitive_to_iso8601_string(credential.created_at),
+            last_used_at: credential.last_used_at.map(primitive_to_iso8601_string),
+        }
+    }
+}
+
+pub fn register_filter() -> impl Filter<Extract = (RegisterBody,), Error = warp::Rejection> + Clone
+{
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+pub fn login_filter()
+-> impl Filter<Extract = (AuthenticationResponse, ClientInfo), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16)
+        .and(warp::body::json())
+        .and(client_info())
+}
+
+pub async fn handle_register_start(
+    state: AppState,
+    auth_session: AuthSession,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let claims = &auth_session.claims;
+    let options =
+        match webauthn::start_registration(&state, claims.uid, &claims.email, &claims.username)
+            .await
+        {
+            Ok(options) => options,
+            Err(_) => return Err(warp::reject::custom(BadRequest)),
+        };
+
+    Ok(super::with_updated_cookies(
+        &state,
+        warp::reply::json(&options),
+        &auth_session,
+    ))
+}
+
+pub async fn handle_register_finish(
+    state: AppState,
+    auth_session: AuthSession,
+    body: RegisterBody,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let name = body
+        .name
+        .map(|name| {
+            name.trim()
+                .chars()
+                .take(MAX_NAME_LENGTH)
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or("Passkey".to_string());
+
+    match webauthn::finish_registration(&state, auth_session.claims.uid, &body.credential, &name)
+        .await
+    {
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Failed to register passkey: {}", e);
+            return Err(warp::reject::custom(BadRequest));
+        }
+    }
+
+    let reply = warp::reply::with_status(warp::reply(), warp::http::StatusCode::CREATED);
+    Ok(super::with_updated_cookies(&state, reply, &auth_session))
+}
+
+pub async fn handle_login_start(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    match webauthn::start_authentication(&state).await {
+        Ok(options) => Ok(warp::reply::json(&options)),
+        Err(_) => Err(warp::reject::custom(BadRequest)),
+    }
+}
+
+// Passkeys that verified the user count as two factors, otherwise accounts
+// with two-factor authentication still have to enter a code
+pub async fn handle_login_finish(
+    state: AppState,
+    body: AuthenticationResponse,
+    client_info: ClientInfo,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (essentials, user_verified) = match webauthn::finish_authentication(&state, &body).await {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Failed passkey login: {}", e);
+            return Err(warp::reject::custom(Unauthorized));
+        }
+    };
+
+    if !user_verified {
+        match rsweb_auth::mfa::is_enabled(&state, essentials.id).await {
+            Ok(false) => {}
+            Ok(true) => {
+                let mfa_token = match rsweb_auth::mfa::create_challenge(&state, &essentials).await {
+                    Ok(token) => token,
+                    Err(_) => return Err(warp::reject::custom(BadRequest)),
+                };
+                return Ok(warp::reply::json(&super::MfaRequired { mfa_token }).into_response());
+            }
+            Err(_) => return Err(warp::reject::custom(BadRequest)),
+        }
+    }
+
+    super::start_session(&state, &essentials, &client_info).await
+}
+
+pub async fn handle_list(
+    state: AppState,
+    auth_session: AuthSession,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let passkeys: Vec<Passkey> =
+        match webauthn::list_credentials(&state, auth_session.claims.uid).await {
+            Ok(credentials) => credentials.into_iter().map(Passkey::from).collect(),
+            Err(_) => return Err(warp::reject::custom(BadRequest)),
+        };
+
+    Ok(super::with_updated_cookies(
+        &state,
+        warp::reply::json(&passkeys),
+        &auth_session,
+    ))
+}
+
+pub async fn handle_remove(
+    id: i32,
+    state: AppState,
+    auth_session: AuthSession,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match webauthn::remove_credential(&state, auth_session.claims.uid, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(warp::reject::not_found()),
+        Err(_) => return Err(warp::reject::custom(BadRequest)),
+    }
+
+    let reply = warp::reply::with_status(warp::reply(), warp::http::StatusCode::NO_CONTENT);
+    Ok(super::with_updated_cookies(&state, reply, &auth_session))
+}
@@ -0,0 +1,29 @@
+use rsweb_state::AppState;
+use warp::reply::Reply;
+
+// Verifiers refetch on an unknown key id, so keys can be cached for a while
+const CACHE_CONTROL: &str = "public, max-age=300";
+
+pub async fn jwks(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let jwks = rsweb_auth::jwt::jwks(&state).await;
+
+    let mut response = warp::reply::json(&jwks).into_response();
+    response.headers_mut().insert(
+        warp::http::header::CACHE_CONTROL,
+        warp::http::HeaderValue::from_static(CACHE_CONTROL),
+    );
+
+    Ok(response)
+}
+
+pub async fn openid_configuration(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let config = rsweb_auth::jwt::openid_configuration(&state);
+
+    let mut response = warp::reply::json(&config).into_response();
+    response.headers_mut().insert(
+        warp::http::header::CACHE_CONTROL,
+        warp::http::HeaderValue::from_static(CACHE_CONTROL),
+    );
+
+    Ok(response)
+}
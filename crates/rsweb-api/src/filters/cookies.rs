@@ -1,14 +1,23 @@
-use rsweb_auth::claims::{AuthSession, Claims};
+use rsweb_auth::claims::{AuthSession, Claims, ClientInfo};
+use rsweb_state::AppState;
 use warp::Filter;
 
-pub fn with_auth() -> impl Filter<Extract = (AuthSession,), Error = warp::Rejection> + Clone {
-    warp::any()
+pub fn with_auth(
+    state: AppState,
+) -> impl Filter<Extract = (AuthSession,), Error = warp::Rejection> + Clone {
+    super::with_state(state)
         .and(warp::cookie::optional("auth_token"))
         .and(warp::cookie::optional("refresh_token"))
+        .and(super::client_info())
         .and_then(
-            |auth_token: Option<String>, refresh_token: Option<String>| async move {
+            |state: AppState,
+             auth_token: Option<String>,
+             refresh_token: Option<String>,
+             client: ClientInfo| async move {
                 let (claims, updated_tokens) =
-                    match Claims::try_from_tokens(&auth_token, &refresh_token).await {
+                    match Claims::try_from_tokens(&state, &auth_token, &refresh_token, &client)
+                        .await
+                    {
                         Ok((claims, updated_tokens)) => (claims, updated_tokens),
                         Err(_) => return Err(warp::reject::custom(super::Unauthorized)),
                     };
@@ -21,15 +30,55 @@ pub fn with_auth() -> impl Filter<Extract = (AuthSession,), Error = warp::Reject
         )
 }
 
-pub fn with_creator_auth() -> impl Filter<Extract = (AuthSession,), Error = warp::Rejection> + Clone
-{
-    warp::any()
+// Like with_auth, but rejects users that haven't verified their email yet
+pub fn with_verified_auth(
+    state: AppState,
+) -> impl Filter<Extract = (AuthSession,), Error = warp::Rejection> + Clone {
+    super::with_state(state)
         .and(warp::cookie::optional("auth_token"))
         .and(warp::cookie::optional("refresh_token"))
+        .and(super::client_info())
         .and_then(
-            |auth_token: Option<String>, refresh_token: Option<String>| async move {
+            |state: AppState,
+             auth_token: Option<String>,
+             refresh_token: Option<String>,
+             client: ClientInfo| async move {
                 let (claims, updated_tokens) =
-                    match Claims::try_from_tokens(&auth_token, &refresh_token).await {
+                    match Claims::try_from_tokens(&state, &auth_token, &refresh_token, &client)
+                        .await
+                    {
+                        Ok((claims, updated_tokens)) => (claims, updated_tokens),
+                        Err(_) => return Err(warp::reject::custom(super::Unauthorized)),
+                    };
+
+                if claims.unverified {
+                    return Err(warp::reject::custom(super::Unverified));
+                }
+
+                Ok::<_, warp::reject::Rejection>(AuthSession {
+                    claims,
+                    updated_tokens,
+                })
+            },
+        )
+}
+
+pub fn with_creator_auth(
+    state: AppState,
+) -> impl Filter<Extract = (AuthSession,), Error = warp::Rejection> + Clone {
+    super::with_state(state)
+        .and(warp::cookie::optional("auth_token"))
+        .and(warp::cookie::optional("refresh_token"))
+        .and(super::client_info())
+        .and_then(
+            |state: AppState,
+             auth_token: Option<String>,
+             refresh_token: Option<String>,
+             client: ClientInfo| async move {
+                let (claims, updated_tokens) =
+                    match Claims::try_from_tokens(&state, &auth_token, &refresh_token, &client)
+                        .await
+                    {
                         Ok((claims, updated_tokens)) => (claims, updated_tokens),
                         Err(_) => return Err(warp::reject::custom(super::Unauthorized)),
                     };
@@ -46,15 +95,22 @@ pub fn with_creator_auth() -> impl Filter<Extract = (AuthSession,), Error = warp
         )
 }
 
-pub fn with_auth_no_profile()
--> impl Filter<Extract = (AuthSession,), Error = warp::Rejection> + Clone {
-    warp::any()
+pub fn with_auth_no_profile(
+    state: AppState,
+) -> impl Filter<Extract = (AuthSession,), Error = warp::Rejection> + Clone {
+    super::with_state(state)
         .and(warp::cookie::optional("auth_token"))
         .and(warp::cookie::optional("refresh_token"))
+        .and(super::client_info())
         .and_then(
-            |auth_token: Option<String>, refresh_token: Option<String>| async move {
+            |state: AppState,
+             auth_token: Option<String>,
+             refresh_token: Option<String>,
+             client: ClientInfo| async move {
                 let (claims, updated_tokens) =
-                    match Claims::try_from_tokens(&auth_token, &refresh_token).await {
+                    match Claims::try_from_tokens(&state, &auth_token, &refresh_token, &client)
+                        .await
+                    {
                         Ok((claims, updated_tokens)) => (claims, updated_tokens),
                         Err(_) => return Err(warp::reject::custom(super::Unauthorized)),
                     };
@@ -1,9 +1,49 @@
 pub mod cookies;
 
+use rsweb_state::AppState;
+use warp::Filter;
+
+pub use rsweb_auth::claims::client_info;
+
 #[derive(Debug)]
 pub struct Unauthorized;
 impl warp::reject::Reject for Unauthorized {}
 
+// Signed in, but the email address hasn't been verified yet
+#[derive(Debug)]
+pub struct Unverified;
+impl warp::reject::Reject for Unverified {}
+
 #[derive(Debug)]
 pub struct BadRequest;
 impl warp::reject::Reject for BadRequest {}
+
+#[derive(Debug)]
+pub struct Conflict(pub &'static str);
+impl warp::reject::Reject for Conflict {}
+
+#[derive(Debug)]
+pub struct ServiceUnavailable;
+impl warp::reject::Reject for ServiceUnavailable {}
+
+// Something on the server side failed (e.g. the database), not the request
+#[derive(Debug)]
+pub struct InternalError;
+impl warp::reject::Reject for InternalError {}
+
+// Requests shed because the password hashing queue is full are retryable
+pub fn hash_rejection(e: rsweb_crypto::errors::CryptoError) -> warp::Rejection {
+    match e {
+        rsweb_crypto::errors::CryptoError::HashQueueTimeout => {
+            warp::reject::custom(ServiceUnavailable)
+        }
+        _ => warp::reject::custom(BadRequest),
+    }
+}
+
+// Hands a clone of the application state to the handlers
+pub fn with_state(
+    state: AppState,
+) -> impl Filter<Extract = (AppState,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || state.clone())
+}
@@ -1,18 +1,248 @@
+use std::sync::Arc;
+
+use rsweb_auth::oauth::{HttpClient, ReqwestClient};
+use rsweb_state::AppState;
 use warp::Filter;
 
-use crate::endpoints::{signin, signup};
+use crate::endpoints::{
+    mfa, oauth, password, sessions, signin, signout, signup, verification, webauthn, well_known,
+};
+use crate::filters::{cookies::with_auth, with_state};
 
-pub fn login() -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn login(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("api" / "login")
         .and(warp::post())
+        .and(with_state(state))
         .and(signin::filter())
         .and_then(signin::handle)
 }
 
-pub fn register() -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
-{
+pub fn login_mfa(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "login" / "mfa")
+        .and(warp::post())
+        .and(with_state(state))
+        .and(mfa::challenge_filter())
+        .and_then(mfa::handle_challenge)
+}
+
+pub fn oauth_start(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("auth" / String / "start")
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(oauth::handle_start)
+}
+
+pub fn oauth_callback(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    oauth_callback_with(state, Arc::new(ReqwestClient::default()))
+}
+
+// The code exchange goes through the given client, e.g. one talking to a
+// local mock provider
+pub fn oauth_callback_with(
+    state: AppState,
+    http: Arc<dyn HttpClient>,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("auth" / String / "callback")
+        .and(warp::get())
+        .and(with_state(state))
+        .and(oauth::callback_filter(http))
+        .and_then(oauth::handle_callback)
+}
+
+pub fn register(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("api" / "register")
         .and(warp::post())
+        .and(with_state(state))
         .and(signup::filter())
         .and_then(signup::handle)
 }
+
+pub fn logout(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "logout")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and(signout::filter(state))
+        .and_then(signout::handle)
+}
+
+pub fn logout_all(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "logout" / "all")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and(with_auth(state))
+        .and_then(signout::handle_all)
+}
+
+pub fn forgot_password(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "password" / "forgot")
+        .and(warp::post())
+        .and(with_state(state))
+        .and(password::forgot_filter())
+        .and_then(password::handle_forgot)
+}
+
+pub fn reset_password(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "password" / "reset")
+        .and(warp::post())
+        .and(with_state(state))
+        .and(password::reset_filter())
+        .and_then(password::handle_reset)
+}
+
+pub fn resend_verification(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "verify-email" / "resend")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and(with_auth(state))
+        .and_then(verification::handle_resend)
+}
+
+pub fn setup_totp(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "mfa" / "totp" / "setup")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and(with_auth(state))
+        .and_then(mfa::handle_setup)
+}
+
+pub fn confirm_totp(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "mfa" / "totp" / "confirm")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and(with_auth(state))
+        .and(mfa::code_filter())
+        .and_then(mfa::handle_confirm)
+}
+
+pub fn disable_totp(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "mfa" / "totp" / "disable")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and(with_auth(state))
+        .and(mfa::code_filter())
+        .and_then(mfa::handle_disable)
+}
+
+pub fn webauthn_register_start(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webauthn" / "register" / "start")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and(with_auth(state))
+        .and_then(webauthn::handle_register_start)
+}
+
+pub fn webauthn_register_finish(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webauthn" / "register" / "finish")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and(with_auth(state))
+        .and(webauthn::register_filter())
+        .and_then(webauthn::handle_register_finish)
+}
+
+pub fn webauthn_login_start(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webauthn" / "login" / "start")
+        .and(warp::post())
+        .and(with_state(state))
+        .and_then(webauthn::handle_login_start)
+}
+
+pub fn webauthn_login_finish(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webauthn" / "login" / "finish")
+        .and(warp::post())
+        .and(with_state(state))
+        .and(webauthn::login_filter())
+        .and_then(webauthn::handle_login_finish)
+}
+
+pub fn webauthn_credentials(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webauthn" / "credentials")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and(with_auth(state))
+        .and_then(webauthn::handle_list)
+}
+
+pub fn remove_webauthn_credential(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webauthn" / "credentials" / i32)
+        .and(warp::delete())
+        .and(with_state(state.clone()))
+        .and(with_auth(state))
+        .and_then(webauthn::handle_remove)
+}
+
+pub fn sessions(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "sessions")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and(sessions::filter(state))
+        .and_then(sessions::handle_list)
+}
+
+pub fn revoke_session(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "sessions" / String)
+        .and(warp::delete())
+        .and(with_state(state.clone()))
+        .and(with_auth(state))
+        .and_then(sessions::handle_revoke)
+}
+
+pub fn jwks(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!(".well-known" / "jwks.json")
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(well_known::jwks)
+}
+
+pub fn openid_configuration(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!(".well-known" / "openid-configuration")
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(well_known::openid_configuration)
+}
@@ -1,2 +1,3 @@
 pub mod load_theme;
 pub mod nav;
+pub mod webauthn;
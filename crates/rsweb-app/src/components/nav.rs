@@ -14,6 +14,7 @@ pub fn navbar(_claims: Option<&Claims>) -> PreEscaped<String> {
                   a href="/about" { "About" }
                   a href="/blog/welcome-to-webrs" { "Blog" }
                   a href="/authenticated" { "Authenticated" }
+                  a href="/security" { "Security" }
                   a href="https://github.com/OnlyF0uR/rsweb-tmpl" { "Template" }
               }
               div class="nav-actions" {
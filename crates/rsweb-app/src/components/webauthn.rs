@@ -0,0 +1,83 @@
+use maud::PreEscaped;
+
+// Passkey ceremonies, the api sends and expects binary fields as base64url
+// strings while the browser works with ArrayBuffers
+pub const WEBAUTHN_SCRIPT: PreEscaped<&'static str> = PreEscaped(
+    r#"
+    <script>
+        window.toBase64Url = function(buffer) {
+            const bytes = new Uint8Array(buffer);
+            let binary = '';
+            for (const byte of bytes) {
+                binary += String.fromCharCode(byte);
+            }
+            return btoa(binary).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+        }
+
+        window.fromBase64Url = function(value) {
+            const base64 = value.replace(/-/g, '+').replace(/_/g, '/');
+            const binary = atob(base64 + '='.repeat((4 - base64.length % 4) % 4));
+            return Uint8Array.from(binary, (c) => c.charCodeAt(0)).buffer;
+        }
+
+        // Resolves with the response of the finish request
+        window.passkeyLogin = async function() {
+            const start = await fetch('/api/webauthn/login/start', { method: 'POST' });
+            if (!start.ok) {
+                return start;
+            }
+
+            const options = await start.json();
+            options.challenge = fromBase64Url(options.challenge);
+            options.allowCredentials = (options.allowCredentials || []).map((c) => ({ ...c, id: fromBase64Url(c.id) }));
+
+            const credential = await navigator.credentials.get({ publicKey: options });
+            return fetch('/api/webauthn/login/finish', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify({
+                    id: credential.id,
+                    response: {
+                        clientDataJSON: toBase64Url(credential.response.clientDataJSON),
+                        authenticatorData: toBase64Url(credential.response.authenticatorData),
+                        signature: toBase64Url(credential.response.signature),
+                        userHandle: credential.response.userHandle && toBase64Url(credential.response.userHandle),
+                    },
+                }),
+            });
+        }
+
+        window.passkeyRegister = async function(name) {
+            const start = await fetch('/api/webauthn/register/start', { method: 'POST' });
+            if (!start.ok) {
+                return start;
+            }
+
+            const options = await start.json();
+            options.challenge = fromBase64Url(options.challenge);
+            options.user.id = fromBase64Url(options.user.id);
+            options.excludeCredentials = (options.excludeCredentials || []).map((c) => ({ ...c, id: fromBase64Url(c.id) }));
+
+            const credential = await navigator.credentials.create({ publicKey: options });
+            return fetch('/api/webauthn/register/finish', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify({
+                    credential: {
+                        id: credential.id,
+                        response: {
+                            clientDataJSON: toBase64Url(credential.response.clientDataJSON),
+                            attestationObject: toBase64Url(credential.response.attestationObject),
+                        },
+                    },
+                    name: name,
+                }),
+            });
+        }
+    </script>
+    "#,
+);
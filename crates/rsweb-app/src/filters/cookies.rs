@@ -1,14 +1,23 @@
-use rsweb_auth::claims::{AuthSession, Claims};
+use rsweb_auth::claims::{AuthSession, Claims, ClientInfo};
+use rsweb_state::AppState;
 use warp::Filter;
 
-pub fn with_auth() -> impl Filter<Extract = (AuthSession,), Error = warp::Rejection> + Clone {
-    warp::any()
+pub fn with_auth(
+    state: AppState,
+) -> impl Filter<Extract = (AuthSession,), Error = warp::Rejection> + Clone {
+    super::with_state(state)
         .and(warp::cookie::optional("auth_token"))
         .and(warp::cookie::optional("refresh_token"))
+        .and(super::client_info())
         .and_then(
-            |auth_token: Option<String>, refresh_token: Option<String>| async move {
+            |state: AppState,
+             auth_token: Option<String>,
+             refresh_token: Option<String>,
+             client: ClientInfo| async move {
                 let (claims, updated_tokens) =
-                    match Claims::try_from_tokens(&auth_token, &refresh_token).await {
+                    match Claims::try_from_tokens(&state, &auth_token, &refresh_token, &client)
+                        .await
+                    {
                         Ok((claims, updated_tokens)) => (claims, updated_tokens),
                         Err(_) => return Err(warp::reject::custom(super::Unauthorized)),
                     };
@@ -21,30 +30,69 @@ pub fn with_auth() -> impl Filter<Extract = (AuthSession,), Error = warp::Reject
         )
 }
 
-pub fn without_auth() -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
-    warp::any()
+// Like with_auth, but rejects users that haven't verified their email yet
+pub fn with_verified_auth(
+    state: AppState,
+) -> impl Filter<Extract = (AuthSession,), Error = warp::Rejection> + Clone {
+    super::with_state(state)
         .and(warp::cookie::optional("auth_token"))
         .and(warp::cookie::optional("refresh_token"))
+        .and(super::client_info())
         .and_then(
-            |auth_token: Option<String>, refresh_token: Option<String>| async move {
-                match Claims::try_from_tokens(&auth_token, &refresh_token).await {
-                    Ok((_claims, _updated_tokens)) => Err(warp::reject::custom(super::Authorized)),
-                    Err(_) => Ok(()),
+            |state: AppState,
+             auth_token: Option<String>,
+             refresh_token: Option<String>,
+             client: ClientInfo| async move {
+                let (claims, updated_tokens) =
+                    match Claims::try_from_tokens(&state, &auth_token, &refresh_token, &client)
+                        .await
+                    {
+                        Ok((claims, updated_tokens)) => (claims, updated_tokens),
+                        Err(_) => return Err(warp::reject::custom(super::Unauthorized)),
+                    };
+
+                if claims.unverified {
+                    return Err(warp::reject::custom(super::Unverified));
                 }
+
+                Ok::<_, warp::reject::Rejection>(AuthSession {
+                    claims,
+                    updated_tokens,
+                })
             },
         )
+}
+
+// Rejects signed in users, only looks at the access token so that a visit
+// doesn't rotate the refresh token behind the browser's back
+pub fn without_auth(state: AppState) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    super::with_state(state)
+        .and(warp::cookie::optional("auth_token"))
+        .and_then(|state: AppState, auth_token: Option<String>| async move {
+            match Claims::from_auth_token(&state, &auth_token).await {
+                Ok(_claims) => Err(warp::reject::custom(super::Authorized)),
+                Err(_) => Ok(()),
+            }
+        })
         .untuple_one()
 }
 
-pub fn with_opt_auth()
--> impl Filter<Extract = (Option<AuthSession>,), Error = warp::Rejection> + Clone {
-    warp::any()
+pub fn with_opt_auth(
+    state: AppState,
+) -> impl Filter<Extract = (Option<AuthSession>,), Error = warp::Rejection> + Clone {
+    super::with_state(state)
         .and(warp::cookie::optional("auth_token"))
         .and(warp::cookie::optional("refresh_token"))
+        .and(super::client_info())
         .and_then(
-            |auth_token: Option<String>, refresh_token: Option<String>| async move {
+            |state: AppState,
+             auth_token: Option<String>,
+             refresh_token: Option<String>,
+             client: ClientInfo| async move {
                 let (claims, updated_tokens) =
-                    match Claims::try_from_tokens(&auth_token, &refresh_token).await {
+                    match Claims::try_from_tokens(&state, &auth_token, &refresh_token, &client)
+                        .await
+                    {
                         Ok((claims, updated_tokens)) => (claims, updated_tokens),
                         Err(_) => return Ok(None),
                     };
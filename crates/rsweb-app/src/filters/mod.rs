@@ -1,10 +1,27 @@
 pub mod blog;
 pub mod cookies;
 
+use rsweb_state::AppState;
+use warp::Filter;
+
+pub use rsweb_auth::claims::client_info;
+
 #[derive(Debug)]
 pub struct Unauthorized;
 impl warp::reject::Reject for Unauthorized {}
 
+// Signed in, but the email address hasn't been verified yet
+#[derive(Debug)]
+pub struct Unverified;
+impl warp::reject::Reject for Unverified {}
+
 #[derive(Debug)]
 pub struct Authorized;
 impl warp::reject::Reject for Authorized {}
+
+// Hands a clone of the application state to the handlers
+pub fn with_state(
+    state: AppState,
+) -> impl Filter<Extract = (AppState,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || state.clone())
+}
@@ -0,0 +1,44 @@
+use maud::{Markup, html};
+
+use super::password::layout;
+
+pub fn verified(success: bool) -> Markup {
+    if success {
+        return layout(
+            "Email verified",
+            html! {
+              p { "Thanks, your email address is verified." }
+              a href="/" { "Continue" }
+            },
+            "",
+        );
+    }
+
+    layout(
+        "Verify email",
+        html! {
+          p { "This link is invalid or has expired." }
+          button id="resend" class="continue" { "Send a new link" }
+          p class="status" {}
+        },
+        r#"
+            document.getElementById('resend').addEventListener('click', async () => {
+                const status = document.querySelector('.status');
+
+                const res = await fetch('/api/verify-email/resend', {
+                    method: 'POST',
+                });
+
+                if (res.ok) {
+                    status.innerText = 'A new link is on its way.';
+                } else if (res.status === 401) {
+                    status.innerText = 'Please sign in to request a new link.';
+                } else if (res.status === 409) {
+                    status.innerText = 'Your email address is already verified.';
+                } else {
+                    status.innerText = 'The link could not be sent, please try again later.';
+                }
+            });
+        "#,
+    )
+}
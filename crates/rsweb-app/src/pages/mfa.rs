@@ -0,0 +1,196 @@
+use maud::{Markup, PreEscaped, html};
+use qrcode::{QrCode, render::svg};
+use rsweb_auth::mfa::Enrolment;
+
+use super::password::layout;
+
+pub enum TotpState {
+    Enabled { recovery_codes_left: i64 },
+    Enrolling(Enrolment),
+    Disabled,
+    Unavailable,
+}
+
+// Second login step, the token from the first step is kept in sessionStorage
+// by the portal
+pub fn challenge() -> Markup {
+    layout(
+        "Two-factor authentication",
+        html! {
+          p { "Enter the code from your authenticator app, or one of your recovery codes." }
+          form id="challenge" {
+            div class="field" {
+              label class="field-label" for="code" { "Code" }
+              input class="field-input" type="text" name="code" id="code" required autocomplete="one-time-code" placeholder="123456" {}
+            }
+            button type="submit" class="continue" { "Verify" }
+          }
+          p class="status" {}
+        },
+        r#"
+            // Sign ins through a provider redirect pass the token in the fragment
+            const fragment = new URLSearchParams(window.location.hash.slice(1));
+            if (fragment.get('mfa_token')) {
+                sessionStorage.setItem('mfa_token', fragment.get('mfa_token'));
+                history.replaceState(null, '', window.location.pathname);
+            }
+
+            if (!sessionStorage.getItem('mfa_token')) {
+                window.location.href = '/login';
+            }
+
+            document.getElementById('challenge').addEventListener('submit', async (e) => {
+                e.preventDefault();
+                const status = document.querySelector('.status');
+
+                const res = await fetch('/api/login/mfa', {
+                    method: 'POST',
+                    headers: {
+                        'Content-Type': 'application/json',
+                    },
+                    body: JSON.stringify({
+                        mfa_token: sessionStorage.getItem('mfa_token'),
+                        code: document.getElementById('code').value,
+                    }),
+                });
+
+                if (res.ok) {
+                    sessionStorage.removeItem('mfa_token');
+                    window.location.href = '/';
+                } else if (res.status === 401) {
+                    sessionStorage.removeItem('mfa_token');
+                    status.innerText = 'Your sign in expired, please start over.';
+                    setTimeout(() => window.location.href = '/login', 2000);
+                } else {
+                    status.innerText = 'That code didn\'t work, please try again.';
+                }
+            });
+        "#,
+    )
+}
+
+pub fn setup(state: &TotpState) -> Markup {
+    match state {
+        TotpState::Enabled {
+            recovery_codes_left,
+        } => layout(
+            "Two-factor authentication",
+            html! {
+              p { "Two-factor authentication is on. You have " (recovery_codes_left) " unused recovery codes." }
+              form id="disable" {
+                div class="field" {
+                  label class="field-label" for="code" { "Code" }
+                  input class="field-input" type="text" name="code" id="code" required autocomplete="one-time-code" placeholder="123456" {}
+                }
+                button type="submit" class="continue" { "Turn off" }
+              }
+              p class="status" {}
+            },
+            r#"
+                document.getElementById('disable').addEventListener('submit', async (e) => {
+                    e.preventDefault();
+
+                    const res = await fetch('/api/mfa/totp/disable', {
+                        method: 'POST',
+                        headers: {
+                            'Content-Type': 'application/json',
+                        },
+                        body: JSON.stringify({
+                            code: document.getElementById('code').value,
+                        }),
+                    });
+
+                    if (res.ok) {
+                        window.location.reload();
+                    } else {
+                        document.querySelector('.status').innerText = 'That code didn\'t work, please try again.';
+                    }
+                });
+            "#,
+        ),
+        TotpState::Enrolling(enrolment) => layout(
+            "Two-factor authentication",
+            html! {
+              p { "Scan the code with your authenticator app, or enter the key by hand." }
+              @if let Some(qr) = qr_svg(&enrolment.uri) {
+                div class="qr" { (PreEscaped(qr)) }
+              }
+              p { code { (enrolment.secret) } }
+              form id="confirm" {
+                div class="field" {
+                  label class="field-label" for="code" { "Code from the app" }
+                  input class="field-input" type="text" name="code" id="code" required autocomplete="one-time-code" placeholder="123456" {}
+                }
+                button type="submit" class="continue" { "Turn on" }
+              }
+              div id="recovery" hidden {
+                p { "Keep these recovery codes somewhere safe. Each one signs you in once if you lose your device, they won't be shown again." }
+                pre {}
+                a href="/security" { "Done" }
+              }
+              p class="status" {}
+            },
+            r#"
+                document.getElementById('confirm').addEventListener('submit', async (e) => {
+                    e.preventDefault();
+                    const form = e.target;
+
+                    const res = await fetch('/api/mfa/totp/confirm', {
+                        method: 'POST',
+                        headers: {
+                            'Content-Type': 'application/json',
+                        },
+                        body: JSON.stringify({
+                            code: document.getElementById('code').value,
+                        }),
+                    });
+
+                    if (res.ok) {
+                        const { recovery_codes } = await res.json();
+                        const recovery = document.getElementById('recovery');
+                        recovery.querySelector('pre').innerText = recovery_codes.join('\n');
+                        recovery.hidden = false;
+                        form.hidden = true;
+                        document.querySelector('.qr')?.remove();
+                    } else {
+                        document.querySelector('.status').innerText = 'That code didn\'t work, check the time on your device and try again.';
+                    }
+                });
+            "#,
+        ),
+        TotpState::Disabled => layout(
+            "Two-factor authentication",
+            html! {
+              p { "Protect your account with a code from an authenticator app when you sign in." }
+              button type="button" id="setup" class="continue" { "Set up" }
+              p class="status" {}
+            },
+            r#"
+                document.getElementById('setup').addEventListener('click', async () => {
+                    const res = await fetch('/api/mfa/totp/setup', {
+                        method: 'POST',
+                    });
+
+                    if (res.ok) {
+                        window.location.reload();
+                    } else {
+                        document.querySelector('.status').innerText = 'Two-factor authentication can\'t be set up right now, please try again later.';
+                    }
+                });
+            "#,
+        ),
+        TotpState::Unavailable => layout(
+            "Two-factor authentication",
+            html! {
+              p { "Two-factor authentication can't be set up right now, please try again later." }
+              a href="/security" { "Back" }
+            },
+            "",
+        ),
+    }
+}
+
+fn qr_svg(uri: &str) -> Option<String> {
+    let code = QrCode::new(uri.as_bytes()).ok()?;
+    Some(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
@@ -1,4 +1,8 @@
 pub mod about;
 pub mod blog;
+pub mod email;
+pub mod mfa;
+pub mod password;
 pub mod portal;
 pub mod root;
+pub mod security;
@@ -0,0 +1,179 @@
+use maud::{DOCTYPE, Markup, PreEscaped, html};
+
+use crate::components::load_theme::LOAD_THEME;
+
+const STYLE: &str = r#"
+    #app {
+        display: flex;
+        justify-content: center;
+        align-items: center;
+        height: 100vh;
+    }
+
+    .box {
+        max-width: 28rem;
+        width: 100%;
+        padding: 0 1rem;
+    }
+
+    .box p {
+        color: rgb(75 85 99);
+        margin-bottom: 1rem;
+    }
+
+    .field {
+        margin-bottom: 1rem;
+        display: flex;
+        flex-direction: column;
+        gap: 0.5rem;
+    }
+
+    .field-label {
+        color: rgb(55 65 81);
+        font-weight: 500;
+        font-size: 1.125rem;
+        line-height: 1.75rem;
+    }
+
+    .field-input {
+        color: rgb(55 65 81);
+        font-size: 0.875rem;
+        line-height: 1.25rem;
+        padding: 0.75rem 1rem;
+        border: 1px solid rgb(209 213 219);
+        border-radius: 0.5rem;
+        height: 2.5rem;
+        width: 100%;
+    }
+
+    .continue {
+        background-color: var(--theme);
+        color: white;
+        border: none;
+        border-radius: 0.5rem;
+        font-weight: 600;
+        font-size: 0.875rem;
+        padding: 0.75rem 1rem;
+        cursor: pointer;
+        height: 2.5rem;
+        width: 100%;
+        margin-bottom: 1rem;
+    }
+
+    .status {
+        color: rgb(75 85 99);
+        font-size: 0.875rem;
+    }
+"#;
+
+pub(crate) fn layout(title: &str, content: Markup, script: &'static str) -> Markup {
+    html! {
+      (DOCTYPE)
+      html {
+        head {
+          title { (title) }
+          script defer src="/static/router.js" {}
+          (LOAD_THEME)
+          link data-dynamic rel="stylesheet" type="text/css" href="/static/app.css" {}
+          style data-dynamic { (PreEscaped(STYLE)) }
+        }
+        body {
+          div id="app" {
+            div class="box" {
+              h1 { (title) }
+              (content)
+            }
+          }
+          script type="text/javascript" data-dynamic { (PreEscaped(script)) }
+        }
+      }
+    }
+}
+
+pub fn forgot() -> Markup {
+    layout(
+        "Forgot password",
+        html! {
+          p { "Enter the email address of your account and we'll send you a link to choose a new password." }
+          form id="forgot" {
+            div class="field" {
+              label class="field-label" for="email" { "Email" }
+              input class="field-input" type="email" name="email" id="email" required placeholder="you@example.com" {}
+            }
+            button type="submit" class="continue" { "Send reset link" }
+          }
+          p class="status" {}
+        },
+        r#"
+            document.getElementById('forgot').addEventListener('submit', async (e) => {
+                e.preventDefault();
+                const status = document.querySelector('.status');
+
+                const res = await fetch('/api/password/forgot', {
+                    method: 'POST',
+                    headers: {
+                        'Content-Type': 'application/json',
+                    },
+                    body: JSON.stringify({
+                        email: document.getElementById('email').value,
+                    }),
+                });
+
+                status.innerText = res.ok
+                    ? 'If an account exists for that address, a reset link is on its way.'
+                    : 'Please enter a valid email address.';
+            });
+        "#,
+    )
+}
+
+pub fn reset(token: &str, valid: bool) -> Markup {
+    if !valid {
+        return layout(
+            "Reset password",
+            html! {
+              p { "This link is invalid or has expired." }
+              a href="/forgot" { "Request a new link" }
+            },
+            "",
+        );
+    }
+
+    layout(
+        "Reset password",
+        html! {
+          form id="reset" data-token=(token) {
+            div class="field" {
+              label class="field-label" for="password" { "New password" }
+              input class="field-input" type="password" name="password" id="password" required minlength="6" maxlength="64" placeholder="••••••••" {}
+            }
+            button type="submit" class="continue" { "Set password" }
+          }
+          p class="status" {}
+        },
+        r#"
+            document.getElementById('reset').addEventListener('submit', async (e) => {
+                e.preventDefault();
+                const form = e.target;
+                const status = document.querySelector('.status');
+
+                const res = await fetch('/api/password/reset', {
+                    method: 'POST',
+                    headers: {
+                        'Content-Type': 'application/json',
+                    },
+                    body: JSON.stringify({
+                        token: form.dataset.token,
+                        password: document.getElementById('password').value,
+                    }),
+                });
+
+                if (res.ok) {
+                    window.location.href = '/login';
+                } else {
+                    status.innerText = 'The password could not be reset, the link may have expired.';
+                }
+            });
+        "#,
+    )
+}
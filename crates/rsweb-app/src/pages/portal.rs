@@ -1,9 +1,10 @@
 use maud::{DOCTYPE, Markup, html};
-use rsweb_auth::google_client_id;
+use rsweb_auth::{google_client_id, oidc};
+use rsweb_state::AppState;
 
-use crate::components::load_theme::LOAD_THEME;
+use crate::components::{load_theme::LOAD_THEME, webauthn::WEBAUTHN_SCRIPT};
 
-pub fn render() -> Markup {
+pub fn render(state: &AppState) -> Markup {
     html! {
       (DOCTYPE)
       html {
@@ -11,6 +12,7 @@ pub fn render() -> Markup {
           title { "Portal" }
           script defer src="/static/router.js" {}
           (LOAD_THEME)
+          (WEBAUTHN_SCRIPT)
           link data-dynamic rel="stylesheet" type="text/css" href="/static/app.css" {}
           style data-dynamic { r#"
                     #app {
@@ -176,6 +178,12 @@ pub fn render() -> Markup {
 		                margin-bottom: 1rem;
                     }
 
+                    .login-error {
+                        color: rgb(185 28 28);
+                        font-size: 0.875rem;
+                        margin-bottom: 1rem;
+                    }
+
                     .sub-button-text {
                     	display: flex;
 		                justify-content: space-around;
@@ -202,6 +210,26 @@ pub fn render() -> Markup {
                         display: flex;
                         justify-content: center;
                     }
+
+                    .passkey,
+                    .provider {
+                        box-sizing: border-box;
+                        display: flex;
+                        justify-content: center;
+                        align-items: center;
+                        text-decoration: none;
+                        background-color: white;
+                        color: rgb(55 65 81);
+                        border: 1px solid rgb(209 213 219);
+                        border-radius: 0.5rem;
+                        font-weight: 500;
+                        font-size: 0.875rem;
+                        line-height: 1.25rem;
+                        cursor: pointer;
+                        height: 2.5rem;
+                        width: 100%;
+                        margin-bottom: 0.75rem;
+                    }
                 "# }
         }
         body {
@@ -254,8 +282,16 @@ pub fn render() -> Markup {
                   }
                 }
 
-                div id="g_id_onload" data-client_id=(&google_client_id().unwrap()) data-auto_prompt="false" data-callback="handleCredentialResponse" {}
-                div class="g_id_signin" data-type="standard" data-size="large" data-theme="outline" data-text="sign_in_with" data-shape="rectangular" data-logo_alignment="left" {}
+                @if let Some(client_id) = google_client_id(state) {
+                  div id="g_id_onload" data-client_id=(client_id) data-auto_prompt="false" data-callback="handleCredentialResponse" {}
+                  div class="g_id_signin" data-type="standard" data-size="large" data-theme="outline" data-text="sign_in_with" data-shape="rectangular" data-logo_alignment="left" {}
+                }
+
+                button type="button" class="passkey" onclick="signInWithPasskey()" { "Sign in with passkey" }
+                @for provider in oidc::provider_names(state) {
+                  a class="provider" href={ "/auth/" (provider) "/start" } { "Sign in with " (provider) }
+                }
+                p class="login-error" hidden {}
 
                 div class="sub-button-text" {
                   p {
@@ -269,6 +305,13 @@ pub fn render() -> Markup {
           script src="https://accounts.google.com/gsi/client" async data-dynamic {}
           script type="text/javascript" data-dynamic {
             r#"
+                    // Set when a sign in through a provider redirect failed
+                    if (new URLSearchParams(window.location.search).has('error')) {
+                        const error = document.querySelector('.login-error');
+                        error.innerText = 'Signing in didn\'t work. Accounts have to be created before signing in with a provider.';
+                        error.hidden = false;
+                    }
+
                     // Constant for the current login display (login vs. register)
                     window.showLogin = true;
 
@@ -319,12 +362,59 @@ pub fn render() -> Markup {
                             }),
                         })
 
-                        if (res.ok) {
-                            window.location.reload();
-                        } else {
+                        await finishLogin(res);
+                    }
+
+                    // Accounts with two-factor authentication get a challenge
+                    // token instead of a session
+                    window.finishLogin = async function(res) {
+                        if (!res.ok) {
                             console.error('Failed to login');
+                            return;
+                        }
+
+                        const body = await res.text();
+                        const mfaToken = body && JSON.parse(body).mfa_token;
+                        if (mfaToken) {
+                            sessionStorage.setItem('mfa_token', mfaToken);
+                            window.location.href = '/login/mfa';
+                        } else {
+                            window.location.reload();
+                        }
+                    }
+
+                    window.signInWithPasskey = async function() {
+                        if (!window.PublicKeyCredential) {
+                            console.error('Passkeys are not supported by this browser');
+                            return;
+                        }
+
+                        try {
+                            await finishLogin(await passkeyLogin());
+                        } catch (e) {
+                            // The user closed the prompt or has no passkey for the site
+                            console.error(e);
                         }
                     }
+
+                    document.querySelector('form').addEventListener('submit', async (e) => {
+                        e.preventDefault();
+                        const form = e.target;
+
+                        const res = await fetch(form.action, {
+                            method: 'POST',
+                            headers: {
+                                'Content-Type': 'application/json',
+                            },
+                            body: JSON.stringify({
+                                username: form.username.value,
+                                email: form.email.value,
+                                password: form.password.value,
+                            }),
+                        });
+
+                        await finishLogin(res);
+                    });
                     "#
           }
         }
@@ -0,0 +1,149 @@
+use maud::{DOCTYPE, Markup, html};
+use rsweb_auth::claims::Claims;
+use rsweb_database::user::{SessionDetails, WebauthnCredential};
+use rsweb_utils::time_ago;
+
+use crate::components::{
+    load_theme::LOAD_THEME,
+    nav::{NAV_SCRIPT, navbar},
+    webauthn::WEBAUTHN_SCRIPT,
+};
+
+pub async fn render(
+    claims: &Claims,
+    sessions: &[SessionDetails],
+    passkeys: &[WebauthnCredential],
+) -> Markup {
+    html! {
+      (DOCTYPE)
+      html {
+        head {
+          title { "Security" }
+          script defer src="/static/router.js" {}
+          (LOAD_THEME)
+          (NAV_SCRIPT)
+          (WEBAUTHN_SCRIPT)
+          link data-dynamic rel="stylesheet" type="text/css" href="/static/app.css" {}
+          style data-dynamic { r#"
+              .security {
+                  max-width: 48rem;
+                  margin: 6rem auto 2rem auto;
+                  padding: 0 1rem;
+              }
+              .session {
+                  display: flex;
+                  justify-content: space-between;
+                  align-items: center;
+                  gap: 1rem;
+                  padding: 1rem 0;
+                  border-bottom: 1px solid rgb(229 231 235);
+              }
+              .session-agent {
+                  color: var(--text-primary);
+                  font-weight: 500;
+                  word-break: break-word;
+              }
+              .session-meta {
+                  color: var(--text-tertiary);
+                  font-size: 0.875rem;
+              }
+              .session-current {
+                  color: var(--primary-accent);
+                  font-size: 0.875rem;
+                  font-weight: 600;
+              }
+          "# }
+        }
+        body {
+          div id="app" {
+            (navbar(Some(claims)))
+            main class="security" {
+              h1 { "Security" }
+              p { a href="/security/mfa" { "Two-factor authentication" } }
+              h2 { "Passkeys" }
+              @for passkey in passkeys {
+                div class="session" {
+                  div {
+                    div class="session-agent" { (passkey.name) }
+                    div class="session-meta" {
+                      "added " (time_ago(&passkey.created_at))
+                      @if let Some(last_used_at) = &passkey.last_used_at {
+                        " · last used " (time_ago(last_used_at))
+                      }
+                    }
+                  }
+                  button type="button" data-passkey=(passkey.id) onclick="removePasskey(this)" { "Remove" }
+                }
+              }
+              form id="add-passkey" {
+                input type="text" name="name" maxlength="64" placeholder="Passkey name" {}
+                button type="submit" { "Add a passkey" }
+              }
+              h2 { "Where you're signed in" }
+              @for session in sessions {
+                div class="session" {
+                  div {
+                    div class="session-agent" {
+                      (session.user_agent.as_deref().unwrap_or("Unknown device"))
+                    }
+                    div class="session-meta" {
+                      (session.ip_address.as_deref().unwrap_or("Unknown location"))
+                      " · signed in " (time_ago(&session.signed_in_at))
+                      " · last active " (time_ago(&session.last_used_at))
+                    }
+                  }
+                  @if session.current {
+                    span class="session-current" { "This device" }
+                  } @else {
+                    button type="button" data-session=(session.family_id) onclick="revokeSession(this)" { "Sign out" }
+                  }
+                }
+              }
+            }
+          }
+          script type="text/javascript" data-dynamic {
+            r#"
+                    window.removePasskey = async function(button) {
+                        const res = await fetch('/api/webauthn/credentials/' + button.dataset.passkey, {
+                            method: 'DELETE',
+                        });
+
+                        if (res.ok) {
+                            button.closest('.session').remove();
+                        } else {
+                            console.error('Failed to remove passkey');
+                        }
+                    }
+
+                    document.getElementById('add-passkey').addEventListener('submit', async (e) => {
+                        e.preventDefault();
+
+                        try {
+                            const res = await passkeyRegister(e.target.name.value);
+                            if (res.ok) {
+                                window.location.reload();
+                            } else {
+                                console.error('Failed to add passkey');
+                            }
+                        } catch (e) {
+                            console.error(e);
+                        }
+                    });
+
+                    window.revokeSession = async function(button) {
+                        const res = await fetch('/api/sessions/' + encodeURIComponent(button.dataset.session), {
+                            method: 'DELETE',
+                        });
+
+                        if (res.ok) {
+                            button.closest('.session').remove();
+                        } else {
+                            console.error('Failed to sign out session');
+                        }
+                    }
+                    "#
+          }
+        }
+      }
+    }
+}
@@ -1,22 +1,71 @@
+use rsweb_state::AppState;
 use warp::{Filter, reject::Rejection, reply::Reply};
 
 use crate::{
     filters::{self, blog::ensure_blog},
-    pages,
+    pages::{self, mfa::TotpState},
 };
 
 // Example of an authenticated route
-pub fn authenticated()
--> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn authenticated(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path("authenticated")
         .and(warp::get())
-        .and(filters::cookies::with_auth().and_then(
-            |auth_session: rsweb_auth::claims::AuthSession| async move {
+        .and(filters::with_state(state.clone()))
+        .and(filters::cookies::with_auth(state))
+        .and_then(
+            |state: AppState, auth_session: rsweb_auth::claims::AuthSession| async move {
                 let reply = warp::reply::html(
                     "You are authenticated! This is a protected route.".to_string(),
                 );
 
-                if let Some(cookies) = cookie_map(auth_session.updated_tokens) {
+                if let Some(cookies) = cookie_map(&state, auth_session.updated_tokens) {
+                    let mut response = reply.into_response();
+                    let headers = response.headers_mut();
+                    headers.extend(cookies);
+
+                    return Ok::<_, Rejection>(response);
+                }
+
+                Ok::<_, Rejection>(reply.into_response())
+            },
+        )
+}
+
+// Lists the signed in devices and passkeys of the user
+pub fn security(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path("security")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(filters::with_state(state.clone()))
+        .and(filters::cookies::with_auth(state))
+        .and(warp::cookie::optional("refresh_token"))
+        .and_then(
+            |state: AppState,
+             auth_session: rsweb_auth::claims::AuthSession,
+             refresh_token: Option<String>| async move {
+                let sessions = rsweb_auth::claims::refresh_tokens::list_sessions(
+                    &state,
+                    auth_session.claims.uid,
+                    refresh_token.as_deref(),
+                )
+                .await
+                .unwrap_or_default();
+                let passkeys =
+                    rsweb_auth::webauthn::list_credentials(&state, auth_session.claims.uid)
+                        .await
+                        .unwrap_or_default();
+
+                let reply = warp::reply::html(
+                    pages::security::render(&auth_session.claims, &sessions, &passkeys)
+                        .await
+                        .into_string(),
+                );
+
+                if let Some(cookies) = cookie_map(&state, auth_session.updated_tokens) {
                     let mut response = reply.into_response();
                     let headers = response.headers_mut();
                     headers.extend(cookies);
@@ -26,7 +75,59 @@ pub fn authenticated()
 
                 Ok::<_, Rejection>(reply.into_response())
             },
-        ))
+        )
+}
+
+// Enrolment in, or turning off, two-factor authentication
+pub fn security_mfa(
+    app_state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("security" / "mfa")
+        .and(warp::get())
+        .and(filters::with_state(app_state.clone()))
+        .and(filters::cookies::with_auth(app_state))
+        .and_then(
+            |app_state: AppState, auth_session: rsweb_auth::claims::AuthSession| async move {
+                let claims = &auth_session.claims;
+                let state = match rsweb_auth::mfa::is_enabled(&app_state, claims.uid).await {
+                    Ok(true) => TotpState::Enabled {
+                        recovery_codes_left: rsweb_auth::mfa::remaining_recovery_codes(
+                            &app_state, claims.uid,
+                        )
+                        .await
+                        .unwrap_or_default(),
+                    },
+                    // Only shows a pending secret, new ones are created by
+                    // POST /api/mfa/totp/setup
+                    Ok(false) => {
+                        match rsweb_auth::mfa::pending_enrolment(
+                            &app_state,
+                            claims.uid,
+                            &claims.email,
+                        )
+                        .await
+                        {
+                            Ok(Some(enrolment)) => TotpState::Enrolling(enrolment),
+                            Ok(None) => TotpState::Disabled,
+                            Err(_) => TotpState::Unavailable,
+                        }
+                    }
+                    Err(_) => TotpState::Unavailable,
+                };
+
+                let reply = warp::reply::html(pages::mfa::setup(&state).into_string());
+
+                if let Some(cookies) = cookie_map(&app_state, auth_session.updated_tokens) {
+                    let mut response = reply.into_response();
+                    let headers = response.headers_mut();
+                    headers.extend(cookies);
+
+                    return Ok::<_, Rejection>(response);
+                }
+
+                Ok::<_, Rejection>(reply.into_response())
+            },
+        )
 }
 
 pub fn explore() -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -45,61 +146,103 @@ pub fn blog() -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rej
         }))
 }
 
-pub fn login() -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn login(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path("login")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(filters::with_state(state.clone()))
+        .and(filters::cookies::without_auth(state))
+        .map(|state: AppState| warp::reply::html(pages::portal::render(&state).into_string()))
+}
+
+// Second login step for accounts with two-factor authentication
+pub fn login_mfa(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("login" / "mfa")
+        .and(warp::get())
+        .and(filters::cookies::without_auth(state))
+        .map(|| warp::reply::html(pages::mfa::challenge().into_string()))
+}
+
+pub fn forgot_password()
+-> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path("forgot")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| warp::reply::html(pages::password::forgot().into_string()))
+}
+
+pub fn reset_password(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("reset" / String)
         .and(warp::get())
-        .and(filters::cookies::without_auth())
-        .map(|| warp::reply::html(pages::portal::render().into_string()))
+        .and(filters::with_state(state))
+        .and_then(|token: String, state: AppState| async move {
+            let valid = rsweb_auth::password_reset::is_valid(&state, &token)
+                .await
+                .unwrap_or(false);
+
+            Ok::<_, Rejection>(warp::reply::html(
+                pages::password::reset(&token, valid).into_string(),
+            ))
+        })
+}
+
+// Target of the link in the verification mail
+pub fn verify_email(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("verify-email" / String)
+        .and(warp::get())
+        .and(filters::with_state(state))
+        .and_then(|token: String, state: AppState| async move {
+            let result = rsweb_auth::email_verification::verify(&state, &token).await;
+
+            Ok::<_, Rejection>(warp::reply::html(
+                pages::email::verified(result.is_ok()).into_string(),
+            ))
+        })
 }
 
 // The root route
-pub fn root() -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn root(
+    state: AppState,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path::end()
         .and(warp::get())
-        .and(filters::cookies::with_opt_auth().and_then(
-            |auth_session: Option<rsweb_auth::claims::AuthSession>| async move {
+        .and(filters::with_state(state.clone()))
+        .and(filters::cookies::with_opt_auth(state))
+        .and_then(
+            |state: AppState, auth_session: Option<rsweb_auth::claims::AuthSession>| async move {
                 let claims = auth_session.as_ref().map(|session| &session.claims);
                 let reply = warp::reply::html(pages::root::home(claims).await.into_string());
 
-                if let Some(auth_session) = auth_session {
-                    if let Some(cookies) = cookie_map(auth_session.updated_tokens) {
-                        let mut response = reply.into_response();
-                        let headers = response.headers_mut();
-                        headers.extend(cookies);
+                if let Some(auth_session) = auth_session
+                    && let Some(cookies) = cookie_map(&state, auth_session.updated_tokens)
+                {
+                    let mut response = reply.into_response();
+                    let headers = response.headers_mut();
+                    headers.extend(cookies);
 
-                        // Allow page and set updated cookies
-                        return Ok::<_, Rejection>(response);
-                    }
+                    // Allow page and set updated cookies
+                    return Ok::<_, Rejection>(response);
                 }
 
                 // Allow without updating cookies
                 Ok::<_, Rejection>(reply.into_response())
             },
-        ))
+        )
 }
 
-fn cookie_map(tokens: Option<(String, String)>) -> Option<warp::http::header::HeaderMap> {
-    let tokens = tokens?;
-
-    let mut cookies = warp::http::header::HeaderMap::new();
-    cookies.append(
-        "Set-Cookie",
-        format!(
-            "auth_token={}; HttpOnly; Secure; SameSite=Strict; Path=/",
-            tokens.0
-        )
-        .parse()
-        .unwrap(),
-    );
-    cookies.append(
-        "Set-Cookie",
-        format!(
-            "refresh_token={}; HttpOnly; Secure; SameSite=Strict; Path=/",
-            tokens.1
-        )
-        .parse()
-        .unwrap(),
-    );
+fn cookie_map(
+    state: &AppState,
+    tokens: Option<(String, String)>,
+) -> Option<warp::http::header::HeaderMap> {
+    let (at, rt) = tokens?;
 
-    Some(cookies)
+    Some(rsweb_auth::cookies::session_cookies(state, &at, &rt))
 }
@@ -1,15 +1,54 @@
 use base64::{Engine as _, engine::general_purpose};
 use rsweb_database::user::UserEssentials;
+use rsweb_state::AppState;
 use serde::{Deserialize, Serialize};
 
+use crate::TokenFormat;
 use crate::errors::AuthError;
 
+// Lifetime of an access token in seconds (2 hours by default)
+pub fn access_token_lifetime(state: &AppState) -> i64 {
+    state.config.tokens.access_token_lifetime.as_secs() as i64
+}
+
 #[derive(Debug)]
 pub struct AuthSession {
     pub claims: Claims,
     pub updated_tokens: Option<(String, String)>,
 }
 
+// Client a refresh token is issued to, shown to the user in their session list
+#[derive(Debug, Default, Clone)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl ClientInfo {
+    const MAX_USER_AGENT_LEN: usize = 256;
+
+    pub fn new(user_agent: Option<String>, ip_address: Option<std::net::IpAddr>) -> Self {
+        let user_agent = user_agent.map(|ua| ua.chars().take(Self::MAX_USER_AGENT_LEN).collect());
+
+        ClientInfo {
+            user_agent,
+            ip_address: ip_address.map(|ip| ip.to_string()),
+        }
+    }
+}
+
+// User agent and remote address of the request, recorded with refresh tokens
+pub fn client_info() -> impl warp::Filter<Extract = (ClientInfo,), Error = warp::Rejection> + Clone
+{
+    use warp::Filter;
+
+    warp::header::optional::<String>("user-agent")
+        .and(warp::addr::remote())
+        .map(|user_agent, addr: Option<std::net::SocketAddr>| {
+            ClientInfo::new(user_agent, addr.map(|addr| addr.ip()))
+        })
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub uid: i32,
@@ -21,64 +60,152 @@ pub struct Claims {
     pub role: String,
     #[serde(skip_serializing_if = "Option::is_none", rename = "a")]
     pub agency_id: Option<i32>,
+    // Only present while the email address hasn't been verified, tokens
+    // issued before the flag existed count as verified
+    #[serde(rename = "u", default, skip_serializing_if = "std::ops::Not::not")]
+    pub unverified: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct TokenMeta {
     #[serde(rename = "c")]
     claims: Claims,
+    #[serde(rename = "iat", default)]
+    issued_at: i64,
     #[serde(rename = "exp")]
     expires: i64,
     #[serde(rename = "n")]
     nonce: String,
 }
 
+// A token whose signature has been verified, in either format
+#[derive(Debug)]
+pub struct VerifiedToken {
+    pub claims: Claims,
+    // jti for JWTs, the nonce for legacy tokens
+    pub id: String,
+    pub issued_at: i64,
+    pub expires: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct SignatureToken {
+    // The serialized TokenMeta exactly as signed (base64url), it is only
+    // parsed after the signature over these bytes has been verified
     #[serde(rename = "m")]
-    meta: TokenMeta,
+    meta: String,
+    #[serde(rename = "k")]
+    kid: String,
     #[serde(rename = "d")]
     digest: String,
 }
 
 impl Claims {
     pub async fn try_from_tokens(
+        state: &AppState,
         auth_token: &Option<String>,
         refresh_token: &Option<String>,
+        client: &ClientInfo,
     ) -> Result<(Self, Option<(String, String)>), AuthError> {
+        // The access token cookie expires with the token, the refresh token
+        // takes over from there
+        let auth_token = match (auth_token, refresh_token) {
+            (Some(token), _) => token,
+            (None, Some(refresh_token)) => {
+                let (claims, tokens) = refresh_tokens::rotate(state, refresh_token, client).await?;
+                return Ok((claims, Some(tokens)));
+            }
+            (None, None) => return Err(AuthError::InvalidToken),
+        };
+
+        let token = Self::decode(state, auth_token).await?;
+
+        // Expired tokens need a refresh either way, so only live tokens are
+        // checked against the revocation list
+        let expired = token.expires < unix_secs();
+        let revoked = !expired && crate::revocation::is_revoked(state, &token).await;
+
+        if expired || revoked {
+            // A revoked token (e.g. after a role change) is replaced through
+            // the refresh token as well, which fails for banned users
+            return match refresh_token {
+                Some(refresh_token) => {
+                    match refresh_tokens::rotate(state, refresh_token, client).await {
+                        Ok((claims, tokens)) => Ok((claims, Some(tokens))),
+                        Err(_) if revoked => Err(AuthError::TokenRevoked),
+                        Err(_) => Err(AuthError::TokenExpired),
+                    }
+                }
+                None if revoked => Err(AuthError::TokenRevoked),
+                None => Err(AuthError::TokenExpired),
+            };
+        }
+
+        Ok((token.claims, None))
+    }
+
+    // Checks the access token alone and never refreshes it, so callers that
+    // can't hand rotated tokens back to the client don't spend the refresh
+    // token
+    pub async fn from_auth_token(
+        state: &AppState,
+        auth_token: &Option<String>,
+    ) -> Result<Self, AuthError> {
         let auth_token = match auth_token {
             Some(token) => token,
             None => return Err(AuthError::InvalidToken),
         };
 
+        let token = Self::decode(state, auth_token).await?;
+        if token.expires < unix_secs() {
+            return Err(AuthError::TokenExpired);
+        }
+        if crate::revocation::is_revoked(state, &token).await {
+            return Err(AuthError::TokenRevoked);
+        }
+
+        Ok(token.claims)
+    }
+
+    // Verifies the signature of a token without checking expiry or revocation
+    pub async fn decode(state: &AppState, auth_token: &str) -> Result<VerifiedToken, AuthError> {
+        // Accept both formats while tokens migrate to JWTs
+        if crate::jwt::is_jwt(auth_token) {
+            let payload = crate::jwt::decode(state, auth_token).await?;
+            Ok(VerifiedToken {
+                claims: payload.claims()?,
+                id: payload.jti,
+                issued_at: payload.iat,
+                expires: payload.exp,
+            })
+        } else {
+            Self::decode_legacy(state, auth_token).await
+        }
+    }
+
+    async fn decode_legacy(state: &AppState, auth_token: &str) -> Result<VerifiedToken, AuthError> {
         let json_str = general_purpose::URL_SAFE_NO_PAD.decode(auth_token.as_bytes())?;
         let sig_token = serde_json::from_slice::<SignatureToken>(&json_str)?;
 
-        // Check signature
-        let json = serde_json::to_string(&sig_token.meta.claims)?;
+        // Check signature over the raw meta bytes
+        let meta_bytes = general_purpose::URL_SAFE_NO_PAD.decode(sig_token.meta.as_bytes())?;
         let sbytes = general_purpose::URL_SAFE_NO_PAD.decode(sig_token.digest.as_bytes())?;
 
-        if !rsweb_crypto::ed25519::verify_signature(json.as_bytes(), sbytes.as_slice()).await {
+        if !state
+            .keys
+            .verify_signature(&sig_token.kid, meta_bytes.as_slice(), sbytes.as_slice())
+            .await
+        {
             return Err(AuthError::InvalidSignature);
         }
 
-        let mut updated_tokens = None;
-
-        // Check expiry
-        if sig_token.meta.expires < unix_secs() {
-            if let Some(refresh_token) = refresh_token {
-                match refresh_tokens::rotate(refresh_token).await {
-                    Ok(tokens) => {
-                        updated_tokens = Some(tokens);
-                    }
-                    Err(_) => return Err(AuthError::TokenExpired),
-                };
-            } else {
-                return Err(AuthError::TokenExpired);
-            }
-        }
-
-        Ok((sig_token.meta.claims, updated_tokens))
+        let meta = serde_json::from_slice::<TokenMeta>(&meta_bytes)?;
+        Ok(VerifiedToken {
+            claims: meta.claims,
+            id: meta.nonce,
+            issued_at: meta.issued_at,
+            expires: meta.expires,
+        })
     }
 
     pub async fn from_user_essentials(user_essentials: &UserEssentials) -> Self {
@@ -88,29 +215,27 @@ impl Claims {
             username: user_essentials.handle.clone(),
             role: user_essentials.role.clone(),
             agency_id: None, // TODO: Implement agency
+            unverified: !user_essentials.email_verified,
         }
     }
 
-    pub async fn create_token(&self) -> String {
-        let expiry = unix_secs() + 7200;
-        let nonce = rsweb_crypto::generate::generate_nonce();
+    pub async fn create_token(&self, state: &AppState) -> Result<String, AuthError> {
+        match crate::token_format(state) {
+            TokenFormat::Jwt => crate::jwt::encode(state, self).await,
+            TokenFormat::Legacy => self.create_legacy_token(state).await,
+        }
+    }
 
+    async fn create_legacy_token(&self, state: &AppState) -> Result<String, AuthError> {
+        let now = unix_secs();
         let meta = TokenMeta {
             claims: self.clone(),
-            expires: expiry,
-            nonce,
+            issued_at: now,
+            expires: now + access_token_lifetime(state),
+            nonce: rsweb_crypto::generate::generate_id(),
         };
 
-        let meta_str = serde_json::to_string(&meta).unwrap();
-        let meta_bytes = meta_str.as_bytes();
-
-        let sig = rsweb_crypto::ed25519::sign_message(meta_bytes).await;
-        let digest = general_purpose::URL_SAFE_NO_PAD.encode(sig);
-
-        let access_token = SignatureToken { meta, digest };
-        let access_token_str = serde_json::to_string(&access_token).unwrap();
-
-        general_purpose::URL_SAFE_NO_PAD.encode(access_token_str.as_bytes())
+        encode_legacy(state, &meta).await
     }
 
     pub fn has_creator_privilege(&self) -> bool {
@@ -118,33 +243,161 @@ impl Claims {
     }
 }
 
+async fn encode_legacy(state: &AppState, meta: &TokenMeta) -> Result<String, AuthError> {
+    let meta_bytes = serde_json::to_vec(meta)?;
+
+    let (kid, sig) = state.keys.sign_message(&meta_bytes)?;
+
+    let access_token = SignatureToken {
+        meta: general_purpose::URL_SAFE_NO_PAD.encode(&meta_bytes),
+        kid,
+        digest: general_purpose::URL_SAFE_NO_PAD.encode(sig),
+    };
+    let access_token_str = serde_json::to_string(&access_token)?;
+
+    Ok(general_purpose::URL_SAFE_NO_PAD.encode(access_token_str.as_bytes()))
+}
+
 pub mod refresh_tokens {
-    use rsweb_database::user::UserService;
+    use rsweb_database::user::{SessionDetails, UserService};
+    use rsweb_state::AppState;
 
     use crate::errors::AuthError;
 
-    use super::Claims;
+    use super::{Claims, ClientInfo};
 
-    pub async fn create(user_id: i32) -> Result<String, AuthError> {
+    // Lifetime of a refresh token in seconds (2 weeks by default)
+    pub fn lifetime(state: &AppState) -> i32 {
+        state.config.tokens.refresh_token_lifetime.as_secs() as i32
+    }
+
+    // Issues the first refresh token of a new family, one family per login
+    pub async fn create(
+        state: &AppState,
+        user_id: i32,
+        client: &ClientInfo,
+    ) -> Result<String, AuthError> {
+        let family_id = rsweb_crypto::generate::generate_id();
         let token = rsweb_crypto::generate::generate_random_string(32);
+        let token_hash = state.hmac.hash(token.as_bytes());
 
-        UserService::insert_user_refresh_token(user_id, &token).await?;
+        UserService::insert_user_refresh_token(
+            state,
+            user_id,
+            &family_id,
+            &token_hash,
+            lifetime(state),
+            client.user_agent.as_deref(),
+            client.ip_address.as_deref(),
+        )
+        .await?;
         Ok(token)
     }
 
-    // TODO: We could also take in a reference to claims
-    // and change the query so it doesnt get the user essentials since
-    // we kind of already have those
-    pub async fn rotate(cookie_rt_str: &str) -> Result<(String, String), AuthError> {
-        // Check if the refresh token is valid
-        let us = UserService::get_user_essentials_by_refresh_token(cookie_rt_str).await?;
+    // Seconds a rotated refresh token is still turned down without revoking
+    // its family. Browsers send parallel requests with the same expired
+    // access token, all but the first of them present the rotated token.
+    const REUSE_GRACE_PERIOD: i32 = 10;
+
+    // Exchanges a refresh token for a new access and refresh token within
+    // the same family. Presenting a token that was already rotated revokes
+    // the whole family, as either the client or an attacker holds a copy,
+    // unless it was rotated just now.
+    pub async fn rotate(
+        state: &AppState,
+        cookie_rt_str: &str,
+        client: &ClientInfo,
+    ) -> Result<(Claims, (String, String)), AuthError> {
+        let rt_hash = state.hmac.hash(cookie_rt_str.as_bytes());
+        let details =
+            UserService::get_refresh_token_details(state, &rt_hash, REUSE_GRACE_PERIOD).await?;
+
+        if details.recently_rotated {
+            return Err(AuthError::TokenRevoked);
+        }
+        if details.revoked_at.is_some() {
+            let revoked =
+                UserService::revoke_refresh_token_family(state, &details.family_id).await?;
+            if revoked > 0 {
+                eprintln!(
+                    "Refresh token reuse detected for user {} (family {}), revoked {} token(s)",
+                    details.user_id, details.family_id, revoked
+                );
+            }
+            return Err(AuthError::TokenReused);
+        }
+
+        if details.banned {
+            UserService::revoke_refresh_token_family(state, &details.family_id).await?;
+            return Err(AuthError::TokenRevoked);
+        }
 
-        let at = Claims::from_user_essentials(&us).await.create_token().await;
+        let claims = Claims::from_user_essentials(&details.user_essentials()).await;
+        let at = claims.create_token(state).await?;
         let rt = rsweb_crypto::generate::generate_random_string(32);
+        let rt_hash = state.hmac.hash(rt.as_bytes());
+
+        // Lost a race against a concurrent rotation of the same token
+        if !UserService::rotate_refresh_token(
+            state,
+            details.id,
+            &rt_hash,
+            lifetime(state),
+            client.user_agent.as_deref(),
+            client.ip_address.as_deref(),
+        )
+        .await?
+        {
+            return Err(AuthError::TokenRevoked);
+        }
+        Ok((claims, (at, rt)))
+    }
 
-        UserService::delete_user_refresh_token(us.id).await?;
-        UserService::insert_user_refresh_token(us.id, &rt).await?;
-        Ok((at, rt))
+    // Live sessions of the user, the one the given refresh token belongs to
+    // is marked as current
+    pub async fn list_sessions(
+        state: &AppState,
+        user_id: i32,
+        cookie_rt_str: Option<&str>,
+    ) -> Result<Vec<SessionDetails>, AuthError> {
+        let rt_hash = match cookie_rt_str {
+            Some(rt) => state.hmac.hash(rt.as_bytes()),
+            None => String::new(),
+        };
+
+        Ok(UserService::get_user_sessions(state, user_id, &rt_hash).await?)
+    }
+
+    // Ends a single session by its family id, returns false if the user has
+    // no such session
+    pub async fn revoke_session(
+        state: &AppState,
+        user_id: i32,
+        family_id: &str,
+    ) -> Result<bool, AuthError> {
+        Ok(UserService::delete_refresh_token_family_by_id(state, user_id, family_id).await? > 0)
+    }
+
+    // Ends the session the refresh token belongs to by deleting its family
+    pub async fn revoke(
+        state: &AppState,
+        user_id: i32,
+        cookie_rt_str: &str,
+    ) -> Result<(), AuthError> {
+        let rt_hash = state.hmac.hash(cookie_rt_str.as_bytes());
+        UserService::delete_refresh_token_family(state, user_id, &rt_hash).await?;
+        Ok(())
+    }
+
+    // Ends every session of the user
+    pub async fn revoke_all(state: &AppState, user_id: i32) -> Result<(), AuthError> {
+        UserService::delete_user_refresh_token(state, user_id).await?;
+        Ok(())
+    }
+
+    // Deletes expired refresh tokens, returns the number of purged rows
+    pub async fn purge_expired(state: &AppState) -> Result<u64, AuthError> {
+        Ok(UserService::delete_expired_refresh_tokens(state).await?)
     }
 }
 
@@ -154,3 +407,458 @@ pub fn unix_secs() -> i64 {
         .unwrap()
         .as_secs() as i64
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsweb_database::user::UserService;
+    use rsweb_state::testing;
+    use sqlx::PgPool;
+
+    fn claims() -> Claims {
+        Claims {
+            uid: 42,
+            email: "user@example.com".to_string(),
+            username: "user".to_string(),
+            role: "user".to_string(),
+            agency_id: None,
+            unverified: false,
+        }
+    }
+
+    fn decode_token(token: &str) -> SignatureToken {
+        let json = general_purpose::URL_SAFE_NO_PAD.decode(token).unwrap();
+        serde_json::from_slice(&json).unwrap()
+    }
+
+    fn encode_token(sig_token: &SignatureToken) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(sig_token).unwrap())
+    }
+
+    // Re-encodes the token with modified meta while keeping the original digest
+    fn tamper_meta(token: &str, f: impl FnOnce(&mut TokenMeta)) -> String {
+        let mut sig_token = decode_token(token);
+        let meta_bytes = general_purpose::URL_SAFE_NO_PAD
+            .decode(&sig_token.meta)
+            .unwrap();
+        let mut meta: TokenMeta = serde_json::from_slice(&meta_bytes).unwrap();
+        f(&mut meta);
+        sig_token.meta =
+            general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&meta).unwrap());
+        encode_token(&sig_token)
+    }
+
+    #[tokio::test]
+    async fn test_valid_token() {
+        let state = &testing::state().await;
+        let token = claims().create_legacy_token(state).await.unwrap();
+        let (claims, updated_tokens) =
+            Claims::try_from_tokens(state, &Some(token.clone()), &None, &ClientInfo::default())
+                .await
+                .unwrap();
+        assert_eq!(claims.uid, 42);
+        assert_eq!(claims.role, "user");
+        assert!(updated_tokens.is_none());
+
+        let verified = Claims::decode(state, &token).await.unwrap();
+        assert_eq!(
+            verified.expires - verified.issued_at,
+            access_token_lifetime(state)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revoked_token() {
+        let mut config = testing::config();
+        config.tokens.format = TokenFormat::Jwt;
+        let state = &testing::state_with(config).await.unwrap();
+        let client = ClientInfo::default();
+
+        let token = claims().create_token(state).await.unwrap();
+        let other = claims().create_token(state).await.unwrap();
+        assert!(crate::jwt::is_jwt(&token));
+
+        // Revoked by its jti, other tokens of the user keep working
+        let verified = Claims::decode(state, &token).await.unwrap();
+        crate::revocation::revoke_token(state, &verified)
+            .await
+            .unwrap();
+        assert!(matches!(
+            Claims::try_from_tokens(state, &Some(token), &None, &client).await,
+            Err(AuthError::TokenRevoked)
+        ));
+        assert!(
+            Claims::try_from_tokens(state, &Some(other), &None, &client)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expired_token() {
+        let state = &testing::state().await;
+        let meta = TokenMeta {
+            claims: claims(),
+            issued_at: unix_secs() - access_token_lifetime(state),
+            expires: unix_secs() - 1,
+            nonce: rsweb_crypto::generate::generate_id(),
+        };
+        let token = encode_legacy(state, &meta).await.unwrap();
+        assert!(matches!(
+            Claims::try_from_tokens(state, &Some(token), &None, &ClientInfo::default()).await,
+            Err(AuthError::TokenExpired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tampered_expiry() {
+        let state = &testing::state().await;
+        let meta = TokenMeta {
+            claims: claims(),
+            issued_at: unix_secs() - access_token_lifetime(state),
+            expires: unix_secs() - 1,
+            nonce: rsweb_crypto::generate::generate_id(),
+        };
+        let token = encode_legacy(state, &meta).await.unwrap();
+        let forged = tamper_meta(&token, |meta| meta.expires = unix_secs() + 3600);
+        assert!(matches!(
+            Claims::try_from_tokens(state, &Some(forged), &None, &ClientInfo::default()).await,
+            Err(AuthError::InvalidSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_token_of_other_state() {
+        let state = &testing::state().await;
+        let other = &testing::state().await;
+        let token = claims().create_legacy_token(state).await.unwrap();
+        assert!(matches!(
+            Claims::decode(other, &token).await,
+            Err(AuthError::InvalidSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tampered_role() {
+        let state = &testing::state().await;
+        let token = claims().create_legacy_token(state).await.unwrap();
+        let forged = tamper_meta(&token, |meta| meta.claims.role = "admin".to_string());
+        assert!(matches!(
+            Claims::try_from_tokens(state, &Some(forged), &None, &ClientInfo::default()).await,
+            Err(AuthError::InvalidSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tampered_nonce() {
+        let state = &testing::state().await;
+        let token = claims().create_legacy_token(state).await.unwrap();
+        let forged = tamper_meta(&token, |meta| meta.nonce = "00000000".to_string());
+        assert!(matches!(
+            Claims::try_from_tokens(state, &Some(forged), &None, &ClientInfo::default()).await,
+            Err(AuthError::InvalidSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_truncated_digest() {
+        let state = &testing::state().await;
+        let token = claims().create_legacy_token(state).await.unwrap();
+        let mut sig_token = decode_token(&token);
+        // 84 base64 characters decode cleanly to 63 of the 64 signature bytes
+        sig_token.digest.truncate(84);
+        assert!(matches!(
+            Claims::try_from_tokens(
+                state,
+                &Some(encode_token(&sig_token)),
+                &None,
+                &ClientInfo::default()
+            )
+            .await,
+            Err(AuthError::InvalidSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_missing_token() {
+        let state = &testing::state().await;
+        assert!(matches!(
+            Claims::try_from_tokens(state, &None, &None, &ClientInfo::default()).await,
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    async fn insert_user(state: &AppState) -> i32 {
+        UserService::insert_user_email(state, "user@example.com", "", "user")
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_rotate_then_reuse(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let user_id = insert_user(state).await;
+        let client = ClientInfo::default();
+
+        let first = refresh_tokens::create(state, user_id, &client)
+            .await
+            .unwrap();
+        let (claims, (_, second)) = refresh_tokens::rotate(state, &first, &client)
+            .await
+            .unwrap();
+        assert_eq!(claims.uid, user_id);
+        sqlx::query("UPDATE refresh_tokens SET revoked_at = revoked_at - INTERVAL '1 minute'")
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        // Replaying the rotated token after the grace period ends the whole
+        // family
+        assert!(matches!(
+            refresh_tokens::rotate(state, &first, &client).await,
+            Err(AuthError::TokenReused)
+        ));
+        assert!(matches!(
+            refresh_tokens::rotate(state, &second, &client).await,
+            Err(AuthError::TokenReused)
+        ));
+        assert!(
+            refresh_tokens::list_sessions(state, user_id, None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_auth_token_only(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let user_id = insert_user(state).await;
+        let client = ClientInfo::default();
+        let refresh_token = refresh_tokens::create(state, user_id, &client)
+            .await
+            .unwrap();
+
+        let token = claims().create_token(state).await.unwrap();
+        assert_eq!(
+            Claims::from_auth_token(state, &Some(token))
+                .await
+                .unwrap()
+                .uid,
+            42
+        );
+
+        let meta = TokenMeta {
+            claims: claims(),
+            issued_at: unix_secs() - access_token_lifetime(state),
+            expires: unix_secs() - 1,
+            nonce: rsweb_crypto::generate::generate_id(),
+        };
+        let expired = encode_legacy(state, &meta).await.unwrap();
+        assert!(matches!(
+            Claims::from_auth_token(state, &Some(expired)).await,
+            Err(AuthError::TokenExpired)
+        ));
+
+        // The refresh token is left for the client to use
+        assert!(
+            refresh_tokens::rotate(state, &refresh_token, &client)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_rotate_twice(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let user_id = insert_user(state).await;
+        let client = ClientInfo::default();
+        let first = refresh_tokens::create(state, user_id, &client)
+            .await
+            .unwrap();
+
+        // A parallel request with the same token right after the rotation is
+        // turned down, but the session survives
+        let (_, (_, second)) = refresh_tokens::rotate(state, &first, &client)
+            .await
+            .unwrap();
+        assert!(matches!(
+            refresh_tokens::rotate(state, &first, &client).await,
+            Err(AuthError::TokenRevoked)
+        ));
+
+        assert_eq!(
+            refresh_tokens::list_sessions(state, user_id, Some(&second))
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(
+            refresh_tokens::rotate(state, &second, &client)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_missing_auth_token(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let user_id = insert_user(state).await;
+        let client = ClientInfo::default();
+        let refresh_token = refresh_tokens::create(state, user_id, &client)
+            .await
+            .unwrap();
+
+        let (claims, updated_tokens) =
+            Claims::try_from_tokens(state, &None, &Some(refresh_token), &client)
+                .await
+                .unwrap();
+        assert_eq!(claims.uid, user_id);
+        assert!(updated_tokens.is_some());
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_concurrent_rotation(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let user_id = insert_user(state).await;
+        let client = ClientInfo::default();
+        let token = refresh_tokens::create(state, user_id, &client)
+            .await
+            .unwrap();
+
+        let (a, b) = tokio::join!(
+            refresh_tokens::rotate(state, &token, &client),
+            refresh_tokens::rotate(state, &token, &client)
+        );
+        assert_eq!([&a, &b].iter().filter(|result| result.is_ok()).count(), 1);
+        // The losing request never leaves a second live token in the family
+        assert!(
+            refresh_tokens::list_sessions(state, user_id, None)
+                .await
+                .unwrap()
+                .len()
+                <= 1
+        );
+    }
+
+    fn client(user_agent: &str) -> ClientInfo {
+        ClientInfo::new(Some(user_agent.to_string()), "192.0.2.1".parse().ok())
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_list_sessions(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let user_id = insert_user(state).await;
+
+        let laptop = refresh_tokens::create(state, user_id, &client("laptop"))
+            .await
+            .unwrap();
+        refresh_tokens::create(state, user_id, &client("phone"))
+            .await
+            .unwrap();
+
+        let sessions = refresh_tokens::list_sessions(state, user_id, Some(&laptop))
+            .await
+            .unwrap();
+        assert_eq!(sessions.len(), 2);
+        let current: Vec<_> = sessions.iter().filter(|session| session.current).collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].user_agent.as_deref(), Some("laptop"));
+        assert_eq!(current[0].ip_address.as_deref(), Some("192.0.2.1"));
+
+        let sessions = refresh_tokens::list_sessions(state, user_id, None)
+            .await
+            .unwrap();
+        assert!(sessions.iter().all(|session| !session.current));
+        assert!(
+            refresh_tokens::list_sessions(state, user_id + 1, Some(&laptop))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_rotate_updates_session(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let user_id = insert_user(state).await;
+
+        let token = refresh_tokens::create(state, user_id, &client("laptop"))
+            .await
+            .unwrap();
+        sqlx::query("UPDATE refresh_tokens SET last_used_at = last_used_at - INTERVAL '1 day'")
+            .execute(&state.db)
+            .await
+            .unwrap();
+        let before = refresh_tokens::list_sessions(state, user_id, None)
+            .await
+            .unwrap();
+
+        let (_, (_, rotated)) = refresh_tokens::rotate(state, &token, &client("laptop, updated"))
+            .await
+            .unwrap();
+        let after = refresh_tokens::list_sessions(state, user_id, Some(&rotated))
+            .await
+            .unwrap();
+
+        // Still the same session, used just now by the updated client
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].family_id, before[0].family_id);
+        assert_eq!(after[0].signed_in_at, before[0].signed_in_at);
+        assert!(after[0].last_used_at > before[0].last_used_at);
+        assert_eq!(after[0].user_agent.as_deref(), Some("laptop, updated"));
+        assert!(after[0].current);
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_revoke_session(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let user_id = insert_user(state).await;
+
+        let laptop = refresh_tokens::create(state, user_id, &client("laptop"))
+            .await
+            .unwrap();
+        let phone = refresh_tokens::create(state, user_id, &client("phone"))
+            .await
+            .unwrap();
+        let sessions = refresh_tokens::list_sessions(state, user_id, Some(&laptop))
+            .await
+            .unwrap();
+        let phone_session = sessions.iter().find(|session| !session.current).unwrap();
+
+        // Only the owner can end a session
+        assert!(
+            !refresh_tokens::revoke_session(state, user_id + 1, &phone_session.family_id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            refresh_tokens::revoke_session(state, user_id, &phone_session.family_id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !refresh_tokens::revoke_session(state, user_id, &phone_session.family_id)
+                .await
+                .unwrap()
+        );
+
+        // The revoked device can't refresh anymore, the other one can
+        assert!(
+            refresh_tokens::rotate(state, &phone, &client("phone"))
+                .await
+                .is_err()
+        );
+        assert!(
+            refresh_tokens::rotate(state, &laptop, &client("laptop"))
+                .await
+                .is_ok()
+        );
+        let sessions = refresh_tokens::list_sessions(state, user_id, None)
+            .await
+            .unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].user_agent.as_deref(), Some("laptop"));
+    }
+}
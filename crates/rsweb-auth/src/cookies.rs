@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use rsweb_state::AppState;
+use rsweb_utils::format_expiry;
+use warp::http::header::HeaderMap;
+
+// Session cookies shared by the API and the app. Each cookie lives as long
+// as its token, a client whose access token cookie is gone can still refresh.
+
+fn cookie(name: &str, value: &str, max_age: u64) -> warp::http::HeaderValue {
+    format!(
+        "{}={}; HttpOnly; Secure; SameSite=Strict; Path=/; Expires={}; Max-Age={}",
+        name,
+        value,
+        format_expiry(Duration::from_secs(max_age)),
+        max_age
+    )
+    .parse()
+    .unwrap()
+}
+
+pub fn session_cookies(state: &AppState, at: &str, rt: &str) -> HeaderMap {
+    let auth_max_age = crate::claims::access_token_lifetime(state) as u64;
+    let refresh_max_age = crate::claims::refresh_tokens::lifetime(state) as u64;
+
+    let mut cookies = HeaderMap::new();
+    cookies.append("Set-Cookie", cookie("auth_token", at, auth_max_age));
+    cookies.append("Set-Cookie", cookie("refresh_token", rt, refresh_max_age));
+
+    cookies
+}
+
+pub fn clear_session_cookies() -> HeaderMap {
+    let mut cookies = HeaderMap::new();
+    for name in ["auth_token", "refresh_token"] {
+        cookies.append(
+            "Set-Cookie",
+            format!(
+                "{}=; HttpOnly; Secure; SameSite=Strict; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0",
+                name
+            )
+            .parse()
+            .unwrap(),
+        );
+    }
+
+    cookies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsweb_state::testing;
+
+    #[tokio::test]
+    async fn test_session_cookies() {
+        let mut config = testing::config();
+        config.tokens.access_token_lifetime = Duration::from_secs(3 * 60 * 60);
+        let state = &testing::state_with(config).await.unwrap();
+
+        let cookies: Vec<_> = session_cookies(state, "at", "rt")
+            .get_all("Set-Cookie")
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect();
+        assert!(cookies[0].starts_with("auth_token=at;"));
+        assert!(cookies[0].ends_with(&format!("Max-Age={}", 3 * 60 * 60)));
+        assert!(cookies[1].starts_with("refresh_token=rt;"));
+        assert!(cookies[1].ends_with(&format!(
+            "Max-Age={}",
+            crate::claims::refresh_tokens::lifetime(state)
+        )));
+    }
+}
@@ -0,0 +1,142 @@
+use base64::{Engine as _, engine::general_purpose};
+use rsweb_database::user::UserService;
+use rsweb_state::AppState;
+
+use crate::claims::unix_secs;
+use crate::errors::AuthError;
+
+// Lifetime of a verification link in seconds (2 days by default)
+pub fn lifetime(state: &AppState) -> i64 {
+    state.config.tokens.email_verification_lifetime.as_secs() as i64
+}
+
+// Verification links aren't stored, the token is {uid}.{exp}.{email}.{tag}
+// with the email base64url encoded and the tag an HMAC over all three. Binding
+// the address means a link stops working once the user changes their email.
+fn signing_input(user_id: i32, email: &str, expires: i64) -> String {
+    format!("verify-email:{}:{}:{}", user_id, email, expires)
+}
+
+fn create_token(state: &AppState, user_id: i32, email: &str, expires: i64) -> String {
+    let tag = state
+        .hmac
+        .hash(signing_input(user_id, email, expires).as_bytes());
+
+    format!(
+        "{}.{}.{}.{}",
+        user_id,
+        expires,
+        general_purpose::URL_SAFE_NO_PAD.encode(email),
+        tag
+    )
+}
+
+// Checks the tag and expiry, returns the user id and address the link was
+// issued for
+fn parse_token(state: &AppState, token: &str) -> Result<(i32, String), AuthError> {
+    let mut parts = token.split('.');
+    let (Some(user_id), Some(expires), Some(email), Some(tag), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return Err(AuthError::InvalidToken);
+    };
+
+    let user_id: i32 = user_id.parse().map_err(|_| AuthError::InvalidToken)?;
+    let expires: i64 = expires.parse().map_err(|_| AuthError::InvalidToken)?;
+    let email = String::from_utf8(general_purpose::URL_SAFE_NO_PAD.decode(email)?)
+        .map_err(|_| AuthError::InvalidToken)?;
+
+    if !state
+        .hmac
+        .verify(signing_input(user_id, &email, expires).as_bytes(), tag)
+    {
+        return Err(AuthError::InvalidSignature);
+    }
+    if expires < unix_secs() {
+        return Err(AuthError::TokenExpired);
+    }
+
+    Ok((user_id, email))
+}
+
+// Emails a verification link for the address
+pub async fn send(
+    state: &AppState,
+    user_id: i32,
+    email: &str,
+    handle: &str,
+) -> Result<(), AuthError> {
+    let token = create_token(state, user_id, email, unix_secs() + lifetime(state));
+    let link = format!("{}/verify-email/{}", crate::app_url(state), token);
+
+    rsweb_mail::queue::enqueue(
+        &state.cache,
+        rsweb_mail::templates::verify_email(email, handle, &link),
+    )
+    .await?;
+    Ok(())
+}
+
+// Marks the address of the link as verified, returns the user id. Outstanding
+// access tokens still carry the unverified flag, so they are revoked to make
+// the next request pick up fresh claims through the refresh token.
+pub async fn verify(state: &AppState, token: &str) -> Result<i32, AuthError> {
+    let (user_id, email) = parse_token(state, token)?;
+
+    if UserService::mark_email_verified(state, user_id, &email).await? == 0 {
+        return Err(AuthError::InvalidToken);
+    }
+
+    if let Err(e) = crate::revocation::revoke_user_tokens(state, user_id).await {
+        eprintln!("Failed to revoke access tokens of user {}: {}", user_id, e);
+    }
+
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsweb_state::testing;
+
+    #[tokio::test]
+    async fn test_token_roundtrip() {
+        let state = &testing::state().await;
+        let token = create_token(state, 42, "user@example.com", unix_secs() + lifetime(state));
+        let (user_id, email) = parse_token(state, &token).unwrap();
+
+        assert_eq!(user_id, 42);
+        assert_eq!(email, "user@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_expired_token() {
+        let state = &testing::state().await;
+        let token = create_token(state, 42, "user@example.com", unix_secs() - 1);
+
+        assert!(matches!(
+            parse_token(state, &token),
+            Err(AuthError::TokenExpired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tampered_token() {
+        let state = &testing::state().await;
+        let token = create_token(state, 42, "user@example.com", unix_secs() + lifetime(state));
+        let (_, rest) = token.split_once('.').unwrap();
+
+        assert!(matches!(
+            parse_token(state, &format!("43.{}", rest)),
+            Err(AuthError::InvalidSignature)
+        ));
+        assert!(matches!(
+            parse_token(state, "42.0"),
+            Err(AuthError::InvalidToken)
+        ));
+    }
+}
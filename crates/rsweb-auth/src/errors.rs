@@ -5,6 +5,16 @@ pub enum AuthError {
     JsonError(serde_json::Error),
     TokenExpired,
     InvalidSignature,
+    TokenRevoked,
+    TokenReused,
+    InvalidCode,
+    TooManyAttempts,
+    MfaAlreadyEnabled,
+    WebauthnError(String),
+    UnknownProvider,
+    OidcError(google_jwt::GoogleError),
+    CryptoError(rsweb_crypto::errors::CryptoError),
+    MailError(rsweb_mail::errors::MailError),
     StandardError(String),
 }
 
@@ -16,6 +26,16 @@ impl std::fmt::Display for AuthError {
             AuthError::JsonError(e) => e.fmt(f),
             AuthError::TokenExpired => write!(f, "Token expired"),
             AuthError::InvalidSignature => write!(f, "Invalid signature"),
+            AuthError::TokenRevoked => write!(f, "Token revoked"),
+            AuthError::TokenReused => write!(f, "Token reused"),
+            AuthError::InvalidCode => write!(f, "Invalid code"),
+            AuthError::TooManyAttempts => write!(f, "Too many attempts"),
+            AuthError::MfaAlreadyEnabled => write!(f, "Two-factor authentication already enabled"),
+            AuthError::WebauthnError(e) => write!(f, "WebAuthn error: {}", e),
+            AuthError::UnknownProvider => write!(f, "Unknown identity provider"),
+            AuthError::OidcError(e) => e.fmt(f),
+            AuthError::CryptoError(e) => e.fmt(f),
+            AuthError::MailError(e) => e.fmt(f),
             AuthError::StandardError(e) => write!(f, "{}", e),
         }
     }
@@ -35,6 +55,24 @@ impl From<serde_json::Error> for AuthError {
     }
 }
 
+impl From<rsweb_crypto::errors::CryptoError> for AuthError {
+    fn from(e: rsweb_crypto::errors::CryptoError) -> Self {
+        AuthError::CryptoError(e)
+    }
+}
+
+impl From<google_jwt::GoogleError> for AuthError {
+    fn from(e: google_jwt::GoogleError) -> Self {
+        AuthError::OidcError(e)
+    }
+}
+
+impl From<rsweb_mail::errors::MailError> for AuthError {
+    fn from(e: rsweb_mail::errors::MailError) -> Self {
+        AuthError::MailError(e)
+    }
+}
+
 impl From<Box<dyn std::error::Error + Send + Sync>> for AuthError {
     fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
         AuthError::StandardError(e.to_string())
@@ -0,0 +1,338 @@
+use base64::{Engine as _, engine::general_purpose};
+use rsweb_state::AppState;
+use serde::{Deserialize, Serialize};
+
+use crate::claims::{Claims, access_token_lifetime, unix_secs};
+use crate::errors::AuthError;
+
+pub const ALGORITHM: &str = "EdDSA";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    typ: Option<String>,
+    kid: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Payload {
+    pub iss: String,
+    pub aud: String,
+    pub sub: String,
+    pub iat: i64,
+    pub nbf: i64,
+    pub exp: i64,
+    pub jti: String,
+    pub email: String,
+    pub name: String,
+    pub role: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agency_id: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+impl Payload {
+    pub fn claims(&self) -> Result<Claims, AuthError> {
+        let uid = self.sub.parse().map_err(|_| AuthError::InvalidToken)?;
+
+        Ok(Claims {
+            uid,
+            email: self.email.clone(),
+            username: self.name.clone(),
+            role: self.role.clone(),
+            agency_id: self.agency_id,
+            unverified: self.email_verified == Some(false),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Jwk {
+    pub kty: &'static str,
+    pub crv: &'static str,
+    pub alg: &'static str,
+    #[serde(rename = "use")]
+    pub usage: &'static str,
+    pub kid: String,
+    pub x: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+// Public keys of all active and verify-only signing keys as OKP JWKs (RFC 8037)
+pub async fn jwks(state: &AppState) -> JwkSet {
+    let keys = state
+        .keys
+        .verifying_keys()
+        .iter()
+        .map(|key| Jwk {
+            kty: "OKP",
+            crv: "Ed25519",
+            alg: ALGORITHM,
+            usage: "sig",
+            kid: key.kid().to_string(),
+            x: key.public_key_base64(),
+        })
+        .collect();
+
+    JwkSet { keys }
+}
+
+// OpenID Provider Metadata (OpenID Connect Discovery 1.0) of the site as the
+// issuer of the access tokens. The issuer is expected to be the
+// public base URL of the site, so that the document is found at
+// {issuer}/.well-known/openid-configuration.
+#[derive(Debug, Serialize)]
+pub struct OpenIdConfiguration {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub jwks_uri: String,
+    pub response_types_supported: Vec<&'static str>,
+    pub subject_types_supported: Vec<&'static str>,
+    pub id_token_signing_alg_values_supported: Vec<&'static str>,
+    pub claims_supported: Vec<&'static str>,
+}
+
+pub fn openid_configuration(state: &AppState) -> OpenIdConfiguration {
+    let issuer = crate::token_issuer(state);
+
+    OpenIdConfiguration {
+        // Users sign in on the login page of the site
+        authorization_endpoint: format!("{}/login", crate::app_url(state).trim_end_matches('/')),
+        jwks_uri: format!("{}/.well-known/jwks.json", issuer.trim_end_matches('/')),
+        issuer,
+        response_types_supported: vec!["id_token"],
+        subject_types_supported: vec!["public"],
+        id_token_signing_alg_values_supported: vec![ALGORITHM],
+        claims_supported: vec![
+            "iss", "aud", "sub", "iat", "nbf", "exp", "jti", "email", "name", "role",
+        ],
+    }
+}
+
+// JWTs are three dot separated segments, while legacy tokens are a single
+// base64url string which can never contain a dot
+pub fn is_jwt(token: &str) -> bool {
+    token.contains('.')
+}
+
+pub async fn encode(state: &AppState, claims: &Claims) -> Result<String, AuthError> {
+    let key = state.keys.active_key()?;
+    let now = unix_secs();
+
+    let header = Header {
+        alg: ALGORITHM.to_string(),
+        typ: Some("JWT".to_string()),
+        kid: key.kid().to_string(),
+    };
+    let payload = Payload {
+        iss: crate::token_issuer(state),
+        aud: crate::token_audience(state),
+        sub: claims.uid.to_string(),
+        iat: now,
+        nbf: now,
+        exp: now + access_token_lifetime(state),
+        jti: rsweb_crypto::generate::generate_id(),
+        email: claims.email.clone(),
+        name: claims.username.clone(),
+        role: claims.role.clone(),
+        agency_id: claims.agency_id,
+        email_verified: Some(!claims.unverified),
+    };
+
+    let signing_input = format!(
+        "{}.{}",
+        general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+        general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&payload)?)
+    );
+    let signature = key.sign(signing_input.as_bytes())?;
+
+    Ok(format!(
+        "{}.{}",
+        signing_input,
+        general_purpose::URL_SAFE_NO_PAD.encode(signature)
+    ))
+}
+
+// Verifies the signature, algorithm, issuer, audience and not-before time.
+// Expiry is left to the caller, which may still rotate an expired token.
+pub async fn decode(state: &AppState, token: &str) -> Result<Payload, AuthError> {
+    let mut segments = token.split('.');
+    let (encoded_header, encoded_payload, encoded_signature) = match (
+        segments.next(),
+        segments.next(),
+        segments.next(),
+        segments.next(),
+    ) {
+        (Some(h), Some(p), Some(s), None) => (h, p, s),
+        _ => return Err(AuthError::InvalidToken),
+    };
+
+    let header: Header =
+        serde_json::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(encoded_header)?)?;
+    if header.alg != ALGORITHM {
+        return Err(AuthError::InvalidToken);
+    }
+
+    // The signature covers the header and payload segments exactly as received
+    let signing_input = &token[..encoded_header.len() + 1 + encoded_payload.len()];
+    let signature = general_purpose::URL_SAFE_NO_PAD.decode(encoded_signature)?;
+    if !state
+        .keys
+        .verify_signature(&header.kid, signing_input.as_bytes(), &signature)
+        .await
+    {
+        return Err(AuthError::InvalidSignature);
+    }
+
+    let payload: Payload =
+        serde_json::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(encoded_payload)?)?;
+    if payload.iss != crate::token_issuer(state) || payload.aud != crate::token_audience(state) {
+        return Err(AuthError::InvalidToken);
+    }
+    if payload.nbf > unix_secs() {
+        return Err(AuthError::InvalidToken);
+    }
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsweb_state::testing;
+
+    fn claims() -> Claims {
+        Claims {
+            uid: 42,
+            email: "user@example.com".to_string(),
+            username: "user".to_string(),
+            role: "user".to_string(),
+            agency_id: None,
+            unverified: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_encode_decode() {
+        let state = &testing::state().await;
+        let token = encode(state, &claims()).await.unwrap();
+        assert!(is_jwt(&token));
+
+        let payload = decode(state, &token).await.unwrap();
+        assert_eq!(payload.sub, "42");
+        assert_eq!(payload.exp - payload.iat, access_token_lifetime(state));
+        assert_eq!(payload.claims().unwrap().username, "user");
+    }
+
+    #[tokio::test]
+    async fn test_unverified_roundtrip() {
+        let state = &testing::state().await;
+        let claims = Claims {
+            unverified: true,
+            ..claims()
+        };
+        let payload = decode(state, &encode(state, &claims).await.unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(payload.email_verified, Some(false));
+        assert!(payload.claims().unwrap().unverified);
+    }
+
+    #[tokio::test]
+    async fn test_jwks_contains_active_key() {
+        let state = &testing::state().await;
+        let token = encode(state, &claims()).await.unwrap();
+        let (encoded_header, _) = token.split_once('.').unwrap();
+        let header: Header = serde_json::from_slice(
+            &general_purpose::URL_SAFE_NO_PAD
+                .decode(encoded_header)
+                .unwrap(),
+        )
+        .unwrap();
+
+        let jwks = jwks(state).await;
+        assert!(jwks.keys.iter().any(|jwk| jwk.kid == header.kid));
+    }
+
+    #[tokio::test]
+    async fn test_openid_configuration() {
+        let state = &testing::state().await;
+        let document = serde_json::to_value(openid_configuration(state)).unwrap();
+
+        // REQUIRED by OpenID Connect Discovery 1.0, section 3, token_endpoint
+        // only for providers that support more than the implicit flow
+        for field in [
+            "issuer",
+            "authorization_endpoint",
+            "jwks_uri",
+            "response_types_supported",
+            "subject_types_supported",
+            "id_token_signing_alg_values_supported",
+        ] {
+            let value = &document[field];
+            assert!(
+                value.as_str().is_some_and(|value| !value.is_empty())
+                    || value.as_array().is_some_and(|values| !values.is_empty()),
+                "missing {}",
+                field
+            );
+        }
+        assert_eq!(document["issuer"], crate::token_issuer(state));
+        assert!(
+            document["jwks_uri"]
+                .as_str()
+                .unwrap()
+                .starts_with(&crate::token_issuer(state))
+        );
+        assert_eq!(
+            document["id_token_signing_alg_values_supported"][0],
+            ALGORITHM
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_tampered_payload() {
+        let state = &testing::state().await;
+        let token = encode(state, &claims()).await.unwrap();
+        let segments: Vec<&str> = token.split('.').collect();
+
+        let mut payload: Payload = serde_json::from_slice(
+            &general_purpose::URL_SAFE_NO_PAD
+                .decode(segments[1])
+                .unwrap(),
+        )
+        .unwrap();
+        payload.role = "admin".to_string();
+        let forged = format!(
+            "{}.{}.{}",
+            segments[0],
+            general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&payload).unwrap()),
+            segments[2]
+        );
+
+        assert!(matches!(
+            decode(state, &forged).await,
+            Err(AuthError::InvalidSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_decode_rejects_other_algorithms() {
+        let state = &testing::state().await;
+        let token = encode(state, &claims()).await.unwrap();
+        let (_, rest) = token.split_once('.').unwrap();
+        let header = general_purpose::URL_SAFE_NO_PAD.encode(br#"{"alg":"none","kid":"x"}"#);
+
+        assert!(matches!(
+            decode(state, &format!("{}.{}", header, rest)).await,
+            Err(AuthError::InvalidToken)
+        ));
+    }
+}
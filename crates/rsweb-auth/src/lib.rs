@@ -1,10 +1,38 @@
 pub mod claims;
+pub mod cookies;
+pub mod email_verification;
 pub mod errors;
+pub mod jwt;
+pub mod mfa;
+pub mod oauth;
+pub mod oidc;
+pub mod password_reset;
+pub mod revocation;
+pub mod webauthn;
 
-pub fn google_client_id() -> Option<String> {
-    let value = std::env::var("GOOGLE_OAUTH_CLIENT_ID");
-    match value {
-        Ok(value) => Some(value),
-        Err(_) => None,
-    }
+use rsweb_state::AppState;
+
+pub use rsweb_config::TokenFormat;
+
+// Google sign in is off without a client id
+pub fn google_client_id(state: &AppState) -> Option<String> {
+    state.config.google.client_id.clone()
+}
+
+// Format of newly issued access tokens, both formats are always accepted
+pub fn token_format(state: &AppState) -> TokenFormat {
+    state.config.tokens.format
+}
+
+pub fn token_issuer(state: &AppState) -> String {
+    state.config.tokens.issuer.clone()
+}
+
+pub fn token_audience(state: &AppState) -> String {
+    state.config.tokens.audience.clone()
+}
+
+// Public base URL of the site, used for links in outgoing mail
+pub fn app_url(state: &AppState) -> String {
+    state.config.app_url.clone()
 }
@@ -0,0 +1,317 @@
+use rsweb_crypto::totp;
+use rsweb_database::user::{UserEssentials, UserService};
+use rsweb_state::AppState;
+use serde::{Deserialize, Serialize};
+
+use crate::claims::unix_secs;
+use crate::errors::AuthError;
+
+// Lifetime of a pending second factor challenge in seconds (5 minutes)
+pub const CHALLENGE_LIFETIME: u64 = 5 * 60;
+// Wrong codes allowed per challenge before the user has to sign in again
+const MAX_ATTEMPTS: i64 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+// The user a challenge was issued for, kept in the cache until it is
+// completed so the second step doesn't need to look the user up again
+#[derive(Debug, Serialize, Deserialize)]
+struct Challenge {
+    id: i32,
+    email: String,
+    handle: String,
+    role: String,
+    email_verified: bool,
+}
+
+#[derive(Debug)]
+pub struct Enrolment {
+    pub secret: String,
+    pub uri: String,
+}
+
+pub async fn is_enabled(state: &AppState, user_id: i32) -> Result<bool, AuthError> {
+    let totp = UserService::get_user_totp(state, user_id).await?;
+    Ok(totp.is_some_and(|totp| totp.confirmed_at.is_some()))
+}
+
+// The secret from begin_enrolment that wasn't confirmed yet, if any
+pub async fn pending_enrolment(
+    state: &AppState,
+    user_id: i32,
+    email: &str,
+) -> Result<Option<Enrolment>, AuthError> {
+    Ok(match UserService::get_user_totp(state, user_id).await? {
+        Some(totp) if totp.confirmed_at.is_none() => Some(Enrolment {
+            uri: totp::otpauth_uri(&crate::token_issuer(state), email, &totp.secret),
+            secret: totp.secret,
+        }),
+        _ => None,
+    })
+}
+
+// Generates a new secret for the user to add to their authenticator app, it
+// only protects the login once confirm_enrolment accepted a first code
+pub async fn begin_enrolment(
+    state: &AppState,
+    user_id: i32,
+    email: &str,
+) -> Result<Enrolment, AuthError> {
+    let secret = totp::generate_secret();
+    if UserService::upsert_user_totp_secret(state, user_id, &secret).await? == 0 {
+        return Err(AuthError::MfaAlreadyEnabled);
+    }
+
+    Ok(Enrolment {
+        uri: totp::otpauth_uri(&crate::token_issuer(state), email, &secret),
+        secret,
+    })
+}
+
+// Turns on two-factor authentication once the code matches the pending
+// secret, returns the recovery codes which are only ever shown this once
+pub async fn confirm_enrolment(
+    state: &AppState,
+    user_id: i32,
+    code: &str,
+) -> Result<Vec<String>, AuthError> {
+    let pending = match UserService::get_user_totp(state, user_id).await? {
+        Some(totp) if totp.confirmed_at.is_none() => totp,
+        Some(_) => return Err(AuthError::MfaAlreadyEnabled),
+        None => return Err(AuthError::InvalidCode),
+    };
+    let step = totp::verify(&pending.secret, code, unix_secs()).ok_or(AuthError::InvalidCode)?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let mut hashes = Vec::with_capacity(codes.len());
+    for code in &codes {
+        hashes.push(hash_recovery_code(state, code));
+    }
+
+    if !UserService::confirm_user_totp(state, user_id, step, &hashes).await? {
+        return Err(AuthError::InvalidCode);
+    }
+    Ok(codes)
+}
+
+// Turns two-factor authentication off, which takes a current code
+pub async fn disable(state: &AppState, user_id: i32, code: &str) -> Result<(), AuthError> {
+    if !check_code(state, user_id, code).await? {
+        return Err(AuthError::InvalidCode);
+    }
+
+    UserService::delete_user_totp(state, user_id).await?;
+    Ok(())
+}
+
+pub async fn remaining_recovery_codes(state: &AppState, user_id: i32) -> Result<i64, AuthError> {
+    Ok(UserService::count_unused_recovery_codes(state, user_id).await?)
+}
+
+// Starts the second login step for a user whose first factor checked out,
+// the returned token is exchanged for a session by complete_challenge
+pub async fn create_challenge(
+    state: &AppState,
+    user: &UserEssentials,
+) -> Result<String, AuthError> {
+    let token = rsweb_crypto::generate::generate_random_string(32);
+    let challenge = Challenge {
+        id: user.id,
+        email: user.email.clone(),
+        handle: user.handle.clone(),
+        role: user.role.clone(),
+        email_verified: user.email_verified,
+    };
+
+    state
+        .challenges
+        .store(
+            &challenge_id(state, &token),
+            &serde_json::to_string(&challenge)?,
+            CHALLENGE_LIFETIME,
+        )
+        .await?;
+    Ok(token)
+}
+
+// Checks a TOTP or recovery code against the pending challenge. The challenge
+// is single-use and dropped after too many wrong codes.
+pub async fn complete_challenge(
+    state: &AppState,
+    token: &str,
+    code: &str,
+) -> Result<UserEssentials, AuthError> {
+    let id = challenge_id(state, token);
+    let challenge: Challenge = match state.challenges.get(&id).await? {
+        Some(payload) => serde_json::from_str(&payload)?,
+        None => return Err(AuthError::InvalidToken),
+    };
+
+    // Counted before the code is checked, so concurrent guesses can't get
+    // past the limit
+    let attempts = state
+        .challenges
+        .record_attempt(&id, CHALLENGE_LIFETIME)
+        .await?;
+    if attempts > MAX_ATTEMPTS {
+        state.challenges.remove(&id).await?;
+        return Err(AuthError::TooManyAttempts);
+    }
+
+    if !check_code(state, challenge.id, code).await? {
+        if attempts == MAX_ATTEMPTS {
+            state.challenges.remove(&id).await?;
+            return Err(AuthError::TooManyAttempts);
+        }
+        return Err(AuthError::InvalidCode);
+    }
+
+    if !state.challenges.remove(&id).await? {
+        return Err(AuthError::InvalidToken);
+    }
+
+    Ok(UserEssentials {
+        id: challenge.id,
+        email: challenge.email,
+        handle: challenge.handle,
+        role: challenge.role,
+        email_verified: challenge.email_verified,
+    })
+}
+
+// Six digits are a TOTP code, anything else is tried as a recovery code.
+// Either is accepted only once.
+async fn check_code(state: &AppState, user_id: i32, code: &str) -> Result<bool, AuthError> {
+    let code = code.trim();
+
+    if code.len() == totp::DIGITS && code.bytes().all(|b| b.is_ascii_digit()) {
+        let secret = match UserService::get_user_totp(state, user_id).await? {
+            Some(totp) if totp.confirmed_at.is_some() => totp.secret,
+            _ => return Ok(false),
+        };
+        return match totp::verify(&secret, code, unix_secs()) {
+            Some(step) => Ok(UserService::use_user_totp_step(state, user_id, step).await? > 0),
+            None => Ok(false),
+        };
+    }
+
+    let code_hash = hash_recovery_code(state, code);
+    Ok(UserService::use_recovery_code(state, user_id, &code_hash).await? > 0)
+}
+
+fn challenge_id(state: &AppState, token: &str) -> String {
+    state
+        .hmac
+        .hash(format!("mfa-challenge:{}", token).as_bytes())
+}
+
+// Lowercase groups like "k3x9q-7bm2d" that are easy to write down
+fn generate_recovery_code() -> String {
+    let code = rsweb_crypto::generate::generate_random_string(RECOVERY_CODE_LENGTH).to_lowercase();
+    let (a, b) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+    format!("{}-{}", a, b)
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn hash_recovery_code(state: &AppState, code: &str) -> String {
+    state.hmac.hash(normalize_recovery_code(code).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsweb_state::testing;
+    use sqlx::PgPool;
+
+    // A user with two-factor authentication on, returns the recovery codes
+    async fn enrolled_user(state: &AppState) -> (UserEssentials, Vec<String>) {
+        let id = UserService::insert_user_email(state, "user@example.com", "", "user")
+            .await
+            .unwrap();
+        let enrolment = begin_enrolment(state, id, "user@example.com")
+            .await
+            .unwrap();
+        let code = totp::code_at(&enrolment.secret, unix_secs()).unwrap();
+        let recovery_codes = confirm_enrolment(state, id, &code).await.unwrap();
+
+        let user = UserEssentials {
+            id,
+            email: "user@example.com".to_string(),
+            handle: "user".to_string(),
+            role: "user".to_string(),
+            email_verified: false,
+        };
+        (user, recovery_codes)
+    }
+
+    #[test]
+    fn test_recovery_code_format() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), RECOVERY_CODE_LENGTH + 1);
+        assert_eq!(code.as_bytes()[RECOVERY_CODE_LENGTH / 2], b'-');
+        assert_eq!(code, code.to_lowercase());
+    }
+
+    #[tokio::test]
+    async fn test_recovery_code_normalized() {
+        let state = &testing::state().await;
+        assert_eq!(
+            hash_recovery_code(state, "ABCDE-12345"),
+            hash_recovery_code(state, " abcde12345 ")
+        );
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_complete_challenge(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let (user, recovery_codes) = enrolled_user(state).await;
+        let token = create_challenge(state, &user).await.unwrap();
+
+        assert!(matches!(
+            complete_challenge(state, &token, "wrong-code").await,
+            Err(AuthError::InvalidCode)
+        ));
+        let essentials = complete_challenge(state, &token, &recovery_codes[0])
+            .await
+            .unwrap();
+        assert_eq!(essentials.id, user.id);
+
+        // Single-use, and so is the recovery code
+        assert!(matches!(
+            complete_challenge(state, &token, &recovery_codes[1]).await,
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_concurrent_guesses_are_limited(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let (user, recovery_codes) = enrolled_user(state).await;
+        let token = create_challenge(state, &user).await.unwrap();
+
+        let results = futures::future::join_all(
+            (0..20).map(|_| complete_challenge(state, &token, "wrong-code")),
+        )
+        .await;
+        let checked = results
+            .iter()
+            .filter(|result| matches!(result, Err(AuthError::InvalidCode)))
+            .count();
+        assert_eq!(checked as i64, MAX_ATTEMPTS - 1);
+
+        // The challenge is gone, even with a valid code
+        assert!(
+            complete_challenge(state, &token, &recovery_codes[0])
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use async_trait::async_trait;
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use google_jwt::{IdPayload, ProviderConfig, Token};
+use rsweb_state::AppState;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AuthError;
+use crate::oidc;
+
+// Server side OAuth2 authorization code flow with PKCE (RFC 7636). The state,
+// code verifier and nonce of an authorization request wait in the cache until
+// the provider redirects back, or they expire.
+
+// Lifetime of a pending authorization request in seconds (10 minutes)
+pub const STATE_LIFETIME: u64 = 10 * 60;
+const STATE_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 32;
+// RFC 7636 allows 43 to 128 characters
+const CODE_VERIFIER_LENGTH: usize = 64;
+
+// The token request is the only call the flow makes to the provider, taking
+// it behind a trait lets tests answer it in process
+#[async_trait]
+pub trait HttpClient: Send + Sync {
+    // Posts an application/x-www-form-urlencoded body, returns the status
+    // code and response body
+    async fn post_form(&self, url: &str, form: &[(&str, &str)])
+    -> Result<(u16, String), AuthError>;
+}
+
+#[derive(Default)]
+pub struct ReqwestClient {
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl HttpClient for ReqwestClient {
+    async fn post_form(
+        &self,
+        url: &str,
+        form: &[(&str, &str)],
+    ) -> Result<(u16, String), AuthError> {
+        let response = self
+            .client
+            .post(url)
+            .form(form)
+            .send()
+            .await
+            .map_err(|e| AuthError::StandardError(e.to_string()))?;
+        let status = response.status().as_u16();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| AuthError::StandardError(e.to_string()))?;
+
+        Ok((status, body))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingAuthorization {
+    provider: String,
+    code_verifier: String,
+    nonce: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+// Where to send the browser, the state has to come back with the callback
+// from the same browser
+pub struct Authorization {
+    pub url: String,
+    pub state: String,
+}
+
+pub fn redirect_uri(app_state: &AppState, provider: &str) -> String {
+    format!("{}/auth/{}/callback", crate::app_url(app_state), provider)
+}
+
+// S256 code challenge of a verifier
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(ring::digest::digest(
+        &ring::digest::SHA256,
+        code_verifier.as_bytes(),
+    ))
+}
+
+// The client id of the code flow is the first configured audience
+fn client_id(config: &ProviderConfig) -> Result<&str, AuthError> {
+    config
+        .audiences
+        .first()
+        .map(String::as_str)
+        .ok_or(AuthError::UnknownProvider)
+}
+
+fn state_id(app_state: &AppState, state: &str) -> String {
+    app_state
+        .hmac
+        .hash(format!("oauth-state:{}", state).as_bytes())
+}
+
+pub fn authorization_url(
+    app_state: &AppState,
+    config: &ProviderConfig,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+) -> Result<String, AuthError> {
+    let endpoint = config
+        .authorization_endpoint
+        .as_deref()
+        .ok_or(AuthError::UnknownProvider)?;
+
+    let query = [
+        ("response_type", "code"),
+        ("client_id", client_id(config)?),
+        ("redirect_uri", &redirect_uri(app_state, &config.name)),
+        ("scope", "openid email profile"),
+        ("state", state),
+        ("nonce", nonce),
+        ("code_challenge", &code_challenge(code_verifier)),
+        ("code_challenge_method", "S256"),
+    ]
+    .iter()
+    .map(|(key, value)| format!("{}={}", key, percent_encode(value)))
+    .collect::<Vec<_>>()
+    .join("&");
+
+    let separator = if endpoint.contains('?') { '&' } else { '?' };
+    Ok(format!("{}{}{}", endpoint, separator, query))
+}
+
+pub async fn start(app_state: &AppState, provider: &str) -> Result<Authorization, AuthError> {
+    let config = oidc::provider(app_state, provider).await?;
+
+    let state = rsweb_crypto::generate::generate_random_string(STATE_LENGTH);
+    let pending = PendingAuthorization {
+        provider: config.name.clone(),
+        code_verifier: rsweb_crypto::generate::generate_random_string(CODE_VERIFIER_LENGTH),
+        nonce: rsweb_crypto::generate::generate_random_string(NONCE_LENGTH),
+    };
+    let url = authorization_url(
+        app_state,
+        &config,
+        &state,
+        &pending.nonce,
+        &pending.code_verifier,
+    )?;
+
+    app_state
+        .challenges
+        .store(
+            &state_id(app_state, &state),
+            &serde_json::to_string(&pending)?,
+            STATE_LIFETIME,
+        )
+        .await?;
+
+    Ok(Authorization { url, state })
+}
+
+// Redeems the authorization code at the token endpoint, returns the ID token
+pub async fn exchange_code(
+    app_state: &AppState,
+    http: &dyn HttpClient,
+    config: &ProviderConfig,
+    client_secret: Option<&str>,
+    code: &str,
+    code_verifier: &str,
+) -> Result<String, AuthError> {
+    let endpoint = config
+        .token_endpoint
+        .as_deref()
+        .ok_or(AuthError::UnknownProvider)?;
+    let redirect_uri = redirect_uri(app_state, &config.name);
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &redirect_uri),
+        ("client_id", client_id(config)?),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(client_secret) = client_secret {
+        form.push(("client_secret", client_secret));
+    }
+
+    let (status, body) = http.post_form(endpoint, &form).await?;
+    if !(200..300).contains(&status) {
+        return Err(AuthError::StandardError(format!(
+            "token endpoint answered {}",
+            status
+        )));
+    }
+
+    serde_json::from_str::<TokenResponse>(&body)?
+        .id_token
+        .ok_or(AuthError::InvalidToken)
+}
+
+// Completes the flow for the callback, the state is single-use and has to
+// belong to the provider the callback came in for
+pub async fn finish(
+    app_state: &AppState,
+    http: &dyn HttpClient,
+    provider: &str,
+    code: &str,
+    state: &str,
+) -> Result<Token<IdPayload>, AuthError> {
+    let id = state_id(app_state, state);
+    let pending: PendingAuthorization = match app_state.challenges.get(&id).await? {
+        Some(payload) => serde_json::from_str(&payload)?,
+        None => return Err(AuthError::InvalidToken),
+    };
+    if !app_state.challenges.remove(&id).await? || pending.provider != provider {
+        return Err(AuthError::InvalidToken);
+    }
+
+    let config = oidc::provider(app_state, provider).await?;
+    let client_secret = oidc::client_secret(app_state, provider);
+    let id_token = exchange_code(
+        app_state,
+        http,
+        &config,
+        client_secret.as_deref(),
+        code,
+        &pending.code_verifier,
+    )
+    .await?;
+
+    let token = oidc::verify_id_token(app_state, provider, &id_token).await?;
+    if token.payload.nonce.as_deref() != Some(pending.nonce.as_str()) {
+        return Err(AuthError::InvalidToken);
+    }
+
+    Ok(token)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsweb_state::testing;
+    use std::sync::Mutex;
+
+    // URL and form fields of a token request
+    type TokenRequest = (String, Vec<(String, String)>);
+
+    // Token endpoint of a mock provider, remembers the last request
+    struct MockIdp {
+        status: u16,
+        body: String,
+        request: Mutex<Option<TokenRequest>>,
+    }
+
+    impl MockIdp {
+        fn new(status: u16, body: &str) -> Self {
+            Self {
+                status,
+                body: body.to_string(),
+                request: Mutex::new(None),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl HttpClient for MockIdp {
+        async fn post_form(
+            &self,
+            url: &str,
+            form: &[(&str, &str)],
+        ) -> Result<(u16, String), AuthError> {
+            let form = form
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            *self.request.lock().unwrap() = Some((url.to_string(), form));
+            Ok((self.status, self.body.clone()))
+        }
+    }
+
+    fn config() -> ProviderConfig {
+        ProviderConfig::new(
+            "keycloak",
+            "https://sso.example.com/realms/main",
+            "https://sso.example.com/realms/main/certs",
+            vec!["web".to_string()],
+        )
+        .with_endpoints(
+            "https://sso.example.com/realms/main/auth?kc_idp_hint=x",
+            "https://sso.example.com/realms/main/token",
+        )
+    }
+
+    #[test]
+    fn test_code_challenge() {
+        // RFC 7636 appendix B
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authorization_url() {
+        let app_state = &testing::state().await;
+        let url = authorization_url(app_state, &config(), "state1", "nonce1", "verifier").unwrap();
+        assert!(url.starts_with("https://sso.example.com/realms/main/auth?kc_idp_hint=x&"));
+        assert!(url.contains("&client_id=web&"));
+        assert!(url.contains("&state=state1&nonce=nonce1&"));
+        assert!(url.contains(&format!("&code_challenge={}&", code_challenge("verifier"))));
+        assert!(url.contains("redirect_uri=http%3A%2F%2F"));
+        assert!(url.ends_with("&code_challenge_method=S256"));
+    }
+
+    #[tokio::test]
+    async fn test_exchange_code() {
+        let app_state = &testing::state().await;
+        let idp = MockIdp::new(
+            200,
+            r#"{"access_token":"a","id_token":"header.payload.sig"}"#,
+        );
+        let id_token = exchange_code(
+            app_state,
+            &idp,
+            &config(),
+            Some("secret"),
+            "code1",
+            "verifier1",
+        )
+        .await
+        .unwrap();
+        assert_eq!(id_token, "header.payload.sig");
+
+        let (url, form) = idp.request.lock().unwrap().clone().unwrap();
+        assert_eq!(url, "https://sso.example.com/realms/main/token");
+        let field = |name: &str| {
+            form.iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(field("grant_type"), Some("authorization_code"));
+        assert_eq!(field("code"), Some("code1"));
+        assert_eq!(field("code_verifier"), Some("verifier1"));
+        assert_eq!(field("client_secret"), Some("secret"));
+
+        // Errors from the provider and answers without an ID token
+        let idp = MockIdp::new(400, r#"{"error":"invalid_grant"}"#);
+        assert!(
+            exchange_code(app_state, &idp, &config(), None, "code1", "verifier1")
+                .await
+                .is_err()
+        );
+        let idp = MockIdp::new(200, r#"{"access_token":"a"}"#);
+        assert!(
+            exchange_code(app_state, &idp, &config(), None, "code1", "verifier1")
+                .await
+                .is_err()
+        );
+    }
+}
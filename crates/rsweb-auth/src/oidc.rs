@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use google_jwt::{ClientAsync, IdPayload, ProviderConfig, Token};
+use rsweb_state::AppState;
+
+use crate::errors::AuthError;
+
+// OpenID Connect providers users can sign in with. Google is built in and
+// enabled by its client id. Other providers (Microsoft Entra, Apple,
+// Keycloak, ...) come from the [oidc.<name>] tables of the config or
+// OIDC_PROVIDERS, checked by rsweb-config at startup. Their verifiers live in
+// the AppState.
+pub use rsweb_state::oidc::GOOGLE;
+
+// Secret for the authorization code flow, public clients go without
+pub fn client_secret(state: &AppState, name: &str) -> Option<String> {
+    let config = &state.config;
+    match name {
+        GOOGLE => config.google.client_secret.clone(),
+        _ => config
+            .oidc_provider(name)
+            .and_then(|provider| provider.client_secret.clone()),
+    }
+}
+
+// Names of the providers configured besides Google
+pub fn provider_names(state: &AppState) -> Vec<String> {
+    state
+        .config
+        .oidc
+        .iter()
+        .map(|provider| provider.name.clone())
+        .collect()
+}
+
+// Discovers the providers and loads their keys, so logins don't wait for it.
+// Failures are only reported, the next login tries again.
+pub async fn prefetch_keys(state: AppState) {
+    for name in state.oidc.names() {
+        let result = match client(&state, name).await {
+            Ok(client) => client.prefetch_keys_async().await.map_err(AuthError::from),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to load keys of identity provider {}: {}", name, e);
+        }
+    }
+}
+
+async fn client(state: &AppState, name: &str) -> Result<Arc<ClientAsync>, AuthError> {
+    state
+        .oidc
+        .get(name)
+        .await?
+        .ok_or(AuthError::UnknownProvider)
+}
+
+pub async fn provider(state: &AppState, name: &str) -> Result<ProviderConfig, AuthError> {
+    Ok(client(state, name).await?.provider().clone())
+}
+
+// Checks an ID token issued by the named provider, the subject identifies the
+// account together with the provider name
+pub async fn verify_id_token(
+    state: &AppState,
+    provider_name: &str,
+    id_token: &str,
+) -> Result<Token<IdPayload>, AuthError> {
+    let client = client(state, provider_name).await?;
+    Ok(client.verify_id_token_async(id_token).await?)
+}
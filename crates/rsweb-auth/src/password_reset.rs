@@ -0,0 +1,76 @@
+use rsweb_database::user::UserService;
+use rsweb_state::AppState;
+
+use crate::errors::AuthError;
+
+// Lifetime of a password reset link in seconds (1 hour by default)
+pub fn lifetime(state: &AppState) -> i32 {
+    state.config.tokens.password_reset_lifetime.as_secs() as i32
+}
+
+// Emails a reset link if the address belongs to an account with a password.
+// Unknown addresses succeed silently so the endpoint can't be used to probe
+// for accounts.
+pub async fn request(state: &AppState, email: &str) -> Result<(), AuthError> {
+    let details = match UserService::get_user_details(state, email).await {
+        Ok(details) => details,
+        Err(_) => return Ok(()),
+    };
+    if details.banned || details.password.is_none() {
+        return Ok(());
+    }
+
+    let token = rsweb_crypto::generate::generate_random_string(32);
+    let token_hash = state.hmac.hash(token.as_bytes());
+    UserService::insert_password_reset(state, details.id, &token_hash, lifetime(state)).await?;
+
+    let link = format!("{}/reset/{}", crate::app_url(state), token);
+    rsweb_mail::queue::enqueue(
+        &state.cache,
+        rsweb_mail::templates::password_reset(&details.email, &details.handle, &link),
+    )
+    .await?;
+
+    Ok(())
+}
+
+// Whether the token can still be redeemed, so the reset page can say so
+// before the user types a new password
+pub async fn is_valid(state: &AppState, token: &str) -> Result<bool, AuthError> {
+    let token_hash = state.hmac.hash(token.as_bytes());
+    Ok(UserService::password_reset_exists(state, &token_hash).await?)
+}
+
+// Redeems the token and sets the new password. All sessions of the user are
+// ended, including outstanding access tokens, and the user is notified.
+pub async fn reset(state: &AppState, token: &str, password: String) -> Result<i32, AuthError> {
+    let token_hash = state.hmac.hash(token.as_bytes());
+    let hash = state.hasher.hash_password_async(password).await?;
+
+    let user = match UserService::reset_user_password(state, &token_hash, &hash).await? {
+        Some(user) => user,
+        None => return Err(AuthError::InvalidToken),
+    };
+
+    if let Err(e) = crate::revocation::revoke_user_tokens(state, user.id).await {
+        eprintln!("Failed to revoke access tokens of user {}: {}", user.id, e);
+    }
+    if let Err(e) = rsweb_mail::queue::enqueue(
+        &state.cache,
+        rsweb_mail::templates::password_changed(&user.email, &user.handle),
+    )
+    .await
+    {
+        eprintln!(
+            "Failed to queue password change notice for user {}: {}",
+            user.id, e
+        );
+    }
+
+    Ok(user.id)
+}
+
+// Deletes expired reset tokens, returns the number of purged rows
+pub async fn purge_expired(state: &AppState) -> Result<u64, AuthError> {
+    Ok(UserService::delete_expired_password_resets(state).await?)
+}
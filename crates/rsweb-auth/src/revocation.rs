@@ -0,0 +1,187 @@
+use rsweb_state::AppState;
+
+use crate::claims::{VerifiedToken, access_token_lifetime, unix_secs};
+use crate::errors::AuthError;
+
+// Revokes a single access token until it would have expired anyway
+pub async fn revoke_token(state: &AppState, token: &VerifiedToken) -> Result<(), AuthError> {
+    let ttl = (token.expires - unix_secs()).max(0) as u64;
+    state.revocations.revoke_token(&token.id, ttl).await?;
+    Ok(())
+}
+
+// Revokes every access token issued to the user so far, including this
+// second. Tokens issued later (e.g. through a refresh) are unaffected.
+pub async fn revoke_user_tokens(state: &AppState, user_id: i32) -> Result<(), AuthError> {
+    state
+        .revocations
+        .revoke_user_tokens(user_id, unix_secs(), access_token_lifetime(state) as u64)
+        .await?;
+    Ok(())
+}
+
+// A cache outage should not lock every user out, so by default lookups fail
+// open: a token revoked during the outage works until it expires. Failing
+// closed counts every token as revoked instead, which replaces it through
+// the refresh token (checked in the database) on each request.
+pub async fn is_revoked(state: &AppState, token: &VerifiedToken) -> bool {
+    match state
+        .revocations
+        .is_revoked(&token.id, token.claims.uid, token.issued_at)
+        .await
+    {
+        Ok(revoked) => revoked,
+        Err(e) => {
+            eprintln!("Failed to check token revocation: {}", e);
+            !state.config.tokens.revocation_fail_open
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use rsweb_cache::revocation::RevocationStore;
+    use rsweb_database::user::UserService;
+    use rsweb_state::testing;
+    use sqlx::PgPool;
+    use std::sync::Arc;
+
+    use crate::claims::{Claims, ClientInfo, refresh_tokens};
+
+    fn token(user_id: i32, issued_at: i64) -> VerifiedToken {
+        VerifiedToken {
+            claims: Claims {
+                uid: user_id,
+                email: "user@example.com".to_string(),
+                username: "user".to_string(),
+                role: "user".to_string(),
+                agency_id: None,
+                unverified: false,
+            },
+            id: rsweb_crypto::generate::generate_id(),
+            issued_at,
+            expires: issued_at + 3600,
+        }
+    }
+
+    // The cache being down
+    struct UnavailableStore;
+
+    #[async_trait]
+    impl RevocationStore for UnavailableStore {
+        async fn revoke_token(
+            &self,
+            _token_id: &str,
+            _ttl_secs: u64,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            Err("connection refused".into())
+        }
+
+        async fn revoke_user_tokens(
+            &self,
+            _user_id: i32,
+            _issued_until: i64,
+            _ttl_secs: u64,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            Err("connection refused".into())
+        }
+
+        async fn is_revoked(
+            &self,
+            _token_id: &str,
+            _user_id: i32,
+            _issued_at: i64,
+        ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+            Err("connection refused".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_revoke_user_tokens() {
+        let state = &testing::state().await;
+        let earlier = token(1, unix_secs() - 10);
+        let same_second = token(1, unix_secs());
+        let later = token(1, unix_secs() + 10);
+        let other_user = token(2, unix_secs() - 10);
+
+        revoke_user_tokens(state, 1).await.unwrap();
+        assert!(is_revoked(state, &earlier).await);
+        assert!(is_revoked(state, &same_second).await);
+        // Tokens issued later, e.g. through a refresh, are unaffected
+        assert!(!is_revoked(state, &later).await);
+        assert!(!is_revoked(state, &other_user).await);
+    }
+
+    #[tokio::test]
+    async fn test_cache_outage() {
+        let mut state = testing::state().await;
+        state.revocations = Arc::new(UnavailableStore);
+        let token = token(1, unix_secs());
+
+        assert!(revoke_token(&state, &token).await.is_err());
+        assert!(!is_revoked(&state, &token).await);
+
+        let mut config = testing::config();
+        config.tokens.revocation_fail_open = false;
+        let mut state = testing::state_with(config).await.unwrap();
+        state.revocations = Arc::new(UnavailableStore);
+        assert!(is_revoked(&state, &token).await);
+
+        // Without a refresh token to fall back on, the request is refused
+        let access_token = token.claims.create_token(&state).await.unwrap();
+        assert!(matches!(
+            Claims::try_from_tokens(&state, &Some(access_token), &None, &ClientInfo::default())
+                .await,
+            Err(AuthError::TokenRevoked)
+        ));
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_ban_revokes_tokens(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let user_id = UserService::insert_user_email(state, "user@example.com", "", "user")
+            .await
+            .unwrap();
+        let client = ClientInfo::default();
+        let refresh_token = refresh_tokens::create(state, user_id, &client)
+            .await
+            .unwrap();
+        // Issued in the same second as the ban
+        let token = token(user_id, unix_secs());
+
+        UserService::update_user_banned_status(state, user_id, true, Some("spam"))
+            .await
+            .unwrap();
+        assert!(is_revoked(state, &token).await);
+        assert!(matches!(
+            refresh_tokens::rotate(state, &refresh_token, &client).await,
+            Err(AuthError::TokenRevoked)
+        ));
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_role_change_revokes_tokens(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let user_id = UserService::insert_user_email(state, "user@example.com", "", "user")
+            .await
+            .unwrap();
+        let client = ClientInfo::default();
+        let refresh_token = refresh_tokens::create(state, user_id, &client)
+            .await
+            .unwrap();
+        let token = token(user_id, unix_secs());
+
+        UserService::update_user_role(state, user_id, "creator")
+            .await
+            .unwrap();
+        assert!(is_revoked(state, &token).await);
+
+        // The replacement carries the new role
+        let (claims, _) = refresh_tokens::rotate(state, &refresh_token, &client)
+            .await
+            .unwrap();
+        assert_eq!(claims.role, "creator");
+    }
+}
@@ -0,0 +1,802 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use ciborium::value::Value;
+use rsweb_crypto::cose::{self, CoseKey};
+use rsweb_database::user::{UserEssentials, UserService, WebauthnCredential};
+use rsweb_state::AppState;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AuthError;
+
+// WebAuthn (passkey) registration and authentication ceremonies. Attestation
+// statements are not verified, credentials are trusted the same whether they
+// come from a platform authenticator or a security key.
+
+// Lifetime of a ceremony challenge in seconds (5 minutes)
+pub const CHALLENGE_LIFETIME: u64 = 5 * 60;
+const CHALLENGE_LENGTH: usize = 32;
+const MAX_CREDENTIAL_ID_LENGTH: usize = 1023;
+
+// Authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Debug, Clone)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+    pub origin: String,
+}
+
+// The site is the relying party, by default the host and origin of the app URL
+pub fn relying_party(state: &AppState) -> RelyingParty {
+    let config = &state.config;
+    let host = reqwest::Url::parse(&config.app_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or("localhost".to_string());
+
+    RelyingParty {
+        id: config.webauthn.rp_id.clone().unwrap_or(host),
+        name: config.webauthn.rp_name.clone(),
+        origin: config
+            .webauthn
+            .origin
+            .clone()
+            .unwrap_or(config.app_url.clone()),
+    }
+}
+
+// PublicKeyCredentialCreationOptions in the JSON form of the WebAuthn spec,
+// binary values are base64url
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    rp: RpEntity,
+    user: UserEntity,
+    challenge: String,
+    pub_key_cred_params: Vec<CredentialParameters>,
+    timeout: u64,
+    attestation: &'static str,
+    authenticator_selection: AuthenticatorSelection,
+    exclude_credentials: Vec<CredentialDescriptor>,
+}
+
+#[derive(Debug, Serialize)]
+struct RpEntity {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UserEntity {
+    id: String,
+    name: String,
+    display_name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CredentialParameters {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    alg: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthenticatorSelection {
+    resident_key: &'static str,
+    user_verification: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+}
+
+// PublicKeyCredentialRequestOptions, no credentials are listed so the
+// authenticator offers the passkeys it holds for the site
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    challenge: String,
+    rp_id: String,
+    timeout: u64,
+    user_verification: &'static str,
+    allow_credentials: Vec<CredentialDescriptor>,
+}
+
+// PublicKeyCredential.toJSON() of a create() call
+#[derive(Debug, Deserialize)]
+pub struct RegistrationResponse {
+    id: String,
+    response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    attestation_object: String,
+}
+
+// PublicKeyCredential.toJSON() of a get() call
+#[derive(Debug, Deserialize)]
+pub struct AuthenticationResponse {
+    id: String,
+    response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+    #[serde(default)]
+    user_handle: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingCeremony {
+    user_id: Option<i32>,
+}
+
+#[derive(Debug)]
+pub struct NewCredential {
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub algorithm: i64,
+    pub sign_count: u32,
+}
+
+#[derive(Debug)]
+pub struct Assertion {
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    // Credential id and COSE key, only present when registering
+    attested_credential: Option<(&'a [u8], &'a [u8])>,
+}
+
+fn invalid(reason: &str) -> AuthError {
+    AuthError::WebauthnError(reason.to_string())
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, AuthError> {
+    Ok(URL_SAFE_NO_PAD.decode(value.trim_end_matches('='))?)
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, data)
+        .as_ref()
+        .to_vec()
+}
+
+fn user_handle(user_id: i32) -> String {
+    URL_SAFE_NO_PAD.encode(user_id.to_string())
+}
+
+impl ClientData {
+    fn parse(client_data_json: &str) -> Result<Self, AuthError> {
+        Ok(serde_json::from_slice(&decode(client_data_json)?)?)
+    }
+
+    fn check(&self, kind: &str, challenge: &str, rp: &RelyingParty) -> Result<(), AuthError> {
+        if self.kind != kind {
+            return Err(invalid("unexpected client data type"));
+        }
+        if self.challenge != challenge {
+            return Err(invalid("challenge mismatch"));
+        }
+        if self.origin != rp.origin {
+            return Err(invalid("origin mismatch"));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> AuthenticatorData<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, AuthError> {
+        if data.len() < 37 {
+            return Err(invalid("authenticator data too short"));
+        }
+        let flags = data[32];
+        let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+        // AAGUID (16 bytes), credential id length (2 bytes), credential id,
+        // then the public key
+        let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+            let rest = data.get(37..).unwrap_or_default();
+            if rest.len() < 18 {
+                return Err(invalid("attested credential data too short"));
+            }
+            let id_length = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+            let credential_id = rest
+                .get(18..18 + id_length)
+                .ok_or(invalid("credential id out of bounds"))?;
+            let key_bytes = &rest[18 + id_length..];
+            let (_, key_length) = CoseKey::parse(key_bytes)?;
+            Some((credential_id, &key_bytes[..key_length]))
+        } else {
+            None
+        };
+
+        Ok(AuthenticatorData {
+            rp_id_hash: &data[..32],
+            flags,
+            sign_count,
+            attested_credential,
+        })
+    }
+
+    fn check(&self, rp: &RelyingParty) -> Result<(), AuthError> {
+        if self.rp_id_hash != sha256(rp.id.as_bytes()) {
+            return Err(invalid("relying party mismatch"));
+        }
+        if self.flags & FLAG_USER_PRESENT == 0 {
+            return Err(invalid("user not present"));
+        }
+        Ok(())
+    }
+}
+
+// Checks a create() response against the challenge that was handed out
+pub fn verify_registration(
+    rp: &RelyingParty,
+    response: &RegistrationResponse,
+    challenge: &str,
+) -> Result<NewCredential, AuthError> {
+    ClientData::parse(&response.response.client_data_json)?.check(
+        "webauthn.create",
+        challenge,
+        rp,
+    )?;
+
+    let attestation = decode(&response.response.attestation_object)?;
+    let attestation: Value = ciborium::de::from_reader(attestation.as_slice())
+        .map_err(|e| AuthError::WebauthnError(e.to_string()))?;
+    let auth_data = attestation
+        .as_map()
+        .and_then(|map| {
+            map.iter()
+                .find(|(k, _)| k.as_text() == Some("authData"))
+                .and_then(|(_, v)| v.as_bytes())
+        })
+        .ok_or(invalid("attestation object without authenticator data"))?;
+
+    let auth_data = AuthenticatorData::parse(auth_data)?;
+    auth_data.check(rp)?;
+
+    let (credential_id, key_bytes) = auth_data
+        .attested_credential
+        .ok_or(invalid("no attested credential"))?;
+    if credential_id.is_empty() || credential_id.len() > MAX_CREDENTIAL_ID_LENGTH {
+        return Err(invalid("invalid credential id length"));
+    }
+    let credential_id = URL_SAFE_NO_PAD.encode(credential_id);
+    if credential_id != response.id.trim_end_matches('=') {
+        return Err(invalid("credential id mismatch"));
+    }
+    let (key, _) = CoseKey::parse(key_bytes)?;
+
+    Ok(NewCredential {
+        credential_id,
+        public_key: key_bytes.to_vec(),
+        algorithm: key.algorithm(),
+        sign_count: auth_data.sign_count,
+    })
+}
+
+// Checks a get() response against the challenge and the stored public key
+pub fn verify_assertion(
+    rp: &RelyingParty,
+    response: &AuthenticationResponse,
+    challenge: &str,
+    public_key: &[u8],
+) -> Result<Assertion, AuthError> {
+    ClientData::parse(&response.response.client_data_json)?.check("webauthn.get", challenge, rp)?;
+
+    let auth_data_bytes = decode(&response.response.authenticator_data)?;
+    let auth_data = AuthenticatorData::parse(&auth_data_bytes)?;
+    auth_data.check(rp)?;
+
+    // The signature covers the authenticator data and the client data hash
+    let client_data_hash = sha256(&decode(&response.response.client_data_json)?);
+    let signed = [auth_data_bytes.as_slice(), client_data_hash.as_slice()].concat();
+    let (key, _) = CoseKey::parse(public_key)?;
+    if !key.verify(&signed, &decode(&response.response.signature)?) {
+        return Err(AuthError::InvalidSignature);
+    }
+
+    Ok(Assertion {
+        sign_count: auth_data.sign_count,
+        user_verified: auth_data.flags & FLAG_USER_VERIFIED != 0,
+    })
+}
+
+fn challenge_id(state: &AppState, purpose: &str, challenge: &str) -> String {
+    state
+        .hmac
+        .hash(format!("webauthn-{}:{}", purpose, challenge).as_bytes())
+}
+
+async fn create_challenge(
+    state: &AppState,
+    purpose: &str,
+    user_id: Option<i32>,
+) -> Result<String, AuthError> {
+    let challenge = URL_SAFE_NO_PAD.encode(rsweb_crypto::generate::generate_random_string(
+        CHALLENGE_LENGTH,
+    ));
+    state
+        .challenges
+        .store(
+            &challenge_id(state, purpose, &challenge),
+            &serde_json::to_string(&PendingCeremony { user_id })?,
+            CHALLENGE_LIFETIME,
+        )
+        .await?;
+
+    Ok(challenge)
+}
+
+// Challenges are single-use, whichever way the ceremony ends
+async fn take_challenge(
+    state: &AppState,
+    purpose: &str,
+    challenge: &str,
+) -> Result<PendingCeremony, AuthError> {
+    let id = challenge_id(state, purpose, challenge);
+    let pending = match state.challenges.get(&id).await? {
+        Some(payload) => serde_json::from_str(&payload)?,
+        None => return Err(AuthError::InvalidToken),
+    };
+    if !state.challenges.remove(&id).await? {
+        return Err(AuthError::InvalidToken);
+    }
+
+    Ok(pending)
+}
+
+fn timeout_ms() -> u64 {
+    CHALLENGE_LIFETIME * 1000
+}
+
+// Options for adding a passkey to the signed in user's account
+pub async fn start_registration(
+    state: &AppState,
+    user_id: i32,
+    email: &str,
+    handle: &str,
+) -> Result<CreationOptions, AuthError> {
+    let rp = relying_party(state);
+    let challenge = create_challenge(state, "register", Some(user_id)).await?;
+    let exclude_credentials = UserService::get_user_webauthn_credentials(state, user_id)
+        .await?
+        .into_iter()
+        .map(|credential| CredentialDescriptor {
+            kind: "public-key",
+            id: credential.credential_id,
+        })
+        .collect();
+
+    Ok(CreationOptions {
+        rp: RpEntity {
+            id: rp.id,
+            name: rp.name,
+        },
+        user: UserEntity {
+            id: user_handle(user_id),
+            name: email.to_string(),
+            display_name: handle.to_string(),
+        },
+        challenge,
+        pub_key_cred_params: [cose::ES256, cose::EDDSA, cose::RS256]
+            .into_iter()
+            .map(|alg| CredentialParameters {
+                kind: "public-key",
+                alg,
+            })
+            .collect(),
+        timeout: timeout_ms(),
+        attestation: "none",
+        authenticator_selection: AuthenticatorSelection {
+            resident_key: "required",
+            user_verification: "preferred",
+        },
+        exclude_credentials,
+    })
+}
+
+// Stores the new passkey, returns its id
+pub async fn finish_registration(
+    state: &AppState,
+    user_id: i32,
+    response: &RegistrationResponse,
+    name: &str,
+) -> Result<i32, AuthError> {
+    let client_data = ClientData::parse(&response.response.client_data_json)?;
+    let pending = take_challenge(state, "register", &client_data.challenge).await?;
+    if pending.user_id != Some(user_id) {
+        return Err(AuthError::InvalidToken);
+    }
+
+    let credential = verify_registration(&relying_party(state), response, &client_data.challenge)?;
+    let id = UserService::insert_webauthn_credential(
+        state,
+        user_id,
+        &credential.credential_id,
+        &credential.public_key,
+        credential.algorithm as i32,
+        credential.sign_count as i64,
+        name,
+    )
+    .await?;
+
+    Ok(id)
+}
+
+pub async fn start_authentication(state: &AppState) -> Result<RequestOptions, AuthError> {
+    let rp = relying_party(state);
+
+    Ok(RequestOptions {
+        challenge: create_challenge(state, "login", None).await?,
+        rp_id: rp.id,
+        timeout: timeout_ms(),
+        user_verification: "preferred",
+        allow_credentials: Vec::new(),
+    })
+}
+
+// Signs in with a passkey, returns the user and whether the authenticator
+// verified them (e.g. biometrics or a PIN) on top of their presence
+pub async fn finish_authentication(
+    state: &AppState,
+    response: &AuthenticationResponse,
+) -> Result<(UserEssentials, bool), AuthError> {
+    let client_data = ClientData::parse(&response.response.client_data_json)?;
+    take_challenge(state, "login", &client_data.challenge).await?;
+
+    let credential_id = response.id.trim_end_matches('=');
+    let credential = UserService::get_webauthn_credential(state, credential_id)
+        .await?
+        .ok_or(invalid("unknown credential"))?;
+    if let Some(handle) = &response.response.user_handle
+        && handle.trim_end_matches('=') != user_handle(credential.user_id)
+    {
+        return Err(invalid("user handle mismatch"));
+    }
+
+    let assertion = verify_assertion(
+        &relying_party(state),
+        response,
+        &client_data.challenge,
+        &credential.public_key,
+    )?;
+
+    // Authenticators without a counter always report 0, any other counter
+    // has to grow or the credential may have been cloned
+    let sign_count = assertion.sign_count as i64;
+    if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+        eprintln!(
+            "WebAuthn sign counter did not increase for credential {} of user {}",
+            credential.id, credential.user_id
+        );
+        return Err(invalid("sign counter did not increase"));
+    }
+    if UserService::update_webauthn_sign_count(
+        state,
+        credential.id,
+        credential.sign_count,
+        sign_count,
+    )
+    .await?
+        == 0
+    {
+        return Err(invalid("credential used concurrently"));
+    }
+
+    let user = UserService::get_active_user_essentials(state, credential.user_id)
+        .await?
+        .ok_or(AuthError::TokenRevoked)?;
+    Ok((user, assertion.user_verified))
+}
+
+pub async fn list_credentials(
+    state: &AppState,
+    user_id: i32,
+) -> Result<Vec<WebauthnCredential>, AuthError> {
+    Ok(UserService::get_user_webauthn_credentials(state, user_id).await?)
+}
+
+// Returns false if the user has no such passkey
+pub async fn remove_credential(state: &AppState, user_id: i32, id: i32) -> Result<bool, AuthError> {
+    Ok(UserService::delete_webauthn_credential(state, user_id, id).await? > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair};
+    use rsweb_state::testing;
+    use sqlx::PgPool;
+
+    // Software authenticator holding a single ES256 passkey
+    struct SoftAuthenticator {
+        rng: SystemRandom,
+        pair: EcdsaKeyPair,
+        credential_id: Vec<u8>,
+        sign_count: u32,
+    }
+
+    impl SoftAuthenticator {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+            let pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
+
+            SoftAuthenticator {
+                rng,
+                pair,
+                credential_id: b"soft-credential".to_vec(),
+                sign_count: 0,
+            }
+        }
+
+        fn cose_key(&self) -> CoseKey {
+            let public = self.pair.public_key().as_ref();
+            CoseKey::Es256 {
+                x: public[1..33].to_vec(),
+                y: public[33..].to_vec(),
+            }
+        }
+
+        fn client_data(kind: &str, challenge: &str, origin: &str) -> Vec<u8> {
+            serde_json::to_vec(&ClientData {
+                kind: kind.to_string(),
+                challenge: challenge.to_string(),
+                origin: origin.to_string(),
+            })
+            .unwrap()
+        }
+
+        fn auth_data(&self, rp_id: &str, flags: u8, attested: bool) -> Vec<u8> {
+            let mut data = sha256(rp_id.as_bytes());
+            data.push(flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            if attested {
+                data.extend_from_slice(&[0u8; 16]);
+                data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                data.extend_from_slice(&self.credential_id);
+                data.extend_from_slice(&self.cose_key().to_cbor());
+            }
+            data
+        }
+
+        fn create(&self, rp: &RelyingParty, challenge: &str) -> RegistrationResponse {
+            let auth_data = self.auth_data(
+                &rp.id,
+                FLAG_USER_PRESENT | FLAG_USER_VERIFIED | FLAG_ATTESTED_CREDENTIAL,
+                true,
+            );
+            let mut attestation_object = Vec::new();
+            ciborium::ser::into_writer(
+                &Value::Map(vec![
+                    (Value::Text("fmt".into()), Value::Text("none".into())),
+                    (Value::Text("attStmt".into()), Value::Map(vec![])),
+                    (Value::Text("authData".into()), Value::Bytes(auth_data)),
+                ]),
+                &mut attestation_object,
+            )
+            .unwrap();
+
+            RegistrationResponse {
+                id: URL_SAFE_NO_PAD.encode(&self.credential_id),
+                response: AttestationResponse {
+                    client_data_json: URL_SAFE_NO_PAD.encode(Self::client_data(
+                        "webauthn.create",
+                        challenge,
+                        &rp.origin,
+                    )),
+                    attestation_object: URL_SAFE_NO_PAD.encode(attestation_object),
+                },
+            }
+        }
+
+        fn get(&mut self, rp: &RelyingParty, challenge: &str) -> AuthenticationResponse {
+            self.sign_count += 1;
+            let auth_data = self.auth_data(&rp.id, FLAG_USER_PRESENT, false);
+            let client_data = Self::client_data("webauthn.get", challenge, &rp.origin);
+            let signed = [auth_data.as_slice(), sha256(&client_data).as_slice()].concat();
+            let signature = self.pair.sign(&self.rng, &signed).unwrap();
+
+            AuthenticationResponse {
+                id: URL_SAFE_NO_PAD.encode(&self.credential_id),
+                response: AssertionResponse {
+                    client_data_json: URL_SAFE_NO_PAD.encode(client_data),
+                    authenticator_data: URL_SAFE_NO_PAD.encode(auth_data),
+                    signature: URL_SAFE_NO_PAD.encode(signature.as_ref()),
+                    user_handle: None,
+                },
+            }
+        }
+    }
+
+    fn rp() -> RelyingParty {
+        RelyingParty {
+            id: "example.com".to_string(),
+            name: "rsweb".to_string(),
+            origin: "https://example.com".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_registration() {
+        let authenticator = SoftAuthenticator::new();
+        let response = authenticator.create(&rp(), "challenge");
+
+        let credential = verify_registration(&rp(), &response, "challenge").unwrap();
+        assert_eq!(credential.algorithm, cose::ES256);
+        assert_eq!(credential.sign_count, 0);
+        assert_eq!(
+            CoseKey::parse(&credential.public_key).unwrap().0,
+            authenticator.cose_key()
+        );
+    }
+
+    #[test]
+    fn test_registration_rejects_mismatches() {
+        let authenticator = SoftAuthenticator::new();
+        let response = authenticator.create(&rp(), "challenge");
+
+        assert!(verify_registration(&rp(), &response, "other challenge").is_err());
+
+        let other_origin = RelyingParty {
+            origin: "https://evil.example".to_string(),
+            ..rp()
+        };
+        assert!(verify_registration(&other_origin, &response, "challenge").is_err());
+
+        let other_rp = RelyingParty {
+            id: "evil.example".to_string(),
+            ..rp()
+        };
+        let response = authenticator.create(&other_rp, "challenge");
+        assert!(verify_registration(&rp(), &response, "challenge").is_err());
+    }
+
+    #[test]
+    fn test_assertion() {
+        let mut authenticator = SoftAuthenticator::new();
+        let credential =
+            verify_registration(&rp(), &authenticator.create(&rp(), "register"), "register")
+                .unwrap();
+
+        let response = authenticator.get(&rp(), "login");
+        let assertion =
+            verify_assertion(&rp(), &response, "login", &credential.public_key).unwrap();
+        assert_eq!(assertion.sign_count, 1);
+        assert!(!assertion.user_verified);
+
+        assert!(verify_assertion(&rp(), &response, "other", &credential.public_key).is_err());
+    }
+
+    #[test]
+    fn test_assertion_rejects_other_key() {
+        let mut authenticator = SoftAuthenticator::new();
+        let other = SoftAuthenticator::new();
+
+        let response = authenticator.get(&rp(), "login");
+        assert!(matches!(
+            verify_assertion(&rp(), &response, "login", &other.cose_key().to_cbor()),
+            Err(AuthError::InvalidSignature)
+        ));
+    }
+
+    // Registers the authenticator's passkey for a new user, returns the user id
+    async fn register(state: &AppState, authenticator: &SoftAuthenticator) -> i32 {
+        let user_id = UserService::insert_user_email(state, "user@example.com", "", "user")
+            .await
+            .unwrap();
+        let options = start_registration(state, user_id, "user@example.com", "user")
+            .await
+            .unwrap();
+        let response = authenticator.create(&relying_party(state), &options.challenge);
+        finish_registration(state, user_id, &response, "Security key")
+            .await
+            .unwrap();
+
+        user_id
+    }
+
+    async fn sign_in(
+        state: &AppState,
+        authenticator: &mut SoftAuthenticator,
+    ) -> AuthenticationResponse {
+        let options = start_authentication(state).await.unwrap();
+        authenticator.get(&relying_party(state), &options.challenge)
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_finish_authentication(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let mut authenticator = SoftAuthenticator::new();
+        let user_id = register(state, &authenticator).await;
+
+        let response = sign_in(state, &mut authenticator).await;
+        let (user, user_verified) = finish_authentication(state, &response).await.unwrap();
+        assert_eq!(user.id, user_id);
+        assert!(!user_verified);
+
+        // Challenges are single-use
+        assert!(matches!(
+            finish_authentication(state, &response).await,
+            Err(AuthError::InvalidToken)
+        ));
+
+        // A counter that didn't grow, e.g. from a cloned authenticator
+        authenticator.sign_count -= 1;
+        let response = sign_in(state, &mut authenticator).await;
+        assert!(finish_authentication(state, &response).await.is_err());
+
+        let response = sign_in(state, &mut authenticator).await;
+        assert!(finish_authentication(state, &response).await.is_ok());
+
+        let credentials = list_credentials(state, user_id).await.unwrap();
+        assert_eq!(credentials[0].sign_count, 2);
+        assert!(credentials[0].last_used_at.is_some());
+    }
+
+    #[sqlx::test(migrations = "../../sql")]
+    async fn test_finish_authentication_concurrently(db: PgPool) {
+        let state = &testing::state_with_db(db).await;
+        let mut authenticator = SoftAuthenticator::new();
+        let user_id = register(state, &authenticator).await;
+
+        // Two assertions with the same counter, whichever comes second must
+        // fail even if both read the stored counter before either updates it
+        let first = sign_in(state, &mut authenticator).await;
+        authenticator.sign_count -= 1;
+        let second = sign_in(state, &mut authenticator).await;
+
+        let (a, b) = tokio::join!(
+            finish_authentication(state, &first),
+            finish_authentication(state, &second)
+        );
+        assert_eq!(a.is_ok() as u8 + b.is_ok() as u8, 1);
+
+        let credentials = list_credentials(state, user_id).await.unwrap();
+        assert_eq!(credentials[0].sign_count, 1);
+
+        // An update based on a stale counter changes nothing
+        assert_eq!(
+            UserService::update_webauthn_sign_count(state, credentials[0].id, 0, 2)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+}
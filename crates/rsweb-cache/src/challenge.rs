@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use deadpool_redis::Pool;
+use deadpool_redis::redis::{self, AsyncCommands};
+
+// Short-lived login challenges (e.g. a pending second factor). The payload is
+// stored under the challenge id next to a counter of attempts, both expire
+// on their own once the challenge times out.
+#[async_trait]
+pub trait ChallengeStore: Send + Sync {
+    async fn store(
+        &self,
+        id: &str,
+        payload: &str,
+        ttl_secs: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    async fn get(
+        &self,
+        id: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>>;
+
+    // Counts an attempt, returns the number of attempts so far. Every caller
+    // gets a number of its own, so concurrent attempts can't share one.
+    async fn record_attempt(
+        &self,
+        id: &str,
+        ttl_secs: u64,
+    ) -> Result<i64, Box<dyn std::error::Error + Send + Sync>>;
+
+    // Ends the challenge, returns false if it was already gone so only one of
+    // several concurrent requests can complete it
+    async fn remove(&self, id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+fn challenge_key(id: &str) -> String {
+    format!("challenge:{}", id)
+}
+
+fn attempts_key(id: &str) -> String {
+    format!("challenge:{}:attempts", id)
+}
+
+pub struct RedisChallengeStore {
+    cache: Pool,
+}
+
+impl RedisChallengeStore {
+    pub fn new(cache: Pool) -> Self {
+        RedisChallengeStore { cache }
+    }
+}
+
+#[async_trait]
+impl ChallengeStore for RedisChallengeStore {
+    async fn store(
+        &self,
+        id: &str,
+        payload: &str,
+        ttl_secs: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.cache.get().await?;
+
+        let _: () = conn.set_ex(challenge_key(id), payload, ttl_secs).await?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.cache.get().await?;
+
+        Ok(conn.get(challenge_key(id)).await?)
+    }
+
+    async fn record_attempt(
+        &self,
+        id: &str,
+        ttl_secs: u64,
+    ) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.cache.get().await?;
+
+        // In one transaction, so the counter can't be left without expiry
+        let (attempts,): (i64,) = redis::pipe()
+            .atomic()
+            .incr(attempts_key(id), 1)
+            .expire(attempts_key(id), ttl_secs as i64)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(attempts)
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.cache.get().await?;
+
+        let removed: usize = conn.del(challenge_key(id)).await?;
+        let _: () = conn.del(attempts_key(id)).await?;
+        Ok(removed == 1)
+    }
+}
+
+// Keeps challenges in memory, for tests
+#[derive(Default)]
+pub struct MemoryChallengeStore {
+    challenges: Mutex<HashMap<String, MemoryChallenge>>,
+}
+
+struct MemoryChallenge {
+    payload: String,
+    attempts: i64,
+    expires_at: Instant,
+}
+
+impl MemoryChallengeStore {
+    pub fn new() -> Self {
+        MemoryChallengeStore::default()
+    }
+}
+
+#[async_trait]
+impl ChallengeStore for MemoryChallengeStore {
+    async fn store(
+        &self,
+        id: &str,
+        payload: &str,
+        ttl_secs: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.challenges.lock().unwrap().insert(
+            id.to_string(),
+            MemoryChallenge {
+                payload: payload.to_string(),
+                attempts: 0,
+                expires_at: Instant::now() + Duration::from_secs(ttl_secs),
+            },
+        );
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let challenges = self.challenges.lock().unwrap();
+        Ok(challenges
+            .get(id)
+            .filter(|challenge| challenge.expires_at > Instant::now())
+            .map(|challenge| challenge.payload.clone()))
+    }
+
+    async fn record_attempt(
+        &self,
+        id: &str,
+        _ttl_secs: u64,
+    ) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut challenges = self.challenges.lock().unwrap();
+        Ok(match challenges.get_mut(id) {
+            Some(challenge) => {
+                challenge.attempts += 1;
+                challenge.attempts
+            }
+            None => 1,
+        })
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let removed = self.challenges.lock().unwrap().remove(id);
+        Ok(removed.is_some_and(|challenge| challenge.expires_at > Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryChallengeStore::new();
+        store.store("id", "payload", 60).await.unwrap();
+
+        assert_eq!(store.get("id").await.unwrap().as_deref(), Some("payload"));
+        assert_eq!(store.record_attempt("id", 60).await.unwrap(), 1);
+        assert_eq!(store.record_attempt("id", 60).await.unwrap(), 2);
+        assert!(store.remove("id").await.unwrap());
+        assert!(!store.remove("id").await.unwrap());
+        assert!(store.get("id").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_expiry() {
+        let store = MemoryChallengeStore::new();
+        store.store("id", "payload", 0).await.unwrap();
+
+        assert!(store.get("id").await.unwrap().is_none());
+        assert!(!store.remove("id").await.unwrap());
+    }
+}
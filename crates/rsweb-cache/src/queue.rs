@@ -0,0 +1,141 @@
+use deadpool_redis::Pool;
+use deadpool_redis::redis::{self, AsyncCommands, Direction};
+
+// Work queues as Redis lists, consumers take from the tail while producers
+// push onto the head. Jobs that should run later wait in a sorted set scored
+// by their due time until a consumer promotes them onto the list.
+//
+// A job being worked on is kept on a processing list of the worker that took
+// it until the worker is done, so a worker that dies mid-job doesn't lose it:
+// it puts the job back on the queue when it starts again.
+
+fn ready_key(queue: &str) -> String {
+    format!("queue:{}", queue)
+}
+
+fn delayed_key(queue: &str) -> String {
+    format!("queue:{}:delayed", queue)
+}
+
+fn processing_key(queue: &str, worker: &str) -> String {
+    format!("queue:{}:processing:{}", queue, worker)
+}
+
+pub async fn push(
+    cache: &Pool,
+    queue: &str,
+    payload: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut conn = cache.get().await?;
+
+    let _: () = conn.lpush(ready_key(queue), payload).await?;
+    Ok(())
+}
+
+// Waits up to timeout_secs for a job and moves it onto the worker's
+// processing list, None if the queue stayed empty. The job stays there until
+// finish or retry.
+pub async fn take(
+    cache: &Pool,
+    queue: &str,
+    worker: &str,
+    timeout_secs: f64,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut conn = cache.get().await?;
+
+    Ok(conn
+        .blmove(
+            ready_key(queue),
+            processing_key(queue, worker),
+            Direction::Right,
+            Direction::Left,
+            timeout_secs,
+        )
+        .await?)
+}
+
+// Removes a job the worker is done with from its processing list
+pub async fn finish(
+    cache: &Pool,
+    queue: &str,
+    worker: &str,
+    payload: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut conn = cache.get().await?;
+
+    let _: () = conn.lrem(processing_key(queue, worker), 1, payload).await?;
+    Ok(())
+}
+
+// Replaces a taken job with one to run at run_at (e.g. the same job with the
+// attempt counted), in one transaction so a crash can't lose it
+pub async fn retry(
+    cache: &Pool,
+    queue: &str,
+    worker: &str,
+    taken: &str,
+    payload: &str,
+    run_at: i64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut conn = cache.get().await?;
+
+    let _: () = redis::pipe()
+        .atomic()
+        .lrem(processing_key(queue, worker), 1, taken)
+        .ignore()
+        .zadd(delayed_key(queue), payload, run_at)
+        .ignore()
+        .query_async(&mut conn)
+        .await?;
+    Ok(())
+}
+
+// Puts the jobs an earlier run of the worker took but never finished back on
+// the queue, returns how many. They are taken again before newer jobs.
+pub async fn requeue_unfinished(
+    cache: &Pool,
+    queue: &str,
+    worker: &str,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut conn = cache.get().await?;
+
+    let mut requeued = 0;
+    loop {
+        let moved: Option<String> = conn
+            .lmove(
+                processing_key(queue, worker),
+                ready_key(queue),
+                Direction::Left,
+                Direction::Right,
+            )
+            .await?;
+        if moved.is_none() {
+            return Ok(requeued);
+        }
+        requeued += 1;
+    }
+}
+
+// Moves delayed jobs that are due onto the queue, returns how many moved.
+// A job is only pushed by the consumer whose ZREM removed it, so concurrent
+// consumers never promote the same job twice.
+pub async fn promote_due(
+    cache: &Pool,
+    queue: &str,
+    now: i64,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut conn = cache.get().await?;
+
+    let due: Vec<String> = conn.zrangebyscore(delayed_key(queue), "-inf", now).await?;
+
+    let mut promoted = 0;
+    for payload in due {
+        let removed: usize = conn.zrem(delayed_key(queue), &payload).await?;
+        if removed == 1 {
+            let _: () = conn.lpush(ready_key(queue), &payload).await?;
+            promoted += 1;
+        }
+    }
+
+    Ok(promoted)
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use deadpool_redis::Pool;
+use deadpool_redis::redis::AsyncCommands;
+
+// Access tokens are revoked individually by their id (jti or legacy nonce),
+// or all at once per user through a cutoff on their issue time. Issue times
+// are whole seconds, so the cutoff includes tokens issued in its second.
+// Entries only need to outlive the tokens they revoke, so they expire on
+// their own.
+#[async_trait]
+pub trait RevocationStore: Send + Sync {
+    async fn revoke_token(
+        &self,
+        token_id: &str,
+        ttl_secs: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: i32,
+        issued_until: i64,
+        ttl_secs: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    async fn is_revoked(
+        &self,
+        token_id: &str,
+        user_id: i32,
+        issued_at: i64,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+fn token_key(token_id: &str) -> String {
+    format!("revoked:token:{}", token_id)
+}
+
+fn user_key(user_id: i32) -> String {
+    format!("revoked:user:{}", user_id)
+}
+
+pub struct RedisRevocationStore {
+    cache: Pool,
+}
+
+impl RedisRevocationStore {
+    pub fn new(cache: Pool) -> Self {
+        RedisRevocationStore { cache }
+    }
+}
+
+#[async_trait]
+impl RevocationStore for RedisRevocationStore {
+    async fn revoke_token(
+        &self,
+        token_id: &str,
+        ttl_secs: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.cache.get().await?;
+
+        let _: () = conn.set_ex(token_key(token_id), 1, ttl_secs.max(1)).await?;
+        Ok(())
+    }
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: i32,
+        issued_until: i64,
+        ttl_secs: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.cache.get().await?;
+
+        let _: () = conn
+            .set_ex(user_key(user_id), issued_until, ttl_secs.max(1))
+            .await?;
+        Ok(())
+    }
+
+    // Checks both the token and the user entry in a single round trip
+    async fn is_revoked(
+        &self,
+        token_id: &str,
+        user_id: i32,
+        issued_at: i64,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.cache.get().await?;
+
+        let (token, issued_until): (Option<i64>, Option<i64>) =
+            conn.mget(&[token_key(token_id), user_key(user_id)]).await?;
+
+        Ok(token.is_some() || issued_until.is_some_and(|cutoff| issued_at <= cutoff))
+    }
+}
+
+// Keeps revocations in memory, for tests
+#[derive(Default)]
+pub struct MemoryRevocationStore {
+    // Value and expiry by the same keys Redis would use
+    entries: Mutex<HashMap<String, (i64, Instant)>>,
+}
+
+impl MemoryRevocationStore {
+    pub fn new() -> Self {
+        MemoryRevocationStore::default()
+    }
+
+    fn set(&self, key: String, value: i64, ttl_secs: u64) {
+        let expires_at = Instant::now() + Duration::from_secs(ttl_secs.max(1));
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key, (value, expires_at));
+    }
+
+    fn get(&self, key: &str) -> Option<i64> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(value, _)| *value)
+    }
+}
+
+#[async_trait]
+impl RevocationStore for MemoryRevocationStore {
+    async fn revoke_token(
+        &self,
+        token_id: &str,
+        ttl_secs: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.set(token_key(token_id), 1, ttl_secs);
+        Ok(())
+    }
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: i32,
+        issued_until: i64,
+        ttl_secs: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.set(user_key(user_id), issued_until, ttl_secs);
+        Ok(())
+    }
+
+    async fn is_revoked(
+        &self,
+        token_id: &str,
+        user_id: i32,
+        issued_at: i64,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.get(&token_key(token_id)).is_some()
+            || self
+                .get(&user_key(user_id))
+                .is_some_and(|cutoff| issued_at <= cutoff))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryRevocationStore::new();
+        assert!(!store.is_revoked("a", 1, 100).await.unwrap());
+
+        store.revoke_token("a", 60).await.unwrap();
+        assert!(store.is_revoked("a", 1, 100).await.unwrap());
+        assert!(!store.is_revoked("b", 1, 100).await.unwrap());
+
+        store.revoke_user_tokens(1, 100, 60).await.unwrap();
+        assert!(store.is_revoked("b", 1, 99).await.unwrap());
+        assert!(store.is_revoked("b", 1, 100).await.unwrap());
+        assert!(!store.is_revoked("b", 1, 101).await.unwrap());
+        assert!(!store.is_revoked("b", 2, 99).await.unwrap());
+    }
+}
@@ -0,0 +1,29 @@
+#[derive(Debug)]
+pub enum ConfigError {
+    FileError(String, std::io::Error),
+    InvalidProfile(String),
+    // Every problem found, so they can all be fixed in one go
+    Invalid(Vec<String>),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::FileError(path, e) => {
+                write!(f, "Failed to read config file {}: {}", path, e)
+            }
+            ConfigError::InvalidProfile(profile) => {
+                write!(f, "Unknown profile {}, expected dev, test or prod", profile)
+            }
+            ConfigError::Invalid(problems) => {
+                write!(f, "Invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
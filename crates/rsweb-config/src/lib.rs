@@ -0,0 +1,611 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use settings::Settings;
+
+pub mod errors;
+mod settings;
+
+pub use errors::ConfigError;
+
+// Settings come from three layers, each overriding the one before: defaults of
+// the profile, the config file and environment variables. The profile is
+// picked by RSWEB_PROFILE (dev by default) and the file is RSWEB_CONFIG or
+// config/{profile}.toml when it exists. Environment variables keep the names
+// they had before the config file existed (DATABASE_URL, SMTP_HOST, ...).
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Dev,
+    Test,
+    // No defaults for anything that differs between deployments
+    Prod,
+}
+
+impl std::str::FromStr for Profile {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dev" | "development" => Ok(Profile::Dev),
+            "test" => Ok(Profile::Test),
+            "prod" | "production" => Ok(Profile::Prod),
+            other => Err(ConfigError::InvalidProfile(other.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Profile::Dev => write!(f, "dev"),
+            Profile::Test => write!(f, "test"),
+            Profile::Prod => write!(f, "prod"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenFormat {
+    // base64(JSON{m,k,d}) tokens only this service understands
+    Legacy,
+    // RFC 7519 JWTs signed with EdDSA
+    Jwt,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub port: u16,
+    pub https: bool,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct KeysConfig {
+    // Ed25519 keyring for auth tokens
+    pub keyring_path: PathBuf,
+    // Single key used before the keyring, imported when there is no keyring
+    pub legacy_key_path: PathBuf,
+    // HMAC secret for keyed hashes
+    pub token_secret_path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenConfig {
+    // Format of newly issued access tokens, both formats are always accepted
+    pub format: TokenFormat,
+    pub issuer: String,
+    pub audience: String,
+    pub access_token_lifetime: Duration,
+    pub refresh_token_lifetime: Duration,
+    pub password_reset_lifetime: Duration,
+    pub email_verification_lifetime: Duration,
+    // Whether access tokens are accepted while the revocation list can't be
+    // read, otherwise they have to be refreshed on every request meanwhile
+    pub revocation_fail_open: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordHashConfig {
+    // Argon2id cost parameters
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    // Hashes running at once, the number of CPUs when unset
+    pub concurrency: Option<u32>,
+    // How long a hash may wait for a free slot before the request is shed
+    pub queue_timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct GoogleConfig {
+    // Google sign in is off without a client id
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    // Google Workspace domains allowed to sign in, any when empty
+    pub hosted_domains: Vec<String>,
+}
+
+// OpenID Connect provider besides Google
+#[derive(Debug, Clone)]
+pub struct OidcProviderConfig {
+    pub name: String,
+    pub issuer: String,
+    // Client ids its tokens may be issued to, the first one runs the code flow
+    pub client_ids: Vec<String>,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebauthnConfig {
+    // Host and origin of app_url when unset
+    pub rp_id: Option<String>,
+    pub origin: Option<String>,
+    pub rp_name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    StartTls,
+    Tls,
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    // Port of the TLS mode when unset
+    pub port: Option<u16>,
+    pub tls: SmtpTls,
+    // Credentials are only used with a username
+    pub username: Option<String>,
+    pub password: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum MailBackend {
+    Stdout,
+    File(PathBuf),
+    Smtp(SmtpConfig),
+}
+
+#[derive(Debug, Clone)]
+pub struct MailConfig {
+    pub backend: MailBackend,
+    pub from: String,
+    // Names the queue worker's list of mail being sent, has to stay the same
+    // across restarts and differ between servers sharing a Redis
+    pub worker_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub profile: Profile,
+    // Public base URL of the site, used for links in outgoing mail
+    pub app_url: String,
+    pub server: ServerConfig,
+    pub database_url: String,
+    pub redis_url: String,
+    pub keys: KeysConfig,
+    pub tokens: TokenConfig,
+    pub password_hash: PasswordHashConfig,
+    pub google: GoogleConfig,
+    pub oidc: Vec<OidcProviderConfig>,
+    pub webauthn: WebauthnConfig,
+    pub mail: MailConfig,
+}
+
+// Environment variable of a provider setting, e.g. OIDC_ENTRA_ID_CLIENT_ID
+pub fn oidc_env_key(name: &str, setting: &str) -> String {
+    format!(
+        "OIDC_{}_{}",
+        name.to_ascii_uppercase().replace('-', "_"),
+        setting
+    )
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        let env: HashMap<String, String> = std::env::vars().collect();
+        let profile = match env.get("RSWEB_PROFILE").filter(|p| !p.is_empty()) {
+            Some(profile) => profile.parse()?,
+            None => Profile::Dev,
+        };
+
+        // Only a file that was asked for explicitly has to exist
+        let (path, explicit) = match env.get("RSWEB_CONFIG").filter(|p| !p.is_empty()) {
+            Some(path) => (PathBuf::from(path), true),
+            None => (PathBuf::from(format!("config/{}.toml", profile)), false),
+        };
+        let file = match std::fs::read_to_string(&path) {
+            Ok(contents) => Some(contents),
+            Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(ConfigError::FileError(path.display().to_string(), e)),
+        };
+
+        Self::from_sources(profile, file.as_deref(), &env)
+    }
+
+    pub fn from_sources(
+        profile: Profile,
+        file: Option<&str>,
+        env: &HashMap<String, String>,
+    ) -> Result<Self, ConfigError> {
+        let mut s = Settings::new(file, env);
+        // Local defaults that production has to set explicitly
+        let local = |value: &'static str| (profile != Profile::Prod).then_some(value);
+
+        let app_url = s.string("app_url", "APP_URL", local("http://localhost:3030"));
+        let app_url = app_url.trim_end_matches('/').to_string();
+        if !app_url.is_empty()
+            && !app_url.starts_with("http://")
+            && !app_url.starts_with("https://")
+        {
+            s.invalid("app_url", &app_url);
+        } else if profile == Profile::Prod && app_url.starts_with("http://") {
+            s.problem("app_url has to be https in prod".to_string());
+        }
+
+        let server = ServerConfig {
+            port: s.parse("server.port", "PORT", 3030),
+            https: s.parse("server.https", "HTTPS", false),
+            cert_path: s
+                .string("server.cert_path", "TLS_CERT_PATH", Some("cert.pem"))
+                .into(),
+            key_path: s
+                .string("server.key_path", "TLS_KEY_PATH", Some("cert.key"))
+                .into(),
+        };
+        if server.https {
+            for path in [&server.cert_path, &server.key_path] {
+                if !path.exists() {
+                    s.problem(format!("https is on but {} is missing", path.display()));
+                }
+            }
+        }
+
+        // Tests get a database on the local server so they run without setup
+        let database_url = s.string(
+            "database.url",
+            "DATABASE_URL",
+            (profile == Profile::Test).then_some("postgres://postgres@127.0.0.1:5432/rsweb_test"),
+        );
+        let redis_url = s.string("redis.url", "REDIS_URL", local("redis://127.0.0.1:6379"));
+
+        let keys = KeysConfig {
+            keyring_path: s
+                .string("keys.keyring_path", "KEYRING_PATH", Some(".keyring"))
+                .into(),
+            legacy_key_path: s
+                .string("keys.legacy_key_path", "LEGACY_KEY_PATH", Some(".private"))
+                .into(),
+            token_secret_path: s
+                .string(
+                    "keys.token_secret_path",
+                    "TOKEN_SECRET_PATH",
+                    Some(".token_secret"),
+                )
+                .into(),
+        };
+
+        let format = match s
+            .string("tokens.format", "TOKEN_FORMAT", Some("legacy"))
+            .as_str()
+        {
+            "legacy" => TokenFormat::Legacy,
+            "jwt" => TokenFormat::Jwt,
+            other => {
+                s.invalid("tokens.format", other);
+                TokenFormat::Legacy
+            }
+        };
+        let tokens = TokenConfig {
+            format,
+            issuer: s.string("tokens.issuer", "TOKEN_ISSUER", Some("rsweb")),
+            audience: s.string("tokens.audience", "TOKEN_AUDIENCE", Some("rsweb")),
+            access_token_lifetime: s.seconds(
+                "tokens.access_token_lifetime",
+                "ACCESS_TOKEN_LIFETIME",
+                2 * 60 * 60,
+            ),
+            refresh_token_lifetime: s.seconds(
+                "tokens.refresh_token_lifetime",
+                "REFRESH_TOKEN_LIFETIME",
+                14 * 24 * 60 * 60,
+            ),
+            password_reset_lifetime: s.seconds(
+                "tokens.password_reset_lifetime",
+                "PASSWORD_RESET_LIFETIME",
+                60 * 60,
+            ),
+            email_verification_lifetime: s.seconds(
+                "tokens.email_verification_lifetime",
+                "EMAIL_VERIFICATION_LIFETIME",
+                2 * 24 * 60 * 60,
+            ),
+            revocation_fail_open: s.parse(
+                "tokens.revocation_fail_open",
+                "REVOCATION_FAIL_OPEN",
+                true,
+            ),
+        };
+
+        // Defaults follow the OWASP recommendation for Argon2id
+        let password_hash = PasswordHashConfig {
+            memory_kib: s.parse(
+                "password_hash.memory_kib",
+                "PASSWORD_HASH_MEMORY_KIB",
+                19 * 1024,
+            ),
+            iterations: s.parse("password_hash.iterations", "PASSWORD_HASH_ITERATIONS", 2),
+            parallelism: s.parse("password_hash.parallelism", "PASSWORD_HASH_PARALLELISM", 1),
+            concurrency: s.parse_optional("password_hash.concurrency", "PASSWORD_HASH_CONCURRENCY"),
+            queue_timeout: Duration::from_millis(s.parse(
+                "password_hash.queue_timeout_ms",
+                "PASSWORD_HASH_QUEUE_TIMEOUT_MS",
+                5000,
+            )),
+        };
+
+        let google = GoogleConfig {
+            client_id: s.optional("google.client_id", "GOOGLE_OAUTH_CLIENT_ID"),
+            client_secret: s.optional("google.client_secret", "GOOGLE_OAUTH_CLIENT_SECRET"),
+            hosted_domains: s.list("google.hosted_domains", "GOOGLE_HOSTED_DOMAINS"),
+        };
+
+        // OIDC_PROVIDERS replaces the [oidc.<name>] tables of the file
+        let names = match env.get("OIDC_PROVIDERS").filter(|v| !v.is_empty()) {
+            Some(names) => settings::split_list(names),
+            None => s.tables("oidc"),
+        };
+        let mut oidc = Vec::new();
+        for name in names {
+            let name = name.to_ascii_lowercase();
+            if name == "google" || oidc.iter().any(|p: &OidcProviderConfig| p.name == name) {
+                continue;
+            }
+            let key = |setting: &str| format!("oidc.{}.{}", name, setting);
+            let issuer = s.string(&key("issuer"), &oidc_env_key(&name, "ISSUER"), None);
+            let client_ids = s.list(&key("client_ids"), &oidc_env_key(&name, "CLIENT_ID"));
+            if client_ids.is_empty() {
+                s.problem(format!(
+                    "{} is not set ({} or config file)",
+                    key("client_ids"),
+                    oidc_env_key(&name, "CLIENT_ID")
+                ));
+            }
+            let client_secret =
+                s.optional(&key("client_secret"), &oidc_env_key(&name, "CLIENT_SECRET"));
+            oidc.push(OidcProviderConfig {
+                name,
+                issuer,
+                client_ids,
+                client_secret,
+            });
+        }
+
+        let webauthn = WebauthnConfig {
+            rp_id: s.optional("webauthn.rp_id", "WEBAUTHN_RP_ID"),
+            origin: s.optional("webauthn.origin", "WEBAUTHN_ORIGIN"),
+            rp_name: s.string("webauthn.rp_name", "WEBAUTHN_RP_NAME", Some("rsweb")),
+        };
+
+        let mail = MailConfig {
+            backend: mail_backend(&mut s, profile),
+            from: s.string("mail.from", "MAIL_FROM", Some("rsweb <no-reply@localhost>")),
+            worker_id: s.string("mail.worker_id", "MAIL_WORKER_ID", Some("main")),
+        };
+
+        s.check_unknown();
+        if !s.problems.is_empty() {
+            return Err(ConfigError::Invalid(s.problems));
+        }
+
+        Ok(Config {
+            profile,
+            app_url,
+            server,
+            database_url,
+            redis_url,
+            keys,
+            tokens,
+            password_hash,
+            google,
+            oidc,
+            webauthn,
+            mail,
+        })
+    }
+
+    pub fn oidc_provider(&self, name: &str) -> Option<&OidcProviderConfig> {
+        self.oidc.iter().find(|provider| provider.name == name)
+    }
+}
+
+// Mail is printed locally, production delivers it through SMTP unless told
+// otherwise
+fn mail_backend(s: &mut Settings, profile: Profile) -> MailBackend {
+    let default = if profile == Profile::Prod {
+        "smtp"
+    } else {
+        "stdout"
+    };
+    match s
+        .string("mail.backend", "MAIL_BACKEND", Some(default))
+        .as_str()
+    {
+        "stdout" => MailBackend::Stdout,
+        "file" => MailBackend::File(s.string("mail.dir", "MAIL_DIR", Some("mail")).into()),
+        "smtp" => {
+            let tls = match s
+                .string("mail.smtp.tls", "SMTP_TLS", Some("starttls"))
+                .as_str()
+            {
+                "starttls" => SmtpTls::StartTls,
+                "tls" => SmtpTls::Tls,
+                "none" => SmtpTls::None,
+                other => {
+                    s.invalid("mail.smtp.tls", other);
+                    SmtpTls::StartTls
+                }
+            };
+            MailBackend::Smtp(SmtpConfig {
+                host: s.string("mail.smtp.host", "SMTP_HOST", None),
+                port: s.parse_optional("mail.smtp.port", "SMTP_PORT"),
+                tls,
+                username: s.optional("mail.smtp.username", "SMTP_USERNAME"),
+                password: s
+                    .optional("mail.smtp.password", "SMTP_PASSWORD")
+                    .unwrap_or_default(),
+            })
+        }
+        other => {
+            s.invalid("mail.backend", other);
+            MailBackend::Stdout
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn problems(result: Result<Config, ConfigError>) -> Vec<String> {
+        match result {
+            Err(ConfigError::Invalid(problems)) => problems,
+            other => panic!("expected problems, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_dev_defaults() {
+        let config = Config::from_sources(
+            Profile::Dev,
+            None,
+            &env(&[("DATABASE_URL", "postgres://localhost/rsweb")]),
+        )
+        .unwrap();
+
+        assert_eq!(config.app_url, "http://localhost:3030");
+        assert_eq!(config.redis_url, "redis://127.0.0.1:6379");
+        assert_eq!(config.server.port, 3030);
+        assert!(!config.server.https);
+        assert_eq!(config.tokens.format, TokenFormat::Legacy);
+        assert_eq!(
+            config.tokens.access_token_lifetime,
+            Duration::from_secs(7200)
+        );
+        assert!(config.tokens.revocation_fail_open);
+        assert!(matches!(config.mail.backend, MailBackend::Stdout));
+        assert_eq!(config.mail.worker_id, "main");
+        assert!(config.google.client_id.is_none());
+        assert!(config.oidc.is_empty());
+    }
+
+    #[test]
+    fn test_all_problems_reported() {
+        let problems = problems(Config::from_sources(
+            Profile::Prod,
+            Some("[server]\nhttps = false\nprot = 80\n"),
+            &env(&[("TOKEN_FORMAT", "paseto"), ("OIDC_PROVIDERS", "keycloak")]),
+        ));
+
+        assert_eq!(
+            problems,
+            vec![
+                "app_url is not set (APP_URL or config file)",
+                "database.url is not set (DATABASE_URL or config file)",
+                "redis.url is not set (REDIS_URL or config file)",
+                "invalid value for tokens.format: paseto",
+                "oidc.keycloak.issuer is not set (OIDC_KEYCLOAK_ISSUER or config file)",
+                "oidc.keycloak.client_ids is not set (OIDC_KEYCLOAK_CLIENT_ID or config file)",
+                "mail.smtp.host is not set (SMTP_HOST or config file)",
+                "unknown setting server.prot",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_and_env_layers() {
+        let file = r#"
+            app_url = "https://example.com/"
+            database.url = "postgres://db/rsweb"
+            redis.url = "redis://cache:6379"
+
+            [server]
+            https = false
+            port = 8080
+
+            [tokens]
+            format = "jwt"
+            access_token_lifetime = 900
+
+            [google]
+            client_id = "web.apps.googleusercontent.com"
+            hosted_domains = ["example.com", "example.org"]
+
+            [oidc.keycloak]
+            issuer = "https://sso.example.com/realms/main"
+            client_ids = ["web", "mobile"]
+
+            [mail]
+            backend = "smtp"
+            smtp.host = "smtp.example.com"
+            smtp.port = 2525
+        "#;
+        let config = Config::from_sources(
+            Profile::Prod,
+            Some(file),
+            &env(&[("PORT", "9090"), ("SMTP_TLS", "tls"), ("TOKEN_ISSUER", "")]),
+        )
+        .unwrap();
+
+        assert_eq!(config.app_url, "https://example.com");
+        // Environment variables win, empty ones don't count
+        assert_eq!(config.server.port, 9090);
+        assert_eq!(config.tokens.issuer, "rsweb");
+        assert_eq!(config.tokens.format, TokenFormat::Jwt);
+        assert_eq!(
+            config.tokens.access_token_lifetime,
+            Duration::from_secs(900)
+        );
+        assert_eq!(
+            config.google.hosted_domains,
+            vec!["example.com", "example.org"]
+        );
+
+        let keycloak = config.oidc_provider("keycloak").unwrap();
+        assert_eq!(keycloak.client_ids, vec!["web", "mobile"]);
+        assert!(keycloak.client_secret.is_none());
+
+        match config.mail.backend {
+            MailBackend::Smtp(smtp) => {
+                assert_eq!(smtp.host, "smtp.example.com");
+                assert_eq!(smtp.port, Some(2525));
+                assert_eq!(smtp.tls, SmtpTls::Tls);
+            }
+            other => panic!("expected smtp, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_test_defaults() {
+        let config = Config::from_sources(Profile::Test, None, &env(&[])).unwrap();
+        assert_eq!(
+            config.database_url,
+            "postgres://postgres@127.0.0.1:5432/rsweb_test"
+        );
+    }
+
+    #[test]
+    fn test_oidc_from_env() {
+        let config = Config::from_sources(
+            Profile::Test,
+            None,
+            &env(&[
+                ("DATABASE_URL", "postgres://localhost/rsweb"),
+                ("OIDC_PROVIDERS", "Entra-ID, google"),
+                (
+                    "OIDC_ENTRA_ID_ISSUER",
+                    "https://login.microsoftonline.com/t/v2.0",
+                ),
+                ("OIDC_ENTRA_ID_CLIENT_ID", " app ,,"),
+                ("OIDC_ENTRA_ID_CLIENT_SECRET", "secret"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.oidc.len(), 1);
+        assert_eq!(config.oidc[0].name, "entra-id");
+        assert_eq!(config.oidc[0].client_ids, vec!["app"]);
+        assert_eq!(config.oidc[0].client_secret.as_deref(), Some("secret"));
+    }
+}
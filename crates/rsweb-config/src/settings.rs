@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+// Raw values by dotted key (e.g. "server.port"), environment variables take
+// precedence over the config file. Problems are collected instead of returned
+// so all of them can be reported at once.
+pub(crate) struct Settings<'a> {
+    file: HashMap<String, String>,
+    env: &'a HashMap<String, String>,
+    used: HashSet<String>,
+    pub problems: Vec<String>,
+}
+
+// Tables become dotted keys, arrays comma separated values like in env vars
+fn flatten(prefix: &str, table: &toml::Table, values: &mut HashMap<String, String>) {
+    for (name, value) in table {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        match value {
+            toml::Value::Table(table) => flatten(&key, table, values),
+            toml::Value::String(s) => {
+                values.insert(key, s.clone());
+            }
+            toml::Value::Array(items) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|item| match item {
+                        toml::Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    })
+                    .collect();
+                values.insert(key, items.join(","));
+            }
+            other => {
+                values.insert(key, other.to_string());
+            }
+        }
+    }
+}
+
+impl<'a> Settings<'a> {
+    pub fn new(file: Option<&str>, env: &'a HashMap<String, String>) -> Self {
+        let mut settings = Self {
+            file: HashMap::new(),
+            env,
+            used: HashSet::new(),
+            problems: Vec::new(),
+        };
+        if let Some(file) = file {
+            match file.parse::<toml::Table>() {
+                Ok(table) => flatten("", &table, &mut settings.file),
+                Err(e) => settings
+                    .problems
+                    .push(format!("config file: {}", e.message())),
+            }
+        }
+        settings
+    }
+
+    // Empty environment variables count as unset
+    fn value(&mut self, key: &str, env_var: &str) -> Option<String> {
+        self.used.insert(key.to_string());
+        self.env
+            .get(env_var)
+            .filter(|value| !value.is_empty())
+            .or_else(|| self.file.get(key))
+            .cloned()
+    }
+
+    pub fn problem(&mut self, problem: String) {
+        self.problems.push(problem);
+    }
+
+    pub fn invalid(&mut self, key: &str, value: &str) {
+        self.problem(format!("invalid value for {}: {}", key, value));
+    }
+
+    pub fn optional(&mut self, key: &str, env_var: &str) -> Option<String> {
+        self.value(key, env_var)
+    }
+
+    // Required when there is no default
+    pub fn string(&mut self, key: &str, env_var: &str, default: Option<&str>) -> String {
+        match self.value(key, env_var).or(default.map(str::to_string)) {
+            Some(value) => value,
+            None => {
+                self.problem(format!("{} is not set ({} or config file)", key, env_var));
+                String::new()
+            }
+        }
+    }
+
+    pub fn parse<T: FromStr>(&mut self, key: &str, env_var: &str, default: T) -> T {
+        self.parse_optional(key, env_var).unwrap_or(default)
+    }
+
+    pub fn parse_optional<T: FromStr>(&mut self, key: &str, env_var: &str) -> Option<T> {
+        let value = self.value(key, env_var)?;
+        match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                self.invalid(key, &value);
+                None
+            }
+        }
+    }
+
+    // Whole seconds, at least one
+    pub fn seconds(&mut self, key: &str, env_var: &str, default: u64) -> Duration {
+        match self.parse(key, env_var, default) {
+            0 => {
+                self.invalid(key, "0");
+                Duration::from_secs(default)
+            }
+            secs => Duration::from_secs(secs),
+        }
+    }
+
+    pub fn list(&mut self, key: &str, env_var: &str) -> Vec<String> {
+        self.value(key, env_var)
+            .map(|value| split_list(&value))
+            .unwrap_or_default()
+    }
+
+    // Names of the [prefix.<name>] tables in the file
+    pub fn tables(&self, prefix: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .file
+            .keys()
+            .filter_map(|key| key.strip_prefix(prefix)?.strip_prefix('.'))
+            .filter_map(|rest| rest.split_once('.').map(|(name, _)| name.to_string()))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // Keys in the file nothing asked for, most likely typos
+    pub fn check_unknown(&mut self) {
+        let mut unknown: Vec<String> = self
+            .file
+            .keys()
+            .filter(|key| !self.used.contains(*key))
+            .cloned()
+            .collect();
+        unknown.sort();
+        for key in unknown {
+            self.problem(format!("unknown setting {}", key));
+        }
+    }
+}
+
+pub(crate) fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,204 @@
+use ciborium::value::Value;
+use ring::signature::{self, UnparsedPublicKey};
+
+use crate::errors::CryptoError;
+
+// COSE algorithm identifiers (RFC 9053) accepted for WebAuthn credentials,
+// together they cover platform authenticators and security keys
+pub const ES256: i64 = -7;
+pub const EDDSA: i64 = -8;
+pub const RS256: i64 = -257;
+
+// Key type and curve labels from the IANA COSE registries
+const KTY_OKP: i128 = 1;
+const KTY_EC2: i128 = 2;
+const KTY_RSA: i128 = 3;
+const CRV_P256: i128 = 1;
+const CRV_ED25519: i128 = 6;
+
+// A public key in COSE_Key format (RFC 9052)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoseKey {
+    Es256 { x: Vec<u8>, y: Vec<u8> },
+    EdDsa { x: Vec<u8> },
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+impl CoseKey {
+    // Parses the CBOR encoded key at the start of the input and returns it
+    // with its encoded length, as keys in authenticator data are followed by
+    // other fields
+    pub fn parse(bytes: &[u8]) -> Result<(Self, usize), CryptoError> {
+        let mut cursor = std::io::Cursor::new(bytes);
+        let value: Value = ciborium::de::from_reader(&mut cursor)
+            .map_err(|e| CryptoError::CoseError(e.to_string()))?;
+        let length = cursor.position() as usize;
+
+        let map = value
+            .as_map()
+            .ok_or(CryptoError::CoseError("key is not a map".to_string()))?;
+        let get = |label: i128| {
+            map.iter()
+                .find(|(k, _)| k.as_integer().map(i128::from) == Some(label))
+                .map(|(_, v)| v)
+        };
+        let int = |label| get(label).and_then(Value::as_integer).map(i128::from);
+        let bytes = |label, len: Option<usize>| {
+            get(label)
+                .and_then(Value::as_bytes)
+                .filter(|b| len.is_none_or(|len| b.len() == len))
+                .cloned()
+                .ok_or(CryptoError::CoseError(format!(
+                    "invalid parameter {}",
+                    label
+                )))
+        };
+
+        let key = match (int(1), int(3)) {
+            (Some(KTY_EC2), Some(alg)) if alg == ES256 as i128 && int(-1) == Some(CRV_P256) => {
+                CoseKey::Es256 {
+                    x: bytes(-2, Some(32))?,
+                    y: bytes(-3, Some(32))?,
+                }
+            }
+            (Some(KTY_OKP), Some(alg)) if alg == EDDSA as i128 && int(-1) == Some(CRV_ED25519) => {
+                CoseKey::EdDsa {
+                    x: bytes(-2, Some(32))?,
+                }
+            }
+            (Some(KTY_RSA), Some(alg)) if alg == RS256 as i128 => CoseKey::Rs256 {
+                n: bytes(-1, None)?,
+                e: bytes(-2, None)?,
+            },
+            (kty, alg) => {
+                return Err(CryptoError::CoseError(format!(
+                    "unsupported key type {:?} with algorithm {:?}",
+                    kty, alg
+                )));
+            }
+        };
+
+        Ok((key, length))
+    }
+
+    pub fn algorithm(&self) -> i64 {
+        match self {
+            CoseKey::Es256 { .. } => ES256,
+            CoseKey::EdDsa { .. } => EDDSA,
+            CoseKey::Rs256 { .. } => RS256,
+        }
+    }
+
+    // ES256 signatures are DER encoded as WebAuthn produces them
+    pub fn verify(&self, message: &[u8], sig: &[u8]) -> bool {
+        match self {
+            CoseKey::Es256 { x, y } => {
+                let point = [&[0x04], x.as_slice(), y.as_slice()].concat();
+                UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point)
+                    .verify(message, sig)
+                    .is_ok()
+            }
+            CoseKey::EdDsa { x } => UnparsedPublicKey::new(&signature::ED25519, x)
+                .verify(message, sig)
+                .is_ok(),
+            CoseKey::Rs256 { n, e } => signature::RsaPublicKeyComponents { n, e }
+                .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, sig)
+                .is_ok(),
+        }
+    }
+
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let int = |i: i64| Value::Integer(i.into());
+        let entries = match self {
+            CoseKey::Es256 { x, y } => vec![
+                (int(1), int(KTY_EC2 as i64)),
+                (int(3), int(ES256)),
+                (int(-1), int(CRV_P256 as i64)),
+                (int(-2), Value::Bytes(x.clone())),
+                (int(-3), Value::Bytes(y.clone())),
+            ],
+            CoseKey::EdDsa { x } => vec![
+                (int(1), int(KTY_OKP as i64)),
+                (int(3), int(EDDSA)),
+                (int(-1), int(CRV_ED25519 as i64)),
+                (int(-2), Value::Bytes(x.clone())),
+            ],
+            CoseKey::Rs256 { n, e } => vec![
+                (int(1), int(KTY_RSA as i64)),
+                (int(3), int(RS256)),
+                (int(-1), Value::Bytes(n.clone())),
+                (int(-2), Value::Bytes(e.clone())),
+            ],
+        };
+
+        let mut out = Vec::new();
+        ciborium::ser::into_writer(&Value::Map(entries), &mut out)
+            .expect("Writing CBOR to a Vec can't fail");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair};
+
+    #[test]
+    fn test_es256_roundtrip() {
+        let rng = SystemRandom::new();
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(
+            &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+            pkcs8.as_ref(),
+            &rng,
+        )
+        .unwrap();
+        let public = pair.public_key().as_ref();
+        let key = CoseKey::Es256 {
+            x: public[1..33].to_vec(),
+            y: public[33..].to_vec(),
+        };
+
+        // Trailing bytes are not part of the key
+        let mut encoded = key.to_cbor();
+        let length = encoded.len();
+        encoded.extend_from_slice(b"trailing");
+        let (parsed, parsed_length) = CoseKey::parse(&encoded).unwrap();
+        assert_eq!(parsed, key);
+        assert_eq!(parsed_length, length);
+
+        let sig = pair.sign(&rng, b"message").unwrap();
+        assert!(parsed.verify(b"message", sig.as_ref()));
+        assert!(!parsed.verify(b"other message", sig.as_ref()));
+    }
+
+    #[test]
+    fn test_eddsa_roundtrip() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key = CoseKey::EdDsa {
+            x: pair.public_key().as_ref().to_vec(),
+        };
+
+        let (parsed, _) = CoseKey::parse(&key.to_cbor()).unwrap();
+        assert_eq!(parsed.algorithm(), EDDSA);
+        assert!(parsed.verify(b"message", pair.sign(b"message").as_ref()));
+    }
+
+    #[test]
+    fn test_unsupported_key() {
+        // EC2 key with ES384
+        let int = |i: i64| Value::Integer(i.into());
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(
+            &Value::Map(vec![(int(1), int(2)), (int(3), int(-35))]),
+            &mut encoded,
+        )
+        .unwrap();
+
+        assert!(CoseKey::parse(&encoded).is_err());
+        assert!(CoseKey::parse(b"\xff").is_err());
+    }
+}
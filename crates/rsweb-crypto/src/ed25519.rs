@@ -1,154 +1,485 @@
 use base64::{Engine as _, engine::general_purpose};
 use nacl::sign::{PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
 use rand::Rng;
-use std::path::Path;
-use std::sync::Arc;
+use rsweb_config::KeysConfig;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::fs as async_fs;
-use tokio::sync::OnceCell;
+use tokio::io::AsyncWriteExt;
 
 use crate::cast;
 use crate::errors::CryptoError;
 
-pub struct KeyStore {
+// Minimum time between two reloads triggered by an unknown key id
+const RELOAD_THROTTLE: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyState {
+    // Signs new tokens and verifies them, there is exactly one active key
+    Active,
+    // No longer signs, but tokens it signed are still accepted
+    VerifyOnly,
+    // Kept for reference only, tokens it signed are rejected
+    Retired,
+}
+
+#[derive(Clone)]
+pub struct SigningKey {
+    kid: String,
+    state: KeyState,
     secret_key: [u8; SECRET_KEY_LENGTH],
     public_key: [u8; PUBLIC_KEY_LENGTH],
+    created_at: i64,
+    demoted_at: Option<i64>,
 }
 
-impl KeyStore {
-    async fn initialize() -> Result<Self, CryptoError> {
-        let key_path = ".private";
-
-        if Path::new(key_path).exists() {
-            let secret_key = async_fs::read(key_path).await?;
-            let public_key = match nacl::sign::extract_pkey(&secret_key) {
-                Ok(bytes) => bytes,
-                Err(err) => return Err(CryptoError::ExtractPubkeyError(err.message)),
-            };
+impl SigningKey {
+    fn generate() -> Self {
+        let random_seed: [u8; 32] = rand::thread_rng().r#gen();
+        let kp = nacl::sign::generate_keypair(&random_seed);
 
-            let skey = cast::slice_to_array_64(&secret_key).unwrap();
-            let pkey = cast::slice_to_array_32(&public_key).unwrap();
+        SigningKey {
+            kid: key_id(&kp.pkey),
+            state: KeyState::Active,
+            secret_key: kp.skey,
+            public_key: kp.pkey,
+            created_at: unix_secs(),
+            demoted_at: None,
+        }
+    }
 
-            Ok(KeyStore {
-                secret_key: *skey,
-                public_key: *pkey,
-            })
-        } else {
-            let random_seed: [u8; 32] = rand::thread_rng().r#gen();
-            let kp = nacl::sign::generate_keypair(&random_seed);
+    fn from_secret_key(secret_key: &[u8], state: KeyState) -> Result<Self, CryptoError> {
+        let public_key = match nacl::sign::extract_pkey(secret_key) {
+            Ok(bytes) => bytes,
+            Err(err) => return Err(CryptoError::ExtractPubkeyError(err.message)),
+        };
 
-            let skey: [u8; 64] = kp.skey;
-            let pkey = kp.pkey;
+        let skey = cast::slice_to_array_64(secret_key)?;
+        let pkey = cast::slice_to_array_32(&public_key)?;
 
-            async_fs::write(key_path, &skey).await?;
+        Ok(SigningKey {
+            kid: key_id(pkey),
+            state,
+            secret_key: *skey,
+            public_key: *pkey,
+            created_at: unix_secs(),
+            demoted_at: None,
+        })
+    }
 
-            Ok(KeyStore {
-                secret_key: skey,
-                public_key: pkey,
-            })
-        }
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn state(&self) -> KeyState {
+        self.state
     }
 
     pub fn public_key(&self) -> &[u8; PUBLIC_KEY_LENGTH] {
         &self.public_key
     }
 
+    pub fn public_key_base64(&self) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(self.public_key)
+    }
+
     pub fn secret_key(&self) -> &[u8; SECRET_KEY_LENGTH] {
         &self.secret_key
     }
+
+    pub fn created_at(&self) -> i64 {
+        self.created_at
+    }
+
+    pub fn demoted_at(&self) -> Option<i64> {
+        self.demoted_at
+    }
+
+    pub fn can_verify(&self) -> bool {
+        self.state != KeyState::Retired
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        match nacl::sign::signature(message, &self.secret_key) {
+            Ok(bytes) => Ok(bytes),
+            Err(e) => Err(CryptoError::SignError(e.message)),
+        }
+    }
 }
 
-// Global KeyStore instance
-static KEY_STORE_INSTANCE: OnceCell<Arc<KeyStore>> = OnceCell::const_new();
+// On-disk representation of a keyring entry
+#[derive(Serialize, Deserialize)]
+struct KeyRecord {
+    kid: String,
+    state: KeyState,
+    secret_key: String,
+    created_at: i64,
+    demoted_at: Option<i64>,
+}
 
-// Function to get a reference to the initialized DB
-pub async fn get_key_store() -> Arc<KeyStore> {
-    KEY_STORE_INSTANCE
-        .get_or_init(|| async {
-            let key_store = KeyStore::initialize()
-                .await
-                .expect("Failed to initialize key store");
-            Arc::new(key_store)
-        })
-        .await
-        .clone()
+impl From<&SigningKey> for KeyRecord {
+    fn from(key: &SigningKey) -> Self {
+        KeyRecord {
+            kid: key.kid.clone(),
+            state: key.state,
+            secret_key: hex::encode(key.secret_key),
+            created_at: key.created_at,
+            demoted_at: key.demoted_at,
+        }
+    }
 }
 
-pub async fn sign_message(message: &[u8]) -> Vec<u8> {
-    let key_store = get_key_store().await;
+impl TryFrom<KeyRecord> for SigningKey {
+    type Error = CryptoError;
 
-    match nacl::sign::signature(message, key_store.secret_key()) {
-        Ok(bytes) => bytes,
-        Err(err) => {
-            println!("Error: {:?}", err);
-            vec![]
+    fn try_from(record: KeyRecord) -> Result<Self, Self::Error> {
+        let secret_key = hex::decode(&record.secret_key)?;
+        let mut key = SigningKey::from_secret_key(&secret_key, record.state)?;
+        if key.kid != record.kid {
+            return Err(CryptoError::KeyringError(format!(
+                "key id {} does not match its secret key",
+                record.kid
+            )));
         }
+
+        key.created_at = record.created_at;
+        key.demoted_at = record.demoted_at;
+        Ok(key)
     }
 }
 
-#[allow(dead_code)]
-pub fn sign_message_sync(message: &[u8]) -> Result<Vec<u8>, CryptoError> {
-    let key_store = KEY_STORE_INSTANCE
-        .get()
-        .ok_or(CryptoError::NotInitialized)?;
+pub struct KeyStore {
+    path: PathBuf,
+    keys: RwLock<Vec<SigningKey>>,
+    last_reload: RwLock<Instant>,
+}
+
+impl KeyStore {
+    // Opens the configured keyring, a new keyring starts out with the legacy
+    // single key (.private by default) as its active key if there is one
+    pub async fn from_config(config: &KeysConfig) -> Result<Self, CryptoError> {
+        Self::open_with_legacy(&config.keyring_path, Some(&config.legacy_key_path)).await
+    }
+
+    // Opens the keyring at the given path, creating it when missing
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, CryptoError> {
+        Self::open_with_legacy(path.as_ref(), None).await
+    }
+
+    async fn open_with_legacy(
+        path: &Path,
+        legacy_key_path: Option<&Path>,
+    ) -> Result<Self, CryptoError> {
+        let path = path.to_path_buf();
+
+        let keys = if path.exists() {
+            read_keyring(&path).await?
+        } else {
+            let key = match legacy_key_path.filter(|legacy| legacy.exists()) {
+                Some(legacy_key_path) => {
+                    let secret_key = async_fs::read(legacy_key_path).await?;
+                    SigningKey::from_secret_key(&secret_key, KeyState::Active)?
+                }
+                None => SigningKey::generate(),
+            };
+
+            let keys = vec![key];
+            write_keyring(&path, &keys).await?;
+            keys
+        };
+
+        Ok(KeyStore {
+            path,
+            keys: RwLock::new(keys),
+            last_reload: RwLock::new(Instant::now()),
+        })
+    }
+
+    // Re-reads the keyring from disk, picking up rotations made by the admin
+    // command or by another instance sharing the keyring
+    pub async fn reload(&self) -> Result<(), CryptoError> {
+        let keys = read_keyring(&self.path).await?;
+        *self.keys.write().unwrap() = keys;
+        *self.last_reload.write().unwrap() = Instant::now();
+        Ok(())
+    }
+
+    pub fn keys(&self) -> Vec<SigningKey> {
+        self.keys.read().unwrap().clone()
+    }
+
+    pub fn active_key(&self) -> Result<SigningKey, CryptoError> {
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .find(|key| key.state == KeyState::Active)
+            .cloned()
+            .ok_or(CryptoError::NoActiveKey)
+    }
+
+    pub fn get_key(&self, kid: &str) -> Option<SigningKey> {
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .find(|key| key.kid == kid)
+            .cloned()
+    }
+
+    // All keys that tokens may currently be signed with, i.e. not retired
+    pub fn verifying_keys(&self) -> Vec<SigningKey> {
+        self.keys()
+            .into_iter()
+            .filter(|key| key.can_verify())
+            .collect()
+    }
 
-    match nacl::sign::sign(message, key_store.secret_key()) {
-        Ok(bytes) => Ok(bytes),
-        Err(e) => Err(CryptoError::SignError(e.message)),
+    // Signs the message with the active key, returns the key id and signature
+    pub fn sign_message(&self, message: &[u8]) -> Result<(String, Vec<u8>), CryptoError> {
+        let key = self.active_key()?;
+        let signature = key.sign(message)?;
+        Ok((key.kid, signature))
+    }
+
+    pub async fn verify_signature(&self, kid: &str, message: &[u8], signature: &[u8]) -> bool {
+        match self.find_key(kid).await {
+            Some(key) if key.can_verify() => {
+                matches!(
+                    nacl::sign::verify(signature, message, key.public_key()),
+                    Ok(true)
+                )
+            }
+            _ => false,
+        }
+    }
+
+    // Like get_key, but reloads the keyring first when the key id is unknown,
+    // since another instance may already be signing with a newly rotated key
+    async fn find_key(&self, kid: &str) -> Option<SigningKey> {
+        if let Some(key) = self.get_key(kid) {
+            return Some(key);
+        }
+
+        if self.last_reload.read().unwrap().elapsed() < RELOAD_THROTTLE {
+            return None;
+        }
+        if let Err(e) = self.reload().await {
+            eprintln!("Failed to reload keyring: {}", e);
+            return None;
+        }
+
+        self.get_key(kid)
+    }
+
+    // Generates a new active key and demotes the current active key to
+    // verify-only. Verify-only keys demoted more than `grace` ago are retired,
+    // `grace` should be at least the lifetime of an access token.
+    pub async fn rotate(&self, grace: Duration) -> Result<SigningKey, CryptoError> {
+        let mut keys = read_keyring(&self.path).await?;
+        let now = unix_secs();
+
+        for key in keys.iter_mut() {
+            match key.state {
+                KeyState::Active => {
+                    key.state = KeyState::VerifyOnly;
+                    key.demoted_at = Some(now);
+                }
+                KeyState::VerifyOnly => {
+                    let demoted_at = key.demoted_at.unwrap_or(key.created_at);
+                    if now - demoted_at >= grace.as_secs() as i64 {
+                        key.state = KeyState::Retired;
+                    }
+                }
+                KeyState::Retired => {}
+            }
+        }
+
+        let key = SigningKey::generate();
+        keys.push(key.clone());
+
+        write_keyring(&self.path, &keys).await?;
+        *self.keys.write().unwrap() = keys;
+        Ok(key)
+    }
+
+    // Retires a verify-only key ahead of its grace period, e.g. when it leaked
+    pub async fn retire(&self, kid: &str) -> Result<(), CryptoError> {
+        let mut keys = read_keyring(&self.path).await?;
+
+        let key = keys
+            .iter_mut()
+            .find(|key| key.kid == kid)
+            .ok_or_else(|| CryptoError::KeyNotFound(kid.to_string()))?;
+        if key.state == KeyState::Active {
+            return Err(CryptoError::KeyringError(
+                "the active key cannot be retired, rotate first".to_string(),
+            ));
+        }
+        key.state = KeyState::Retired;
+
+        write_keyring(&self.path, &keys).await?;
+        *self.keys.write().unwrap() = keys;
+        Ok(())
     }
 }
 
-#[allow(dead_code)]
-pub async fn verify_signature(message: &[u8], signature: &[u8]) -> bool {
-    let key_store = get_key_store().await;
-    nacl::sign::verify(message, signature, key_store.public_key()).is_ok()
+async fn read_keyring(path: &Path) -> Result<Vec<SigningKey>, CryptoError> {
+    let content = async_fs::read(path).await?;
+    let records: Vec<KeyRecord> = serde_json::from_slice(&content)?;
+
+    let keys = records
+        .into_iter()
+        .map(SigningKey::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let active = keys.iter().filter(|k| k.state == KeyState::Active).count();
+    if active != 1 {
+        return Err(CryptoError::KeyringError(format!(
+            "expected exactly one active key, found {}",
+            active
+        )));
+    }
+
+    Ok(keys)
 }
 
-pub fn verify_signature_sync(message: &[u8], signature: &[u8]) -> Result<bool, CryptoError> {
-    let key_store = KEY_STORE_INSTANCE
-        .get()
-        .ok_or(CryptoError::NotInitialized)?;
+async fn write_keyring(path: &Path, keys: &[SigningKey]) -> Result<(), CryptoError> {
+    let records: Vec<KeyRecord> = keys.iter().map(KeyRecord::from).collect();
+    let content = serde_json::to_vec_pretty(&records)?;
 
-    match nacl::sign::verify(message, signature, key_store.public_key()) {
-        Ok(_) => Ok(true),
-        Err(_) => Ok(false),
+    // Write to a temporary file first so readers never see a partial keyring.
+    // It holds secret keys, so only the owner may read it from the start.
+    let tmp_path = path.with_extension("tmp");
+    match async_fs::remove_file(&tmp_path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
     }
+    let mut options = async_fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(&tmp_path).await?;
+    file.write_all(&content).await?;
+    file.sync_all().await?;
+    async_fs::rename(&tmp_path, path).await?;
+    Ok(())
 }
 
-pub async fn get_public_key_base64() -> String {
-    let key_store = get_key_store().await;
-    let vk_base64 = general_purpose::URL_SAFE_NO_PAD.encode(key_store.public_key());
-    vk_base64
+// Key ids are the first 8 bytes of the SHA-256 of the public key, hex encoded
+fn key_id(public_key: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, public_key);
+    hex::encode(&digest.as_ref()[..8])
 }
 
-#[allow(dead_code)]
-pub fn get_public_key_base64_sync() -> String {
-    let key_store = KEY_STORE_INSTANCE.get().expect("KeyStore not initialized");
-    let vk_base64 = general_purpose::URL_SAFE_NO_PAD.encode(key_store.public_key());
-    vk_base64
+fn unix_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn temp_keyring(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "keyring-{}-{}-{}",
+            name,
+            std::process::id(),
+            unix_secs()
+        ))
+    }
+
     #[tokio::test]
     async fn test_sign_message() {
+        let path = temp_keyring("sign");
+        let key_store = KeyStore::open(&path).await.unwrap();
         let message = b"Hello, world!";
-        let signature = sign_message(message).await;
+        let (kid, signature) = key_store.sign_message(message).unwrap();
+        assert!(!kid.is_empty());
         assert!(!signature.is_empty());
+        assert!(
+            !key_store
+                .active_key()
+                .unwrap()
+                .public_key_base64()
+                .is_empty()
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_keyring_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_keyring("permissions");
+        let key_store = KeyStore::open(&path).await.unwrap();
+        key_store.rotate(Duration::from_secs(60)).await.unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        std::fs::remove_file(path).unwrap();
     }
 
     #[tokio::test]
     async fn test_verify_signature() {
+        let path = temp_keyring("verify");
+        let key_store = KeyStore::open(&path).await.unwrap();
         let message = b"Hello, world!";
-        let signature = sign_message(message).await;
-        let verified = verify_signature(message, &signature).await;
-        assert!(verified);
+        let (kid, signature) = key_store.sign_message(message).unwrap();
+        assert!(key_store.verify_signature(&kid, message, &signature).await);
+        assert!(
+            !key_store
+                .verify_signature(&kid, b"Hello, world?", &signature)
+                .await
+        );
+        assert!(
+            !key_store
+                .verify_signature("unknown", message, &signature)
+                .await
+        );
+
+        std::fs::remove_file(path).unwrap();
     }
 
     #[tokio::test]
-    async fn test_get_public_key_base64() {
-        let vk_base64 = get_public_key_base64().await;
-        assert!(!vk_base64.is_empty());
+    async fn test_rotate() {
+        let path = temp_keyring("rotate");
+        let key_store = KeyStore::open(&path).await.unwrap();
+        let first = key_store.active_key().unwrap();
+
+        let second = key_store.rotate(Duration::from_secs(3600)).await.unwrap();
+        assert_ne!(first.kid(), second.kid());
+        assert_eq!(key_store.active_key().unwrap().kid(), second.kid());
+        assert_eq!(
+            key_store.get_key(first.kid()).unwrap().state(),
+            KeyState::VerifyOnly
+        );
+
+        // Without a grace period the previous key is retired on the next rotation
+        key_store.rotate(Duration::ZERO).await.unwrap();
+        assert_eq!(
+            key_store.get_key(first.kid()).unwrap().state(),
+            KeyState::Retired
+        );
+        assert_eq!(
+            key_store.get_key(second.kid()).unwrap().state(),
+            KeyState::VerifyOnly
+        );
+
+        // Rotations are persisted
+        let reopened = KeyStore::open(&path).await.unwrap();
+        assert_eq!(reopened.keys().len(), 3);
+
+        std::fs::remove_file(path).unwrap();
     }
 }
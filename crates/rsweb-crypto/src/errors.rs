@@ -4,10 +4,15 @@ pub enum CryptoError {
     ExtractPubkeyError(String),
     SignError(String),
     IoError(std::io::Error),
-    NotInitialized,
     FromHexError(hex::FromHexError),
     IncongruentLength(usize, usize),
     ConversionError(std::array::TryFromSliceError),
+    JsonError(serde_json::Error),
+    NoActiveKey,
+    KeyNotFound(String),
+    KeyringError(String),
+    HashQueueTimeout,
+    CoseError(String),
 }
 
 impl std::fmt::Display for CryptoError {
@@ -17,7 +22,6 @@ impl std::fmt::Display for CryptoError {
             CryptoError::ExtractPubkeyError(e) => write!(f, "Extract public key error: {}", e),
             CryptoError::SignError(e) => write!(f, "Sign error: {}", e),
             CryptoError::IoError(e) => e.fmt(f),
-            CryptoError::NotInitialized => write!(f, "KeyStore not initialized"),
             CryptoError::FromHexError(e) => e.fmt(f),
             CryptoError::IncongruentLength(expected, actual) => {
                 write!(
@@ -27,6 +31,12 @@ impl std::fmt::Display for CryptoError {
                 )
             }
             CryptoError::ConversionError(e) => e.fmt(f),
+            CryptoError::JsonError(e) => e.fmt(f),
+            CryptoError::NoActiveKey => write!(f, "Keyring has no active key"),
+            CryptoError::KeyNotFound(kid) => write!(f, "Key not found: {}", kid),
+            CryptoError::KeyringError(e) => write!(f, "Keyring error: {}", e),
+            CryptoError::HashQueueTimeout => write!(f, "Timed out waiting for a hashing slot"),
+            CryptoError::CoseError(e) => write!(f, "COSE error: {}", e),
         }
     }
 }
@@ -50,3 +60,21 @@ impl From<std::array::TryFromSliceError> for CryptoError {
         CryptoError::ConversionError(e)
     }
 }
+
+impl From<serde_json::Error> for CryptoError {
+    fn from(e: serde_json::Error) -> Self {
+        CryptoError::JsonError(e)
+    }
+}
+
+impl From<argon2::password_hash::Error> for CryptoError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        CryptoError::HashError(e.to_string())
+    }
+}
+
+impl From<argon2::Error> for CryptoError {
+    fn from(e: argon2::Error) -> Self {
+        CryptoError::HashError(e.to_string())
+    }
+}
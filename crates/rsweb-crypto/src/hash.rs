@@ -1,44 +1,77 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rsweb_config::PasswordHashConfig;
+use scrypt::Scrypt;
+use tokio::sync::Semaphore;
+
 use crate::errors::CryptoError;
 
-fn hash_progress_callback(progress: u32) {
-    eprintln!("Hashing progress: {}%", progress);
+// Hashes a password into a PHC string, which carries the algorithm,
+// parameters and salt so they can change without a schema change
+fn hash_password(params: Params, password: &[u8]) -> Result<String, CryptoError> {
+    let salt = SaltString::encode_b64(&super::generate::generate_salt())?;
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+    Ok(argon2.hash_password(password, &salt)?.to_string())
 }
 
-pub fn hash_password(password: &[u8]) -> Result<(String, String), CryptoError> {
-    let salt_bytes = super::generate::generate_salt();
-    let digest = match nacl::scrypt(
-        password,
-        &salt_bytes,
-        10, // u8::pow(2, 15),
-        8,
-        16,
-        64,
-        &hash_progress_callback,
-    ) {
-        Ok(d) => d,
-        Err(e) => {
-            return Err(CryptoError::HashError(e.message));
-        }
+// Verifies a password against a PHC string (Argon2 or scrypt), or against a
+// legacy hex scrypt digest when the separate salt column is still set
+pub fn verify_password(
+    password: &[u8],
+    stored_hash: &str,
+    legacy_salt: Option<&str>,
+) -> Result<bool, CryptoError> {
+    if !stored_hash.starts_with('$') {
+        return match legacy_salt {
+            Some(salt) => verify_legacy_scrypt(password, stored_hash, salt),
+            None => Err(CryptoError::HashError("missing legacy salt".to_string())),
+        };
+    }
+
+    let parsed = PasswordHash::new(stored_hash)?;
+    match parsed.verify_password(&[&Argon2::default(), &Scrypt], password) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn needs_rehash(current: &Params, stored_hash: &str) -> bool {
+    let parsed = match PasswordHash::new(stored_hash) {
+        Ok(parsed) => parsed,
+        Err(_) => return true,
     };
+    if parsed.algorithm != Algorithm::Argon2id.ident()
+        || parsed.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
 
-    Ok((hex::encode(digest), hex::encode(salt_bytes)))
+    match Params::try_from(&parsed) {
+        Ok(stored) => {
+            stored.m_cost() != current.m_cost()
+                || stored.t_cost() != current.t_cost()
+                || stored.p_cost() != current.p_cost()
+        }
+        Err(_) => true,
+    }
 }
 
-pub fn cmp_password_hash(
-    input_pwd: &str,
+fn no_progress(_progress: u32) {}
+
+// Hashes from before the PHC format, hex scrypt digest with a hex salt
+fn verify_legacy_scrypt(
+    password: &[u8],
     cmp_hash: &str,
     cmp_salt: &str,
 ) -> Result<bool, CryptoError> {
     let salt_bytes = hex::decode(cmp_salt)?;
-    let digest = match nacl::scrypt(
-        input_pwd.as_bytes(),
-        &salt_bytes,
-        10, // u8::pow(2, 15),
-        8,
-        16,
-        64,
-        &hash_progress_callback,
-    ) {
+    let digest = match nacl::scrypt(password, &salt_bytes, 10, 8, 16, 64, &no_progress) {
         Ok(d) => d,
         Err(e) => {
             return Err(CryptoError::HashError(e.message));
@@ -49,22 +82,226 @@ pub fn cmp_password_hash(
     Ok(nacl::compare(&digest, &hash_bytes))
 }
 
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HashMetrics {
+    pub completed: u64,
+    pub queue_timeouts: u64,
+    pub queue_wait: Duration,
+    pub hash_time: Duration,
+}
+
+#[derive(Default)]
+struct HashCounters {
+    completed: AtomicU64,
+    queue_timeouts: AtomicU64,
+    queue_wait_us: AtomicU64,
+    hash_time_us: AtomicU64,
+}
+
+// Hashing is CPU bound, so it runs on the blocking pool behind a semaphore
+// which caps how many worker threads login traffic can occupy at once
+pub struct Hasher {
+    params: Params,
+    slots: Arc<Semaphore>,
+    queue_timeout: Duration,
+    counters: Arc<HashCounters>,
+}
+
+impl Hasher {
+    pub fn new(config: &PasswordHashConfig) -> Result<Self, CryptoError> {
+        let params = Params::new(
+            config.memory_kib,
+            config.iterations,
+            config.parallelism,
+            None,
+        )?;
+        let default = std::thread::available_parallelism().map_or(1, |n| n.get() as u32);
+        let slots = config.concurrency.unwrap_or(default).max(1);
+
+        Ok(Hasher {
+            params,
+            slots: Arc::new(Semaphore::new(slots as usize)),
+            queue_timeout: config.queue_timeout,
+            counters: Arc::default(),
+        })
+    }
+
+    pub fn hash_password(&self, password: &[u8]) -> Result<String, CryptoError> {
+        hash_password(self.params.clone(), password)
+    }
+
+    // Whether a verified hash should be replaced by one with the current
+    // algorithm and parameters
+    pub fn needs_rehash(&self, stored_hash: &str) -> bool {
+        needs_rehash(&self.params, stored_hash)
+    }
+
+    pub async fn hash_password_async(&self, password: String) -> Result<String, CryptoError> {
+        let params = self.params.clone();
+        self.run(move || hash_password(params, password.as_bytes()))
+            .await
+    }
+
+    pub async fn verify_password_async(
+        &self,
+        password: String,
+        stored_hash: String,
+        legacy_salt: Option<String>,
+    ) -> Result<bool, CryptoError> {
+        self.run(move || verify_password(password.as_bytes(), &stored_hash, legacy_salt.as_deref()))
+            .await
+    }
+
+    // Totals since startup of the hashes run through the async methods
+    pub fn metrics(&self) -> HashMetrics {
+        let counters = &self.counters;
+        HashMetrics {
+            completed: counters.completed.load(Ordering::Relaxed),
+            queue_timeouts: counters.queue_timeouts.load(Ordering::Relaxed),
+            queue_wait: Duration::from_micros(counters.queue_wait_us.load(Ordering::Relaxed)),
+            hash_time: Duration::from_micros(counters.hash_time_us.load(Ordering::Relaxed)),
+        }
+    }
+
+    async fn run<T, F>(&self, f: F) -> Result<T, CryptoError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, CryptoError> + Send + 'static,
+    {
+        let queued_at = Instant::now();
+        let permit = match tokio::time::timeout(
+            self.queue_timeout,
+            self.slots.clone().acquire_owned(),
+        )
+        .await
+        {
+            Ok(Ok(permit)) => permit,
+            Ok(Err(e)) => return Err(CryptoError::HashError(e.to_string())),
+            Err(_) => {
+                self.counters.queue_timeouts.fetch_add(1, Ordering::Relaxed);
+                return Err(CryptoError::HashQueueTimeout);
+            }
+        };
+        self.counters
+            .queue_wait_us
+            .fetch_add(queued_at.elapsed().as_micros() as u64, Ordering::Relaxed);
+
+        // The permit moves into the task so the slot stays taken even if the
+        // request is dropped while hashing
+        let counters = self.counters.clone();
+        tokio::task::spawn_blocking(move || {
+            let started_at = Instant::now();
+            let result = f();
+            drop(permit);
+
+            counters.completed.fetch_add(1, Ordering::Relaxed);
+            counters
+                .hash_time_us
+                .fetch_add(started_at.elapsed().as_micros() as u64, Ordering::Relaxed);
+            result
+        })
+        .await
+        .map_err(|e| CryptoError::HashError(e.to_string()))?
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn hasher() -> Hasher {
+        Hasher::new(&PasswordHashConfig {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+            concurrency: Some(1),
+            queue_timeout: Duration::from_secs(5),
+        })
+        .unwrap()
+    }
+
+    fn legacy_hash(password: &[u8]) -> (String, String) {
+        let salt_bytes = crate::generate::generate_salt();
+        let digest = nacl::scrypt(password, &salt_bytes, 10, 8, 16, 64, &no_progress).unwrap();
+        (hex::encode(digest), hex::encode(salt_bytes))
+    }
+
     #[test]
     fn test_hash_password() {
-        let password = "password";
-        let (hash, salt) = hash_password(password.as_bytes()).unwrap();
-        assert_eq!(hash.len(), 128);
-        assert_eq!(salt.len(), 64);
+        let hasher = hasher();
+        let hash = hasher.hash_password(b"password").unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$"));
+        assert!(!hasher.needs_rehash(&hash));
     }
 
     #[test]
-    fn test_cmp_password_hash() {
-        let password = "password";
-        let (hash, salt) = hash_password(password.as_bytes()).unwrap();
-        assert_eq!(cmp_password_hash(password, &hash, &salt).unwrap(), true);
+    fn test_verify_password() {
+        let hash = hasher().hash_password(b"password").unwrap();
+        assert!(verify_password(b"password", &hash, None).unwrap());
+        assert!(!verify_password(b"wrong", &hash, None).unwrap());
+    }
+
+    #[test]
+    fn test_verify_legacy_scrypt() {
+        let (hash, salt) = legacy_hash(b"password");
+        assert!(verify_password(b"password", &hash, Some(&salt)).unwrap());
+        assert!(!verify_password(b"wrong", &hash, Some(&salt)).unwrap());
+        assert!(verify_password(b"password", &hash, None).is_err());
+        assert!(hasher().needs_rehash(&hash));
+    }
+
+    #[test]
+    fn test_verify_phc_scrypt() {
+        let salt = SaltString::encode_b64(&crate::generate::generate_salt()).unwrap();
+        let params = scrypt::Params::new(10, 8, 1, 32).unwrap();
+        let hash = Scrypt
+            .hash_password_customized(b"password", None, None, params, &salt)
+            .unwrap()
+            .to_string();
+
+        assert!(verify_password(b"password", &hash, None).unwrap());
+        assert!(hasher().needs_rehash(&hash));
+    }
+
+    #[test]
+    fn test_needs_rehash_on_changed_params() {
+        let salt = SaltString::encode_b64(&crate::generate::generate_salt()).unwrap();
+        let params = Params::new(8 * 1024, 1, 1, None).unwrap();
+        let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(b"password", &salt)
+            .unwrap()
+            .to_string();
+
+        assert!(verify_password(b"password", &hash, None).unwrap());
+        assert!(hasher().needs_rehash(&hash));
+    }
+
+    #[tokio::test]
+    async fn test_async_roundtrip() {
+        let hasher = hasher();
+        let hash = hasher
+            .hash_password_async("password".to_string())
+            .await
+            .unwrap();
+        assert!(
+            hasher
+                .verify_password_async("password".to_string(), hash, None)
+                .await
+                .unwrap()
+        );
+        assert_eq!(hasher.metrics().completed, 2);
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout() {
+        let hasher = Hasher {
+            queue_timeout: Duration::from_millis(10),
+            ..hasher()
+        };
+        let _held = hasher.slots.clone().acquire_owned().await.unwrap();
+
+        let result = hasher.run(|| Ok(())).await;
+        assert!(matches!(result, Err(CryptoError::HashQueueTimeout)));
+        assert_eq!(hasher.metrics().queue_timeouts, 1);
     }
 }
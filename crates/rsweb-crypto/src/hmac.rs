@@ -0,0 +1,90 @@
+use rand::RngCore;
+use std::path::Path;
+use tokio::fs as async_fs;
+
+use crate::errors::CryptoError;
+
+const SECRET_LENGTH: usize = 32;
+
+pub struct HmacKey {
+    key: ring::hmac::Key,
+}
+
+impl HmacKey {
+    pub fn new(secret: &[u8]) -> Self {
+        HmacKey {
+            key: ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret),
+        }
+    }
+
+    // Reads the server secret, creating it on first start
+    pub async fn open(key_path: &Path) -> Result<Self, CryptoError> {
+        let secret = if key_path.exists() {
+            let secret = async_fs::read(key_path).await?;
+            if secret.len() != SECRET_LENGTH {
+                return Err(CryptoError::IncongruentLength(SECRET_LENGTH, secret.len()));
+            }
+            secret
+        } else {
+            let secret = Self::generate_secret();
+            async_fs::write(key_path, &secret).await?;
+            secret
+        };
+
+        Ok(HmacKey::new(&secret))
+    }
+
+    // A key that only lives as long as the process, for tests
+    pub fn ephemeral() -> Self {
+        HmacKey::new(&Self::generate_secret())
+    }
+
+    fn generate_secret() -> Vec<u8> {
+        let mut secret = vec![0u8; SECRET_LENGTH];
+        rand::thread_rng().fill_bytes(&mut secret);
+        secret
+    }
+
+    // Hex encoded HMAC-SHA256 of the message under the server secret, used to
+    // store bearer secrets such as refresh tokens without keeping them in plaintext
+    pub fn hash(&self, message: &[u8]) -> String {
+        hex::encode(ring::hmac::sign(&self.key, message).as_ref())
+    }
+
+    // Constant time check of a tag produced by hash, for secrets that are
+    // handed out signed rather than stored
+    pub fn verify(&self, message: &[u8], hex_tag: &str) -> bool {
+        match hex::decode(hex_tag) {
+            Ok(tag) => ring::hmac::verify(&self.key, message, &tag).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyed_hash() {
+        let key = HmacKey::ephemeral();
+        let hash = key.hash(b"Hello, world!");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, key.hash(b"Hello, world!"));
+        assert_ne!(hash, key.hash(b"Hello, world?"));
+        assert_ne!(hash, HmacKey::ephemeral().hash(b"Hello, world!"));
+
+        assert!(key.verify(b"Hello, world!", &hash));
+        assert!(!key.verify(b"Hello, world?", &hash));
+        assert!(!key.verify(b"Hello, world!", "not hex"));
+    }
+
+    #[tokio::test]
+    async fn test_open_keeps_secret() {
+        let path = std::env::temp_dir().join(format!("rsweb-hmac-{}", std::process::id()));
+        let hash = HmacKey::open(&path).await.unwrap().hash(b"message");
+        assert_eq!(HmacKey::open(&path).await.unwrap().hash(b"message"), hash);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
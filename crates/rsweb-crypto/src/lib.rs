@@ -1,5 +1,8 @@
 pub mod cast;
+pub mod cose;
 pub mod ed25519;
 pub mod errors;
 pub mod generate;
 pub mod hash;
+pub mod hmac;
+pub mod totp;
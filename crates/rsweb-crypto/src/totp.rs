@@ -0,0 +1,183 @@
+use rand::RngCore;
+
+// RFC 6238 time-based one-time passwords with the parameters every
+// authenticator app supports: HMAC-SHA1, 6 digits and 30 second steps
+
+pub const DIGITS: usize = 6;
+pub const STEP_SECS: i64 = 30;
+// Steps accepted before and after the current one to allow for clock drift
+const SKEW: i64 = 1;
+const SECRET_LENGTH: usize = 20;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// Unpadded RFC 4648 base32, the encoding otpauth URIs use for secrets
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+
+    out
+}
+
+// Accepts lowercase, spaces and padding as people copy secrets around
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in encoded.chars().filter(|c| *c != ' ' && *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+// A new random secret, base32 encoded
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_LENGTH];
+    rand::thread_rng().fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+// RFC 4226 HOTP value for the counter
+fn hotp(secret: &[u8], counter: u64) -> String {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = ring::hmac::sign(&key, &counter.to_be_bytes());
+    let digest = tag.as_ref();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS as u32),
+        width = DIGITS
+    )
+}
+
+pub fn step(unix_secs: i64) -> i64 {
+    unix_secs.div_euclid(STEP_SECS)
+}
+
+// Code for the step the given time falls into
+pub fn code_at(secret: &str, unix_secs: i64) -> Option<String> {
+    let secret = base32_decode(secret)?;
+    Some(hotp(&secret, step(unix_secs) as u64))
+}
+
+// Checks the code against the current step and its neighbours, returns the
+// matching step so callers can refuse to accept it a second time
+pub fn verify(secret: &str, code: &str, unix_secs: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secret = base32_decode(secret)?;
+    let current = step(unix_secs);
+
+    (current - SKEW..=current + SKEW)
+        .filter(|s| *s >= 0)
+        .find(|s| constant_time_eq(hotp(&secret, *s as u64).as_bytes(), code.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Key URI understood by authenticator apps, usually shown as a QR code
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer),
+        DIGITS,
+        STEP_SECS
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Secret of the SHA1 test vectors in RFC 6238 appendix B
+    fn rfc_secret() -> String {
+        base32_encode(b"12345678901234567890")
+    }
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(base32_encode(b"Hello!\xde\xad\xbe\xef"), "JBSWY3DPEHPK3PXP");
+        assert_eq!(
+            base32_decode("jbsw y3dp ehpk 3pxp").unwrap(),
+            b"Hello!\xde\xad\xbe\xef"
+        );
+        assert_eq!(base32_decode(&base32_encode(b"abc")).unwrap(), b"abc");
+        assert!(base32_decode("not base32!").is_none());
+    }
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        // The RFC lists 8 digit codes, 6 digit codes are their last digits
+        assert_eq!(code_at(&rfc_secret(), 59).unwrap(), "287082");
+        assert_eq!(code_at(&rfc_secret(), 1111111109).unwrap(), "081804");
+        assert_eq!(code_at(&rfc_secret(), 2000000000).unwrap(), "279037");
+    }
+
+    #[test]
+    fn test_verify_skew() {
+        let secret = generate_secret();
+        let now = 1_700_000_000;
+        let code = code_at(&secret, now).unwrap();
+
+        assert_eq!(verify(&secret, &code, now), Some(step(now)));
+        assert_eq!(verify(&secret, &code, now + STEP_SECS), Some(step(now)));
+        assert_eq!(verify(&secret, &code, now + 3 * STEP_SECS), None);
+        assert_eq!(verify(&secret, "12345", now), None);
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        let uri = otpauth_uri("rsweb", "user@example.com", "JBSWY3DPEHPK3PXP");
+        assert!(uri.starts_with("otpauth://totp/rsweb:user%40example.com?secret=JBSWY3DPEHPK3PXP"));
+    }
+}
@@ -1,4 +1,4 @@
-use super::get_db;
+use rsweb_state::AppState;
 use sqlx::types::time::PrimitiveDateTime;
 
 #[derive(Debug, sqlx::FromRow)]
@@ -7,12 +7,14 @@ pub struct UserEssentials {
     pub email: String,
     pub handle: String,
     pub role: String,
+    pub email_verified: bool,
 }
 
 #[derive(Debug, sqlx::FromRow)]
 pub struct UserDetails {
     pub id: i32,
     pub email: String,
+    pub email_verified_at: Option<PrimitiveDateTime>,
     pub password: Option<String>,
     pub password_salt: Option<String>,
     pub handle: String,
@@ -23,9 +25,10 @@ pub struct UserDetails {
 }
 
 #[derive(Debug, sqlx::FromRow)]
-pub struct GoogleUserDetails {
+pub struct IdentityUserDetails {
     pub id: i32,
     pub email: String,
+    pub email_verified_at: Option<PrimitiveDateTime>,
     pub handle: String,
     pub role: String,
     pub banned: bool,
@@ -33,149 +36,779 @@ pub struct GoogleUserDetails {
     pub banned_at: Option<PrimitiveDateTime>,
 }
 
+#[derive(Debug, sqlx::FromRow)]
+pub struct RefreshTokenDetails {
+    pub id: i32,
+    pub family_id: String,
+    pub revoked_at: Option<PrimitiveDateTime>,
+    // Replaced by a successor within the grace period passed to the query
+    pub recently_rotated: bool,
+    pub user_id: i32,
+    pub email: String,
+    pub handle: String,
+    pub role: String,
+    pub email_verified: bool,
+    pub banned: bool,
+}
+
+impl RefreshTokenDetails {
+    pub fn user_essentials(&self) -> UserEssentials {
+        UserEssentials {
+            id: self.user_id,
+            email: self.email.clone(),
+            handle: self.handle.clone(),
+            role: self.role.clone(),
+            email_verified: self.email_verified,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct SessionDetails {
+    pub family_id: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub signed_in_at: PrimitiveDateTime,
+    pub last_used_at: PrimitiveDateTime,
+    pub current: bool,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct UserTotp {
+    pub secret: String,
+    pub confirmed_at: Option<PrimitiveDateTime>,
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct WebauthnCredential {
+    pub id: i32,
+    pub user_id: i32,
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub algorithm: i32,
+    pub sign_count: i64,
+    pub name: String,
+    pub created_at: PrimitiveDateTime,
+    pub last_used_at: Option<PrimitiveDateTime>,
+}
+
 pub struct UserService;
 
 impl UserService {
     pub async fn insert_user_email(
+        state: &AppState,
         email: &str,
         password: &str,
-        salt: &str,
         username: &str,
     ) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
-        let db = get_db().await;
         let result = sqlx::query!(
-            "INSERT INTO users (email, password, password_salt, handle) VALUES ($1, $2, $3, $4) RETURNING id",
+            "INSERT INTO users (email, password, handle) VALUES ($1, $2, $3) RETURNING id",
             email,
             password,
-            salt,
             username
         )
-        .fetch_one(&db.pool)
+        .fetch_one(&state.db)
         .await?;
 
         Ok(result.id)
     }
 
-    pub async fn insert_user_google(
-        google_sub: &str,
+    // Creates a user that signs in through an OpenID Connect provider
+    pub async fn insert_user_identity(
+        state: &AppState,
+        provider: &str,
+        subject: &str,
         email: &str,
         username: &str,
+        email_verified: bool,
     ) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
-        let db = get_db().await;
+        let mut tx = state.db.begin().await?;
+
         let result = sqlx::query!(
-            "INSERT INTO users (google_sub, email, handle) VALUES ($1, $2, $3) RETURNING id",
-            google_sub,
+            "INSERT INTO users (email, handle, email_verified_at) VALUES ($1, $2, CASE WHEN $3 THEN CURRENT_TIMESTAMP END) RETURNING id",
             email,
-            username
+            username,
+            email_verified
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO users_identities (user_id, provider, subject) VALUES ($1, $2, $3)",
+            result.id,
+            provider,
+            subject
         )
-        .fetch_one(&db.pool)
+        .execute(&mut *tx)
         .await?;
 
+        tx.commit().await?;
         Ok(result.id)
     }
 
     pub async fn get_user_details(
+        state: &AppState,
         email: &str,
     ) -> Result<UserDetails, Box<dyn std::error::Error + Send + Sync>> {
-        let db = get_db().await;
         let result = sqlx::query_as!(
             UserDetails,
-            "SELECT u.id, u.email, u.password, u.password_salt, u.handle, u.role, u.banned, u.banned_at, u.ban_reason FROM users u WHERE u.email = $1",
+            "SELECT u.id, u.email, u.email_verified_at, u.password, u.password_salt, u.handle, u.role, u.banned, u.banned_at, u.ban_reason FROM users u WHERE u.email = $1",
             email
         )
-        .fetch_one(&db.pool)
+        .fetch_one(&state.db)
         .await?;
 
         Ok(result)
     }
 
-    pub async fn get_google_user_details(
-        sub: &str,
-    ) -> Result<GoogleUserDetails, Box<dyn std::error::Error + Send + Sync>> {
-        let db = get_db().await;
+    pub async fn get_identity_user_details(
+        state: &AppState,
+        provider: &str,
+        subject: &str,
+    ) -> Result<IdentityUserDetails, Box<dyn std::error::Error + Send + Sync>> {
         let result = sqlx::query_as!(
-            GoogleUserDetails,
-            "SELECT u.id, u.email, u.handle, u.role, u.banned, u.banned_at, u.ban_reason FROM users u WHERE u.google_sub = $1",
-            sub
+            IdentityUserDetails,
+            "SELECT u.id, u.email, u.email_verified_at, u.handle, u.role, u.banned, u.banned_at, u.ban_reason FROM users u JOIN users_identities i ON i.user_id = u.id WHERE i.provider = $1 AND i.subject = $2",
+            provider,
+            subject
         )
-        .fetch_one(&db.pool)
+        .fetch_one(&state.db)
         .await?;
 
         Ok(result)
     }
 
-    pub async fn get_user_essentials_by_refresh_token(
-        refresh_token: &str,
-    ) -> Result<UserEssentials, Box<dyn std::error::Error + Send + Sync>> {
-        let db = get_db().await;
+    pub async fn get_refresh_token_details(
+        state: &AppState,
+        token_hash: &str,
+        grace_secs: i32,
+    ) -> Result<RefreshTokenDetails, Box<dyn std::error::Error + Send + Sync>> {
         let result = sqlx::query_as!(
-            UserEssentials,
-            "SELECT u.id, u.email, u.handle, u.role FROM users u JOIN refresh_tokens r ON u.id = r.user_id WHERE r.token = $1",
-            refresh_token
+            RefreshTokenDetails,
+            r#"SELECT r.id, r.family_id, r.revoked_at, COALESCE(r.revoked_at > CURRENT_TIMESTAMP - $2::INT * INTERVAL '1 second' AND EXISTS (SELECT 1 FROM refresh_tokens c WHERE c.parent_id = r.id), FALSE) AS "recently_rotated!", u.id AS user_id, u.email, u.handle, u.role, u.email_verified_at IS NOT NULL AS "email_verified!", u.banned FROM refresh_tokens r JOIN users u ON u.id = r.user_id WHERE r.token_hash = $1 AND r.expires_at > CURRENT_TIMESTAMP"#,
+            token_hash,
+            grace_secs
         )
-        .fetch_one(&db.pool)
+        .fetch_one(&state.db)
         .await?;
 
         Ok(result)
     }
 
     pub async fn insert_user_refresh_token(
+        state: &AppState,
         user_id: i32,
-        token: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let db = get_db().await;
+        family_id: &str,
+        token_hash: &str,
+        lifetime_secs: i32,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+    ) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at, user_agent, ip_address) VALUES ($1, $2, $3, CURRENT_TIMESTAMP + $4::INT * INTERVAL '1 second', $5, $6) RETURNING id",
+            user_id,
+            family_id,
+            token_hash,
+            lifetime_secs,
+            user_agent,
+            ip_address
+        )
+        .fetch_one(&state.db)
+        .await?;
+
+        Ok(result.id)
+    }
+
+    // Revokes a refresh token and inserts its successor into the same family
+    // in one transaction, which makes the session's last use now. Returns
+    // false without inserting anything if the token was already revoked, e.g.
+    // by a concurrent rotation.
+    pub async fn rotate_refresh_token(
+        state: &AppState,
+        token_id: i32,
+        token_hash: &str,
+        lifetime_secs: i32,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut tx = state.db.begin().await?;
+
+        let revoked = sqlx::query!(
+            "UPDATE refresh_tokens SET revoked_at = CURRENT_TIMESTAMP, last_used_at = CURRENT_TIMESTAMP WHERE id = $1 AND revoked_at IS NULL RETURNING user_id, family_id",
+            token_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let revoked = match revoked {
+            Some(revoked) => revoked,
+            None => return Ok(false),
+        };
+
         sqlx::query!(
-            "INSERT INTO refresh_tokens (user_id, token) VALUES ($1, $2)",
+            "INSERT INTO refresh_tokens (user_id, family_id, parent_id, token_hash, expires_at, user_agent, ip_address, last_used_at) VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP + $5::INT * INTERVAL '1 second', $6, $7, CURRENT_TIMESTAMP)",
+            revoked.user_id,
+            revoked.family_id,
+            token_id,
+            token_hash,
+            lifetime_secs,
+            user_agent,
+            ip_address
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    pub async fn revoke_refresh_token_family(
+        state: &AppState,
+        family_id: &str,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "UPDATE refresh_tokens SET revoked_at = CURRENT_TIMESTAMP WHERE family_id = $1 AND revoked_at IS NULL",
+            family_id
+        )
+        .execute(&state.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete_refresh_token_family(
+        state: &AppState,
+        user_id: i32,
+        token_hash: &str,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "DELETE FROM refresh_tokens WHERE user_id = $1 AND family_id = (SELECT family_id FROM refresh_tokens WHERE token_hash = $2)",
+            user_id,
+            token_hash
+        )
+        .execute(&state.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // One row per live session: the unrotated token of each family, current
+    // marks the family the given token belongs to
+    pub async fn get_user_sessions(
+        state: &AppState,
+        user_id: i32,
+        token_hash: &str,
+    ) -> Result<Vec<SessionDetails>, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query_as!(
+            SessionDetails,
+            r#"SELECT r.family_id, r.user_agent, r.ip_address, (SELECT MIN(f.created_at) FROM refresh_tokens f WHERE f.family_id = r.family_id) AS "signed_in_at!", r.last_used_at, COALESCE(r.family_id = (SELECT c.family_id FROM refresh_tokens c WHERE c.token_hash = $2), FALSE) AS "current!" FROM refresh_tokens r WHERE r.user_id = $1 AND r.revoked_at IS NULL AND r.expires_at > CURRENT_TIMESTAMP ORDER BY r.last_used_at DESC"#,
+            user_id,
+            token_hash
+        )
+        .fetch_all(&state.db)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn delete_refresh_token_family_by_id(
+        state: &AppState,
+        user_id: i32,
+        family_id: &str,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "DELETE FROM refresh_tokens WHERE user_id = $1 AND family_id = $2",
             user_id,
-            token
+            family_id
         )
-        .execute(&db.pool)
+        .execute(&state.db)
         .await?;
 
-        Ok(())
+        Ok(result.rows_affected())
     }
 
     pub async fn delete_user_refresh_token(
+        state: &AppState,
         user_id: i32,
     ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
-        let db = get_db().await;
         let result = sqlx::query!("DELETE FROM refresh_tokens WHERE user_id = $1", user_id)
-            .execute(&db.pool)
+            .execute(&state.db)
             .await?;
 
         Ok(result.rows_affected())
     }
 
-    #[allow(dead_code)]
     pub async fn handle_exists(
+        state: &AppState,
         handle: &str,
     ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        let db = get_db().await;
         let result = sqlx::query!(
             "SELECT EXISTS(SELECT 1 FROM users WHERE handle = $1) AS exists",
             handle
         )
-        .fetch_one(&db.pool)
+        .fetch_one(&state.db)
         .await?;
 
         let exists = matches!(result.exists, Some(true));
         Ok(exists)
     }
 
-    #[allow(dead_code)]
+    pub async fn delete_expired_refresh_tokens(
+        state: &AppState,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result =
+            sqlx::query!("DELETE FROM refresh_tokens WHERE expires_at <= CURRENT_TIMESTAMP")
+                .execute(&state.db)
+                .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn email_exists(
+        state: &AppState,
+        email: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE email = $1) AS exists",
+            email
+        )
+        .fetch_one(&state.db)
+        .await?;
+
+        let exists = matches!(result.exists, Some(true));
+        Ok(exists)
+    }
+
+    pub async fn identity_exists(
+        state: &AppState,
+        provider: &str,
+        subject: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "SELECT EXISTS(SELECT 1 FROM users_identities WHERE provider = $1 AND subject = $2) AS exists",
+            provider,
+            subject
+        )
+        .fetch_one(&state.db)
+        .await?;
+
+        let exists = matches!(result.exists, Some(true));
+        Ok(exists)
+    }
+
+    // Bans (or unbans) the user. Outstanding access tokens of a banned user
+    // stop working immediately and refreshing them is refused while banned.
     pub async fn update_user_banned_status(
+        state: &AppState,
         user_id: i32,
         banned: bool,
         reason: Option<&str>,
     ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
-        let db = get_db().await;
         let result = sqlx::query!(
             "UPDATE users SET banned = $1, ban_reason = $2, banned_at = CURRENT_TIMESTAMP WHERE id = $3",
             banned,
             reason,
             user_id
         )
-        .execute(&db.pool)
+        .execute(&state.db)
+        .await?;
+
+        if banned {
+            Self::revoke_access_tokens(state, user_id).await?;
+        }
+        Ok(result.rows_affected())
+    }
+
+    // Outstanding access tokens carry the old role, they are replaced through
+    // their refresh token on the next request
+    pub async fn update_user_role(
+        state: &AppState,
+        user_id: i32,
+        role: &str,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!("UPDATE users SET role = $1 WHERE id = $2", role, user_id)
+            .execute(&state.db)
+            .await?;
+
+        Self::revoke_access_tokens(state, user_id).await?;
+        Ok(result.rows_affected())
+    }
+
+    // Revokes every access token issued to the user up to now
+    async fn revoke_access_tokens(
+        state: &AppState,
+        user_id: i32,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        state
+            .revocations
+            .revoke_user_tokens(
+                user_id,
+                now.as_secs() as i64,
+                state.config.tokens.access_token_lifetime.as_secs(),
+            )
+            .await
+    }
+
+    // Replaces the password hash, dropping the legacy salt along with it
+    pub async fn update_user_password(
+        state: &AppState,
+        user_id: i32,
+        password: &str,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "UPDATE users SET password = $1, password_salt = NULL WHERE id = $2",
+            password,
+            user_id
+        )
+        .execute(&state.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // Marks the address as verified as long as it is still the user's, a
+    // repeated verification keeps the original timestamp
+    pub async fn mark_email_verified(
+        state: &AppState,
+        user_id: i32,
+        email: &str,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP) WHERE id = $1 AND email = $2",
+            user_id,
+            email
+        )
+        .execute(&state.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn insert_password_reset(
+        state: &AppState,
+        user_id: i32,
+        token_hash: &str,
+        lifetime_secs: i32,
+    ) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "INSERT INTO password_resets (user_id, token_hash, expires_at) VALUES ($1, $2, CURRENT_TIMESTAMP + $3::INT * INTERVAL '1 second') RETURNING id",
+            user_id,
+            token_hash,
+            lifetime_secs
+        )
+        .fetch_one(&state.db)
+        .await?;
+
+        Ok(result.id)
+    }
+
+    pub async fn password_reset_exists(
+        state: &AppState,
+        token_hash: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            r#"SELECT EXISTS(SELECT 1 FROM password_resets WHERE token_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP) AS "exists!""#,
+            token_hash
+        )
+        .fetch_one(&state.db)
+        .await?;
+
+        Ok(result.exists)
+    }
+
+    // Redeems a reset token and replaces the password in one transaction.
+    // Every other reset token and refresh token of the user is invalidated
+    // too. Returns None if the token is unknown, used or expired.
+    pub async fn reset_user_password(
+        state: &AppState,
+        token_hash: &str,
+        password: &str,
+    ) -> Result<Option<UserEssentials>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut tx = state.db.begin().await?;
+
+        let reset = sqlx::query!(
+            "UPDATE password_resets SET used_at = CURRENT_TIMESTAMP WHERE token_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP RETURNING user_id",
+            token_hash
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let user_id = match reset {
+            Some(reset) => reset.user_id,
+            None => return Ok(None),
+        };
+
+        let essentials = sqlx::query_as!(
+            UserEssentials,
+            r#"UPDATE users SET password = $1, password_salt = NULL WHERE id = $2 RETURNING id, email, handle, role, email_verified_at IS NOT NULL AS "email_verified!""#,
+            password,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE password_resets SET used_at = CURRENT_TIMESTAMP WHERE user_id = $1 AND used_at IS NULL",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!("DELETE FROM refresh_tokens WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(Some(essentials))
+    }
+
+    pub async fn delete_expired_password_resets(
+        state: &AppState,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result =
+            sqlx::query!("DELETE FROM password_resets WHERE expires_at <= CURRENT_TIMESTAMP")
+                .execute(&state.db)
+                .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_user_totp(
+        state: &AppState,
+        user_id: i32,
+    ) -> Result<Option<UserTotp>, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query_as!(
+            UserTotp,
+            "SELECT secret, confirmed_at, last_used_step FROM user_totp WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&state.db)
+        .await?;
+
+        Ok(result)
+    }
+
+    // Stores a new unconfirmed secret, replacing an earlier unconfirmed one.
+    // Returns 0 if the user already has a confirmed secret.
+    pub async fn upsert_user_totp_secret(
+        state: &AppState,
+        user_id: i32,
+        secret: &str,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "INSERT INTO user_totp (user_id, secret) VALUES ($1, $2) ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, last_used_step = NULL, created_at = CURRENT_TIMESTAMP WHERE user_totp.confirmed_at IS NULL",
+            user_id,
+            secret
+        )
+        .execute(&state.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // Confirms the pending secret and replaces the recovery codes of the user
+    // in one transaction, returns false if there was nothing to confirm
+    pub async fn confirm_user_totp(
+        state: &AppState,
+        user_id: i32,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut tx = state.db.begin().await?;
+
+        let confirmed = sqlx::query!(
+            "UPDATE user_totp SET confirmed_at = CURRENT_TIMESTAMP, last_used_step = $2 WHERE user_id = $1 AND confirmed_at IS NULL",
+            user_id,
+            step
+        )
+        .execute(&mut *tx)
+        .await?;
+        if confirmed.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+            "INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, * FROM UNNEST($2::VARCHAR[])",
+            user_id,
+            recovery_code_hashes
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    // Records an accepted time step, returns 0 if the step (or a later one)
+    // was already used
+    pub async fn use_user_totp_step(
+        state: &AppState,
+        user_id: i32,
+        step: i64,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "UPDATE user_totp SET last_used_step = $2 WHERE user_id = $1 AND confirmed_at IS NOT NULL AND (last_used_step IS NULL OR last_used_step < $2)",
+            user_id,
+            step
+        )
+        .execute(&state.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn use_recovery_code(
+        state: &AppState,
+        user_id: i32,
+        code_hash: &str,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "UPDATE recovery_codes SET used_at = CURRENT_TIMESTAMP WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+            user_id,
+            code_hash
+        )
+        .execute(&state.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn count_unused_recovery_codes(
+        state: &AppState,
+        user_id: i32,
+    ) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL"#,
+            user_id
+        )
+        .fetch_one(&state.db)
+        .await?;
+
+        Ok(result.count)
+    }
+
+    // Turns two-factor authentication off along with the recovery codes
+    pub async fn delete_user_totp(
+        state: &AppState,
+        user_id: i32,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut tx = state.db.begin().await?;
+
+        let result = sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    // Essentials of a user that isn't banned, for logins that start from
+    // something other than an email address
+    pub async fn get_active_user_essentials(
+        state: &AppState,
+        user_id: i32,
+    ) -> Result<Option<UserEssentials>, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query_as!(
+            UserEssentials,
+            r#"SELECT id, email, handle, role, email_verified_at IS NOT NULL AS "email_verified!" FROM users WHERE id = $1 AND banned = FALSE"#,
+            user_id
+        )
+        .fetch_optional(&state.db)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn insert_webauthn_credential(
+        state: &AppState,
+        user_id: i32,
+        credential_id: &str,
+        public_key: &[u8],
+        algorithm: i32,
+        sign_count: i64,
+        name: &str,
+    ) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "INSERT INTO webauthn_credentials (user_id, credential_id, public_key, algorithm, sign_count, name) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+            user_id,
+            credential_id,
+            public_key,
+            algorithm,
+            sign_count,
+            name
+        )
+        .fetch_one(&state.db)
+        .await?;
+
+        Ok(result.id)
+    }
+
+    pub async fn get_webauthn_credential(
+        state: &AppState,
+        credential_id: &str,
+    ) -> Result<Option<WebauthnCredential>, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query_as!(
+            WebauthnCredential,
+            "SELECT id, user_id, credential_id, public_key, algorithm, sign_count, name, created_at, last_used_at FROM webauthn_credentials WHERE credential_id = $1",
+            credential_id
+        )
+        .fetch_optional(&state.db)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn get_user_webauthn_credentials(
+        state: &AppState,
+        user_id: i32,
+    ) -> Result<Vec<WebauthnCredential>, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query_as!(
+            WebauthnCredential,
+            "SELECT id, user_id, credential_id, public_key, algorithm, sign_count, name, created_at, last_used_at FROM webauthn_credentials WHERE user_id = $1 ORDER BY created_at",
+            user_id
+        )
+        .fetch_all(&state.db)
+        .await?;
+
+        Ok(result)
+    }
+
+    // Stores the new counter only if nobody else used the credential since
+    // it was read, returns 0 otherwise
+    pub async fn update_webauthn_sign_count(
+        state: &AppState,
+        id: i32,
+        previous: i64,
+        sign_count: i64,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "UPDATE webauthn_credentials SET sign_count = $3, last_used_at = CURRENT_TIMESTAMP WHERE id = $1 AND sign_count = $2",
+            id,
+            previous,
+            sign_count
+        )
+        .execute(&state.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete_webauthn_credential(
+        state: &AppState,
+        user_id: i32,
+        id: i32,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let result = sqlx::query!(
+            "DELETE FROM webauthn_credentials WHERE user_id = $1 AND id = $2",
+            user_id,
+            id
+        )
+        .execute(&state.db)
         .await?;
 
         Ok(result.rows_affected())
@@ -183,11 +816,11 @@ impl UserService {
 
     #[allow(dead_code)]
     pub async fn delete_user(
+        state: &AppState,
         user_id: i32,
     ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
-        let db = get_db().await;
         let result = sqlx::query!("DELETE FROM users WHERE id = $1", user_id)
-            .execute(&db.pool)
+            .execute(&state.db)
             .await?;
 
         Ok(result.rows_affected())
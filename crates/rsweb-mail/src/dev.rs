@@ -0,0 +1,85 @@
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use std::path::PathBuf;
+
+use crate::errors::MailError;
+use crate::{Email, Mailer};
+
+// Prints every message to stdout, the default for local development
+pub struct StdoutMailer {
+    from: Mailbox,
+}
+
+impl StdoutMailer {
+    pub fn new(from: Mailbox) -> Self {
+        StdoutMailer { from }
+    }
+}
+
+#[async_trait]
+impl Mailer for StdoutMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = email.to_message(&self.from)?;
+        println!("{}", String::from_utf8_lossy(&message.formatted()));
+        Ok(())
+    }
+}
+
+// Drops every message as an .eml file into a directory
+pub struct FileMailer {
+    dir: PathBuf,
+    from: Mailbox,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>, from: Mailbox) -> Self {
+        FileMailer {
+            dir: dir.into(),
+            from,
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = email.to_message(&self.from)?;
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let path = self.dir.join(format!("{}.eml", nanos));
+        tokio::fs::write(path, message.formatted()).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_mailer() {
+        let dir = std::env::temp_dir().join(format!("rsweb-mail-{}", std::process::id()));
+        let mailer = FileMailer::new(&dir, "rsweb <noreply@example.com>".parse().unwrap());
+        let email = Email {
+            to: "user@example.com".to_string(),
+            subject: "Hello".to_string(),
+            text: "Hello, world!".to_string(),
+            html: None,
+        };
+        mailer.send(&email).await.unwrap();
+
+        let mut entries = std::fs::read_dir(&dir).unwrap();
+        let message = std::fs::read_to_string(entries.next().unwrap().unwrap().path()).unwrap();
+        assert!(message.contains("From: rsweb <noreply@example.com>\r\n"));
+        assert!(message.contains("To: user@example.com\r\n"));
+        assert!(message.contains("Subject: Hello\r\n"));
+        assert!(message.contains("Hello, world!"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+#[derive(Debug)]
+pub enum MailError {
+    IoError(std::io::Error),
+    InvalidConfig(String),
+    InvalidAddress(lettre::address::AddressError),
+    BuildError(lettre::error::Error),
+    SmtpError(lettre::transport::smtp::Error),
+    JsonError(serde_json::Error),
+    QueueError(String),
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MailError::IoError(e) => e.fmt(f),
+            MailError::InvalidConfig(e) => write!(f, "Invalid mail configuration: {}", e),
+            MailError::InvalidAddress(e) => write!(f, "Invalid address: {}", e),
+            MailError::BuildError(e) => e.fmt(f),
+            MailError::SmtpError(e) => e.fmt(f),
+            MailError::JsonError(e) => e.fmt(f),
+            MailError::QueueError(e) => write!(f, "Mail queue error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MailError {}
+
+impl From<std::io::Error> for MailError {
+    fn from(e: std::io::Error) -> Self {
+        MailError::IoError(e)
+    }
+}
+
+impl From<lettre::address::AddressError> for MailError {
+    fn from(e: lettre::address::AddressError) -> Self {
+        MailError::InvalidAddress(e)
+    }
+}
+
+impl From<lettre::error::Error> for MailError {
+    fn from(e: lettre::error::Error) -> Self {
+        MailError::BuildError(e)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for MailError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        MailError::SmtpError(e)
+    }
+}
+
+impl From<serde_json::Error> for MailError {
+    fn from(e: serde_json::Error) -> Self {
+        MailError::JsonError(e)
+    }
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for MailError {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        MailError::QueueError(e.to_string())
+    }
+}
@@ -0,0 +1,64 @@
+use async_trait::async_trait;
+use lettre::Message;
+use lettre::message::{Mailbox, MultiPart, SinglePart};
+use rsweb_config::{MailBackend, MailConfig};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::errors::MailError;
+
+pub mod dev;
+pub mod errors;
+pub mod memory;
+pub mod queue;
+pub mod smtp;
+pub mod templates;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    #[serde(default)]
+    pub html: Option<String>,
+}
+
+impl Email {
+    // Plain text mail, or multipart/alternative when there is an HTML part
+    pub fn to_message(&self, from: &Mailbox) -> Result<Message, MailError> {
+        let builder = Message::builder()
+            .from(from.clone())
+            .to(self.to.parse::<Mailbox>()?)
+            .subject(&self.subject);
+
+        let message = match &self.html {
+            Some(html) => builder.multipart(MultiPart::alternative_plain_html(
+                self.text.clone(),
+                html.clone(),
+            ))?,
+            None => builder.singlepart(SinglePart::plain(self.text.clone()))?,
+        };
+
+        Ok(message)
+    }
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+// Whether mail can be addressed to it, stricter than checking for an '@'
+pub fn is_valid_address(address: &str) -> bool {
+    address.len() <= 255 && address.parse::<lettre::Address>().is_ok()
+}
+
+// The backend picked in the configuration
+pub fn from_config(config: &MailConfig) -> Result<Arc<dyn Mailer>, MailError> {
+    let from: Mailbox = config.from.parse()?;
+    match &config.backend {
+        MailBackend::Smtp(smtp) => Ok(Arc::new(smtp::SmtpMailer::from_config(smtp, from)?)),
+        MailBackend::File(dir) => Ok(Arc::new(dev::FileMailer::new(dir, from))),
+        MailBackend::Stdout => Ok(Arc::new(dev::StdoutMailer::new(from))),
+    }
+}
@@ -0,0 +1,45 @@
+use async_trait::async_trait;
+use std::sync::Mutex;
+
+use crate::errors::MailError;
+use crate::{Email, Mailer};
+
+// Keeps sent mail in memory so tests can assert on it
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+impl MemoryMailer {
+    pub fn new() -> Self {
+        MemoryMailer::default()
+    }
+
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_mailer() {
+        let mailer = MemoryMailer::new();
+        let email = crate::templates::password_changed("user@example.com", "user");
+        mailer.send(&email).await.unwrap();
+
+        let sent = mailer.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].subject, "Your password was changed");
+    }
+}
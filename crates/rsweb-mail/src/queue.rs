@@ -0,0 +1,267 @@
+use deadpool_redis::Pool;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::errors::MailError;
+use crate::{Email, Mailer};
+
+const QUEUE: &str = "mail";
+// Attempts before a message is dropped, retries back off from 30 seconds
+// up to 4 minutes
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_BASE_SECS: i64 = 30;
+// How long a worker blocks on an empty queue before checking for due retries
+const POLL_TIMEOUT_SECS: f64 = 5.0;
+// Pause after a cache error so an outage doesn't turn into a busy loop
+const ERROR_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Job {
+    // Random, so the same mail queued twice is still two jobs. The queue tells
+    // jobs apart by their payload, identical ones would be delivered once.
+    #[serde(default)]
+    id: String,
+    email: Email,
+    #[serde(default)]
+    attempts: u32,
+}
+
+#[derive(Debug)]
+enum Delivery {
+    Sent,
+    Retry { job: Job, run_at: i64 },
+    Failed,
+}
+
+fn unix_secs() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+fn retry_delay(attempts: u32) -> i64 {
+    RETRY_BASE_SECS << (attempts.saturating_sub(1)).min(10)
+}
+
+// Hands the mail to the background worker, returns once it is queued
+pub async fn enqueue(cache: &Pool, email: Email) -> Result<(), MailError> {
+    let payload = serde_json::to_string(&Job {
+        id: rsweb_crypto::generate::generate_id(),
+        email,
+        attempts: 0,
+    })?;
+    rsweb_cache::queue::push(cache, QUEUE, &payload).await?;
+    Ok(())
+}
+
+// Sends a job, failed sends are rescheduled until they run out of attempts
+async fn deliver(mailer: &dyn Mailer, mut job: Job, now: i64) -> Delivery {
+    let e = match mailer.send(&job.email).await {
+        Ok(()) => return Delivery::Sent,
+        Err(e) => e,
+    };
+
+    job.attempts += 1;
+    if job.attempts >= MAX_ATTEMPTS {
+        eprintln!(
+            "Giving up on mail to {} after {} attempts: {}",
+            job.email.to, job.attempts, e
+        );
+        return Delivery::Failed;
+    }
+
+    eprintln!(
+        "Failed to send mail to {} (attempt {}): {}",
+        job.email.to, job.attempts, e
+    );
+    let run_at = now + retry_delay(job.attempts);
+    Delivery::Retry { job, run_at }
+}
+
+// Sends queued mail until the process exits. A job stays on the worker's
+// processing list until it was sent or rescheduled, so mail taken before a
+// crash or restart is delivered when the worker is back, at worst twice.
+// Workers sharing a Redis need ids of their own.
+pub async fn run_worker(cache: Pool, mailer: Arc<dyn Mailer>, worker: String) {
+    loop {
+        match rsweb_cache::queue::requeue_unfinished(&cache, QUEUE, &worker).await {
+            Ok(0) => break,
+            Ok(requeued) => {
+                eprintln!("Requeued {} unfinished mail jobs", requeued);
+                break;
+            }
+            Err(e) => {
+                eprintln!("Failed to requeue unfinished mail: {}", e);
+                tokio::time::sleep(ERROR_BACKOFF).await;
+            }
+        }
+    }
+
+    loop {
+        if let Err(e) = rsweb_cache::queue::promote_due(&cache, QUEUE, unix_secs()).await {
+            eprintln!("Failed to promote delayed mail: {}", e);
+            tokio::time::sleep(ERROR_BACKOFF).await;
+            continue;
+        }
+
+        let payload =
+            match rsweb_cache::queue::take(&cache, QUEUE, &worker, POLL_TIMEOUT_SECS).await {
+                Ok(Some(payload)) => payload,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("Failed to read mail queue: {}", e);
+                    tokio::time::sleep(ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+
+        let delivery = match serde_json::from_str(&payload) {
+            Ok(job) => deliver(mailer.as_ref(), job, unix_secs()).await,
+            Err(e) => {
+                eprintln!("Dropping malformed mail job: {}", e);
+                Delivery::Failed
+            }
+        };
+
+        // Left on the processing list if this fails, the job is tried again
+        // after a restart
+        let done = match delivery {
+            Delivery::Sent | Delivery::Failed => {
+                rsweb_cache::queue::finish(&cache, QUEUE, &worker, &payload).await
+            }
+            Delivery::Retry { job, run_at } => match serde_json::to_string(&job) {
+                Ok(rescheduled) => {
+                    rsweb_cache::queue::retry(
+                        &cache,
+                        QUEUE,
+                        &worker,
+                        &payload,
+                        &rescheduled,
+                        run_at,
+                    )
+                    .await
+                }
+                Err(e) => Err(e.into()),
+            },
+        };
+        if let Err(e) = done {
+            eprintln!("Failed to update mail job: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::memory::MemoryMailer;
+
+    // Fails the first sends, then delivers
+    struct FlakyMailer {
+        failures: AtomicU32,
+        inner: MemoryMailer,
+    }
+
+    #[async_trait]
+    impl Mailer for FlakyMailer {
+        async fn send(&self, email: &Email) -> Result<(), MailError> {
+            if self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                return Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into());
+            }
+            self.inner.send(email).await
+        }
+    }
+
+    fn job() -> Job {
+        Job {
+            id: "id".to_string(),
+            email: crate::templates::password_changed("user@example.com", "user"),
+            attempts: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deliver_retries() {
+        let mailer = FlakyMailer {
+            failures: AtomicU32::new(2),
+            inner: MemoryMailer::new(),
+        };
+
+        let Delivery::Retry { job, run_at } = deliver(&mailer, job(), 1000).await else {
+            panic!("expected a retry");
+        };
+        assert_eq!((job.attempts, run_at), (1, 1030));
+        let Delivery::Retry { job, run_at } = deliver(&mailer, job, 2000).await else {
+            panic!("expected a retry");
+        };
+        assert_eq!((job.attempts, run_at), (2, 2060));
+        assert!(mailer.inner.sent().is_empty());
+
+        assert!(matches!(deliver(&mailer, job, 3000).await, Delivery::Sent));
+        assert_eq!(mailer.inner.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_gives_up() {
+        let mailer = FlakyMailer {
+            failures: AtomicU32::new(u32::MAX),
+            inner: MemoryMailer::new(),
+        };
+
+        let mut job = job();
+        for attempt in 1..MAX_ATTEMPTS {
+            job = match deliver(&mailer, job, 0).await {
+                Delivery::Retry { job, .. } => job,
+                other => panic!("attempt {}: {:?}", attempt, other),
+            };
+        }
+        assert!(matches!(deliver(&mailer, job, 0).await, Delivery::Failed));
+    }
+
+    #[tokio::test]
+    async fn test_jobs_are_distinct() {
+        // Identical mail makes distinct payloads, the queue keeps both
+        let email = || crate::templates::password_changed("user@example.com", "user");
+        let payloads: Vec<String> = [email(), email()]
+            .into_iter()
+            .map(|email| {
+                serde_json::to_string(&Job {
+                    id: rsweb_crypto::generate::generate_id(),
+                    email,
+                    attempts: 0,
+                })
+                .unwrap()
+            })
+            .collect();
+        assert_ne!(payloads[0], payloads[1]);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), 30);
+        assert_eq!(retry_delay(2), 60);
+        assert_eq!(retry_delay(4), 240);
+    }
+
+    #[test]
+    fn test_job_roundtrip() {
+        let job = Job {
+            id: "id".to_string(),
+            email: crate::templates::password_changed("user@example.com", "user"),
+            attempts: 2,
+        };
+        let parsed: Job = serde_json::from_str(&serde_json::to_string(&job).unwrap()).unwrap();
+
+        assert_eq!(parsed.attempts, 2);
+        assert_eq!(parsed.email.to, "user@example.com");
+        assert_eq!(parsed.email.html, job.email.html);
+    }
+}
@@ -0,0 +1,46 @@
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use rsweb_config::{SmtpConfig, SmtpTls};
+
+use crate::errors::MailError;
+use crate::{Email, Mailer};
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn from_config(config: &SmtpConfig, from: Mailbox) -> Result<Self, MailError> {
+        let host = &config.host;
+        let mut builder = match config.tls {
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = &config.username {
+            builder =
+                builder.credentials(Credentials::new(username.clone(), config.password.clone()));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = email.to_message(&self.from)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use maud::{DOCTYPE, Markup, html};
+
+use crate::Email;
+
+// Mail clients ignore stylesheets, so everything is styled inline
+fn layout(title: &str, content: Markup) -> Markup {
+    html! {
+      (DOCTYPE)
+      html {
+        head {
+          meta charset="utf-8";
+          title { (title) }
+        }
+        body style="margin: 0; padding: 2rem; background-color: rgb(249 250 251); font-family: sans-serif; color: #2e2d2d;" {
+          div style="max-width: 32rem; margin: 0 auto; padding: 2rem; background-color: white; border-radius: 0.5rem;" {
+            h1 style="font-size: 1.5rem; margin-top: 0;" { (title) }
+            (content)
+          }
+        }
+      }
+    }
+}
+
+fn button(href: &str, label: &str) -> Markup {
+    html! {
+      p style="margin: 2rem 0;" {
+        a href=(href) style="background-color: rgb(0 113 227); color: white; padding: 0.75rem 1rem; border-radius: 0.5rem; text-decoration: none; font-weight: 600;" {
+          (label)
+        }
+      }
+    }
+}
+
+pub fn verify_email(to: &str, handle: &str, link: &str) -> Email {
+    let title = "Verify your email address";
+    let html = layout(
+        title,
+        html! {
+          p { "Hi " (handle) "," }
+          p { "Thanks for signing up! Please confirm that this is your email address, the link expires in two days." }
+          (button(link, "Verify email address"))
+          p { "If you didn't create an account, you can ignore this email." }
+        },
+    );
+
+    Email {
+        to: to.to_string(),
+        subject: title.to_string(),
+        text: format!(
+            "Hi {},\n\nThanks for signing up! Please confirm that this is your email address by opening the link below, it expires in two days:\n\n{}\n\nIf you didn't create an account, you can ignore this email.",
+            handle, link
+        ),
+        html: Some(html.into_string()),
+    }
+}
+
+pub fn password_reset(to: &str, handle: &str, link: &str) -> Email {
+    let title = "Reset your password";
+    let html = layout(
+        title,
+        html! {
+          p { "Hi " (handle) "," }
+          p { "Someone asked to reset the password of your account. Use the button below to choose a new one, the link expires in an hour." }
+          (button(link, "Choose a new password"))
+          p { "If this wasn't you, you can ignore this email." }
+        },
+    );
+
+    Email {
+        to: to.to_string(),
+        subject: title.to_string(),
+        text: format!(
+            "Hi {},\n\nSomeone asked to reset the password of your account. Use the link below to choose a new one, it expires in an hour:\n\n{}\n\nIf this wasn't you, you can ignore this email.",
+            handle, link
+        ),
+        html: Some(html.into_string()),
+    }
+}
+
+pub fn password_changed(to: &str, handle: &str) -> Email {
+    let title = "Your password was changed";
+    let html = layout(
+        title,
+        html! {
+          p { "Hi " (handle) "," }
+          p { "The password of your account was just changed and you were signed out everywhere." }
+          p { "If this wasn't you, reset your password right away and get in touch with us." }
+        },
+    );
+
+    Email {
+        to: to.to_string(),
+        subject: title.to_string(),
+        text: format!(
+            "Hi {},\n\nThe password of your account was just changed and you were signed out everywhere.\n\nIf this wasn't you, reset your password right away and get in touch with us.",
+            handle
+        ),
+        html: Some(html.into_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_reset() {
+        let email = password_reset(
+            "user@example.com",
+            "<user>",
+            "https://example.com/reset/abc",
+        );
+        let html = email.html.unwrap();
+
+        assert!(email.text.contains("https://example.com/reset/abc"));
+        assert!(html.contains(r#"href="https://example.com/reset/abc""#));
+        // Markup is escaped like on the pages
+        assert!(html.contains("&lt;user&gt;"));
+    }
+}
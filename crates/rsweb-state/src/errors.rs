@@ -0,0 +1,48 @@
+#[derive(Debug)]
+pub enum StateError {
+    DatabaseError(sqlx::Error),
+    CacheError(deadpool_redis::CreatePoolError),
+    KeyringError(rsweb_crypto::errors::CryptoError),
+    SecretError(rsweb_crypto::errors::CryptoError),
+    HasherError(rsweb_crypto::errors::CryptoError),
+    MailerError(rsweb_mail::errors::MailError),
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::DatabaseError(e) => write!(f, "Failed to connect to database: {}", e),
+            StateError::CacheError(e) => write!(f, "Failed to create cache pool: {}", e),
+            StateError::KeyringError(e) => write!(f, "Failed to open keyring: {}", e),
+            StateError::SecretError(e) => write!(f, "Failed to open token secret: {}", e),
+            StateError::HasherError(e) => write!(f, "Invalid password hash settings: {}", e),
+            StateError::MailerError(e) => write!(f, "Failed to set up mailer: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<sqlx::Error> for StateError {
+    fn from(e: sqlx::Error) -> Self {
+        StateError::DatabaseError(e)
+    }
+}
+
+impl From<deadpool_redis::CreatePoolError> for StateError {
+    fn from(e: deadpool_redis::CreatePoolError) -> Self {
+        StateError::CacheError(e)
+    }
+}
+
+impl From<rsweb_crypto::errors::CryptoError> for StateError {
+    fn from(e: rsweb_crypto::errors::CryptoError) -> Self {
+        StateError::KeyringError(e)
+    }
+}
+
+impl From<rsweb_mail::errors::MailError> for StateError {
+    fn from(e: rsweb_mail::errors::MailError) -> Self {
+        StateError::MailerError(e)
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use deadpool_redis::{Pool, Runtime};
+use rsweb_cache::challenge::{ChallengeStore, RedisChallengeStore};
+use rsweb_cache::revocation::{RedisRevocationStore, RevocationStore};
+use rsweb_config::Config;
+use rsweb_crypto::ed25519::KeyStore;
+use rsweb_crypto::hash::Hasher;
+use rsweb_crypto::hmac::HmacKey;
+use rsweb_mail::Mailer;
+use sqlx::PgPool;
+
+use crate::oidc::OidcClients;
+
+pub mod errors;
+pub mod oidc;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use errors::StateError;
+
+// Everything a request needs besides its own input. Built once at startup
+// and handed to the routes, cloning it only clones the handles.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub db: PgPool,
+    pub cache: Pool,
+    pub challenges: Arc<dyn ChallengeStore>,
+    pub revocations: Arc<dyn RevocationStore>,
+    pub keys: Arc<KeyStore>,
+    pub hmac: Arc<HmacKey>,
+    pub hasher: Arc<Hasher>,
+    pub mailer: Arc<dyn Mailer>,
+    pub oidc: Arc<OidcClients>,
+}
+
+impl AppState {
+    // Connects to the database and opens the keyring and secrets named in
+    // the config
+    pub async fn new(config: Config) -> Result<Self, StateError> {
+        let db = PgPool::connect(&config.database_url).await?;
+        let cache = cache_pool(&config)?;
+        let keys = KeyStore::from_config(&config.keys).await?;
+        let hmac = HmacKey::open(&config.keys.token_secret_path)
+            .await
+            .map_err(StateError::SecretError)?;
+        let hasher = Hasher::new(&config.password_hash).map_err(StateError::HasherError)?;
+        let mailer = rsweb_mail::from_config(&config.mail)?;
+        let oidc = OidcClients::from_config(&config);
+
+        Ok(AppState {
+            config: Arc::new(config),
+            db,
+            challenges: Arc::new(RedisChallengeStore::new(cache.clone())),
+            revocations: Arc::new(RedisRevocationStore::new(cache.clone())),
+            cache,
+            keys: Arc::new(keys),
+            hmac: Arc::new(hmac),
+            hasher: Arc::new(hasher),
+            mailer,
+            oidc: Arc::new(oidc),
+        })
+    }
+}
+
+// Connections are only opened once the pool is first used
+fn cache_pool(config: &Config) -> Result<Pool, StateError> {
+    Ok(deadpool_redis::Config::from_url(&config.redis_url).create_pool(Some(Runtime::Tokio1))?)
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn test_states_are_isolated() {
+        let state = crate::testing::state().await;
+        let other = crate::testing::state().await;
+        assert!(!state.config.keys.keyring_path.exists());
+
+        let (kid, signature) = state.keys.sign_message(b"message").unwrap();
+        assert!(
+            state
+                .keys
+                .verify_signature(&kid, b"message", &signature)
+                .await
+        );
+        assert!(
+            !other
+                .keys
+                .verify_signature(&kid, b"message", &signature)
+                .await
+        );
+
+        let tag = state.hmac.hash(b"message");
+        assert!(state.hmac.verify(b"message", &tag));
+        assert!(!other.hmac.verify(b"message", &tag));
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use google_jwt::{ClientAsync, GoogleError, ProviderConfig};
+use rsweb_config::Config;
+use tokio::sync::OnceCell;
+
+pub const GOOGLE: &str = "google";
+
+// One ID token verifier per configured provider, so discovery documents and
+// keys are fetched once instead of on every login. Google's needs no
+// discovery and is ready right away, the others are discovered on first use
+// and retried until that succeeds. Each provider has a cell of its own, a
+// provider that is slow to answer only holds up logins through itself.
+pub struct OidcClients {
+    providers: Vec<Provider>,
+}
+
+struct Provider {
+    name: String,
+    issuer: String,
+    client_ids: Vec<String>,
+    client: OnceCell<Arc<ClientAsync>>,
+}
+
+impl OidcClients {
+    pub fn from_config(config: &Config) -> Self {
+        let mut providers = Vec::with_capacity(config.oidc.len() + 1);
+
+        if let Some(client_id) = &config.google.client_id {
+            let provider = ProviderConfig::google(client_id);
+            let client = ClientAsync::for_provider(provider.clone())
+                .with_hosted_domains(config.google.hosted_domains.clone());
+            providers.push(Provider {
+                name: GOOGLE.to_string(),
+                issuer: provider.issuer,
+                client_ids: provider.audiences,
+                client: OnceCell::from(Arc::new(client)),
+            });
+        }
+        for settings in &config.oidc {
+            providers.push(Provider {
+                name: settings.name.clone(),
+                issuer: settings.issuer.clone(),
+                client_ids: settings.client_ids.clone(),
+                client: OnceCell::new(),
+            });
+        }
+
+        OidcClients { providers }
+    }
+
+    // Names of the configured providers, Google first
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.providers.iter().map(|provider| provider.name.as_str())
+    }
+
+    // The verifier of the named provider, None if there is no such provider
+    pub async fn get(&self, name: &str) -> Result<Option<Arc<ClientAsync>>, GoogleError> {
+        let Some(provider) = self.providers.iter().find(|provider| provider.name == name) else {
+            return Ok(None);
+        };
+
+        let client = provider
+            .client
+            .get_or_try_init(|| async {
+                let config = ProviderConfig::discover(
+                    &provider.name,
+                    &provider.issuer,
+                    provider.client_ids.clone(),
+                )
+                .await?;
+                Ok::<_, GoogleError>(Arc::new(ClientAsync::for_provider(config)))
+            })
+            .await?;
+        Ok(Some(client.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use rsweb_config::Profile;
+
+    fn clients() -> OidcClients {
+        let env: HashMap<String, String> = [
+            ("GOOGLE_OAUTH_CLIENT_ID", "web.apps.googleusercontent.com"),
+            ("OIDC_PROVIDERS", "keycloak"),
+            // Nothing listens there, discovery fails right away
+            ("OIDC_KEYCLOAK_ISSUER", "http://127.0.0.1:9/realms/main"),
+            ("OIDC_KEYCLOAK_CLIENT_ID", "web"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        OidcClients::from_config(&Config::from_sources(Profile::Test, None, &env).unwrap())
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(
+            clients().names().collect::<Vec<_>>(),
+            vec!["google", "keycloak"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_providers_are_independent() {
+        let clients = clients();
+
+        assert!(clients.get("keycloak").await.is_err());
+        // A failed discovery is not cached, the next login tries again
+        assert!(!clients.providers[1].client.initialized());
+        assert!(clients.get("google").await.unwrap().is_some());
+        assert!(clients.get("github").await.unwrap().is_none());
+    }
+}
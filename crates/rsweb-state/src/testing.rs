@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rsweb_cache::challenge::MemoryChallengeStore;
+use rsweb_cache::revocation::MemoryRevocationStore;
+use rsweb_config::{Config, Profile};
+use rsweb_crypto::ed25519::KeyStore;
+use rsweb_crypto::hash::Hasher;
+use rsweb_crypto::hmac::HmacKey;
+use rsweb_mail::memory::MemoryMailer;
+use sqlx::PgPool;
+
+use crate::oidc::OidcClients;
+use crate::{AppState, StateError};
+
+// States for tests. Each one gets a fresh keyring and token secret of its
+// own and keeps mail, login challenges and revoked tokens in memory. The
+// pools only connect once a test actually talks to the database or cache, so
+// tests that don't need them run without either. Only built for tests and
+// with the testing feature.
+
+static KEYRINGS: AtomicUsize = AtomicUsize::new(0);
+
+// The test profile with the environment's overrides
+pub fn config() -> Config {
+    let env: HashMap<String, String> = std::env::vars().collect();
+    Config::from_sources(Profile::Test, None, &env).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub async fn state() -> AppState {
+    state_with(config())
+        .await
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+// A state on the given database, e.g. the fresh one #[sqlx::test] migrated
+pub async fn state_with_db(db: PgPool) -> AppState {
+    AppState {
+        db,
+        ..state().await
+    }
+}
+
+pub async fn state_with(mut config: Config) -> Result<AppState, StateError> {
+    config.keys.keyring_path = std::env::temp_dir().join(format!(
+        "rsweb-keyring-{}-{}",
+        std::process::id(),
+        KEYRINGS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let db = PgPool::connect_lazy(&config.database_url)?;
+    let cache = crate::cache_pool(&config)?;
+    let keys = KeyStore::open(&config.keys.keyring_path).await;
+    // The keys stay in memory, so the file isn't left behind. Tests that
+    // rotate keys open a KeyStore of their own.
+    let _ = std::fs::remove_file(&config.keys.keyring_path);
+    let keys = keys?;
+    let hasher = Hasher::new(&config.password_hash).map_err(StateError::HasherError)?;
+    let oidc = OidcClients::from_config(&config);
+
+    Ok(AppState {
+        config: Arc::new(config),
+        db,
+        cache,
+        challenges: Arc::new(MemoryChallengeStore::new()),
+        revocations: Arc::new(MemoryRevocationStore::new()),
+        keys: Arc::new(keys),
+        hmac: Arc::new(HmacKey::ephemeral()),
+        hasher: Arc::new(hasher),
+        mailer: Arc::new(MemoryMailer::new()),
+        oidc: Arc::new(oidc),
+    })
+}
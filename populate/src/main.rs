@@ -1,9 +1,7 @@
-use std::sync::Arc;
-
 use clap::Parser;
 use dotenvy::dotenv;
-use rsweb_database::{get_db, DB};
 use simulate::insert_data;
+use sqlx::PgPool;
 use tokio::fs;
 
 mod simulate;
@@ -19,11 +17,20 @@ struct Cli {
 #[tokio::main]
 async fn main() {
     dotenv().ok();
+    let config = match rsweb_config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     let cli = Cli::parse();
 
-    reset_db().await;
+    reset_db(&config.database_url).await;
 
-    let db = get_db().await;
+    let db = PgPool::connect(&config.database_url)
+        .await
+        .expect("Failed to connect to database");
     setup_db(&db).await;
 
     if cli.sim {
@@ -34,13 +41,13 @@ async fn main() {
     }
 }
 
-async fn reset_db() {
-    let mut database_url = std::env::var("DATABASE_URL").unwrap();
+async fn reset_db(database_url: &str) {
+    let mut database_url = database_url.to_string();
     let db_name = database_url.split("/").last().unwrap().to_string();
 
     // Remove the database name and connect to postgres database instead
     database_url = database_url.replace(&db_name, "postgres");
-    let db = DB::from_url(database_url.as_str())
+    let db = PgPool::connect(database_url.as_str())
         .await
         .expect("Failed to connect to database");
 
@@ -54,14 +61,14 @@ async fn reset_db() {
 
     // Execute each query separately
     sqlx::query(&terminate_query)
-        .execute(&db.pool)
+        .execute(&db)
         .await
         .expect("Failed to terminate connections");
 
     // Simple drop query
     let drop_query = format!("DROP DATABASE IF EXISTS {}", db_name);
     sqlx::query(&drop_query)
-        .execute(&db.pool)
+        .execute(&db)
         .await
         .expect("Failed to drop database");
 
@@ -70,14 +77,14 @@ async fn reset_db() {
     // Simple create query
     let create_query = format!("CREATE DATABASE {}", db_name);
     sqlx::query(&create_query)
-        .execute(&db.pool)
+        .execute(&db)
         .await
         .expect("Failed to create database");
 
     println!("Database created");
 }
 
-async fn setup_db(db: &Arc<DB>) {
+async fn setup_db(db: &PgPool) {
     // Loop through all the sql files in the sql folder and add their names to a vector
     let mut sql_files = vec![];
     for entry in std::fs::read_dir("sql").unwrap() {
@@ -100,7 +107,7 @@ async fn setup_db(db: &Arc<DB>) {
 
         // Execute using raw exuctor
         sqlx::raw_sql(&sql_content)
-            .execute(&db.pool)
+            .execute(db)
             .await
             .expect("Failed to execute SQL file");
     }
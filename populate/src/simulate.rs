@@ -1,8 +1,5 @@
-use std::sync::Arc;
-
-use rsweb_database::DB;
 use serde::Deserialize;
-use sqlx::types::time::Date;
+use sqlx::{PgPool, types::time::Date};
 use time_macros::format_description;
 use tokio::fs;
 
@@ -22,7 +19,7 @@ struct JsonUser {
     role: String,
 }
 
-pub async fn insert_data(db: &Arc<DB>) {
+pub async fn insert_data(db: &PgPool) {
     // Read the json file
     let json_content = fs::read_to_string("simulate.json")
         .await
@@ -35,19 +32,25 @@ pub async fn insert_data(db: &Arc<DB>) {
     insert_users(db, json_data.users.as_slice()).await;
 }
 
-async fn insert_users(db: &Arc<DB>, users: &[JsonUser]) {
+async fn insert_users(db: &PgPool, users: &[JsonUser]) {
     for user in users {
         let dob = Date::parse(&user.dob, format_description!("[year]-[month]-[day]"))
             .expect("Failed to parse date");
 
-        sqlx::query!("INSERT INTO users (email, handle, gender, date_of_birth, role, google_sub) VALUES ($1, $2, $3, $4, $5, $6)",
+        let result = sqlx::query!("INSERT INTO users (email, handle, gender, date_of_birth, role) VALUES ($1, $2, $3, $4, $5) RETURNING id",
             user.email,
             user.handle,
             user.gender,
             dob,
-            user.role,
-            user.google_sub
-        ).execute(&db.pool).await.expect("Failed to insert user");
+            user.role
+        ).fetch_one(db).await.expect("Failed to insert user");
+
+        if let Some(google_sub) = &user.google_sub {
+            sqlx::query!("INSERT INTO users_identities (user_id, provider, subject) VALUES ($1, 'google', $2)",
+                result.id,
+                google_sub
+            ).execute(db).await.expect("Failed to insert identity");
+        }
     }
 
     println!("Inserted {} users", users.len());
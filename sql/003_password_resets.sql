@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS password_resets (
+  id SERIAL PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  -- Keyed hash of the emailed token, the token itself is never stored
+  token_hash VARCHAR(128) NOT NULL UNIQUE,
+
+  expires_at TIMESTAMP NOT NULL,
+  -- Set once the token is redeemed, tokens are single-use
+  used_at TIMESTAMP,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_password_resets_user_id ON password_resets (user_id);
+CREATE INDEX IF NOT EXISTS idx_password_resets_expires_at ON password_resets (expires_at);
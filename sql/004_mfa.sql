@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS user_totp (
+  user_id INT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  -- Base32 secret shared with the authenticator app
+  secret VARCHAR(64) NOT NULL,
+  -- NULL until the user entered a first code, unconfirmed secrets don't
+  -- protect the login yet
+  confirmed_at TIMESTAMP,
+  -- Last accepted time step, codes can't be replayed within their window
+  last_used_step BIGINT,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+  id SERIAL PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  -- Keyed hash of the code, the code itself is only shown once
+  code_hash VARCHAR(128) NOT NULL UNIQUE,
+  used_at TIMESTAMP,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON recovery_codes (user_id);
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+  id SERIAL PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  -- Credential id chosen by the authenticator (base64url)
+  credential_id VARCHAR(1024) NOT NULL UNIQUE,
+  -- COSE_Key encoded public key and its COSE algorithm identifier
+  public_key BYTEA NOT NULL,
+  algorithm INT NOT NULL,
+  -- Signature counter reported by the authenticator, a counter that goes
+  -- backwards hints at a cloned authenticator
+  sign_count BIGINT NOT NULL DEFAULT 0,
+  name VARCHAR(64) NOT NULL,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_used_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_webauthn_credentials_user_id ON webauthn_credentials (user_id);
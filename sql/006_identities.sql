@@ -0,0 +1,32 @@
+-- Accounts at external OpenID Connect providers (Google, Microsoft, Apple,
+-- Keycloak, ...) that sign in as a user
+CREATE TABLE IF NOT EXISTS users_identities (
+  id SERIAL PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  -- Provider name as configured, e.g. 'google'
+  provider VARCHAR(64) NOT NULL,
+  -- The provider's stable id of the account (sub claim)
+  subject VARCHAR(255) NOT NULL,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+  UNIQUE (provider, subject)
+);
+
+CREATE INDEX IF NOT EXISTS idx_users_identities_user_id ON users_identities (user_id);
+
+-- Databases created before identities were split out keep the Google
+-- subject on the users table, move it over
+DO $$
+BEGIN
+  IF EXISTS (
+    SELECT 1 FROM information_schema.columns
+    WHERE table_name = 'users' AND column_name = 'google_sub'
+  ) THEN
+    INSERT INTO users_identities (user_id, provider, subject)
+    SELECT id, 'google', google_sub FROM users WHERE google_sub IS NOT NULL
+    ON CONFLICT DO NOTHING;
+
+    ALTER TABLE users DROP COLUMN google_sub;
+  END IF;
+END $$;
@@ -0,0 +1,28 @@
+-- Set once the user followed the verification link (or Google vouched)
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMP;
+
+-- users.password holds PHC strings, which carry the algorithm, parameters
+-- and salt. password_salt only belongs to legacy hex scrypt hashes and is
+-- cleared once the password is rehashed.
+
+-- Refresh tokens used to be stored as they were handed out and without an
+-- expiry, none of them can be carried over, so every device signs in again
+DELETE FROM refresh_tokens;
+ALTER TABLE refresh_tokens DROP COLUMN IF EXISTS token;
+
+ALTER TABLE refresh_tokens
+  -- All tokens rotated from the same login share a family
+  ADD COLUMN IF NOT EXISTS family_id VARCHAR(64) NOT NULL,
+  ADD COLUMN IF NOT EXISTS parent_id INT REFERENCES refresh_tokens(id) ON DELETE SET NULL,
+  -- Keyed hash of the token, the token itself is never stored
+  ADD COLUMN IF NOT EXISTS token_hash VARCHAR(128) NOT NULL UNIQUE,
+  ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP NOT NULL,
+  ADD COLUMN IF NOT EXISTS revoked_at TIMESTAMP,
+  -- Client that last used the session, recorded on every rotation
+  ADD COLUMN IF NOT EXISTS user_agent TEXT,
+  ADD COLUMN IF NOT EXISTS ip_address VARCHAR(64),
+  ADD COLUMN IF NOT EXISTS last_used_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_user_id ON refresh_tokens (user_id);
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_family_id ON refresh_tokens (family_id);
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_expires_at ON refresh_tokens (expires_at);
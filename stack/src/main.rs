@@ -1,41 +1,140 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use dotenvy::dotenv;
+use rsweb_crypto::ed25519::KeyStore;
+use rsweb_state::AppState;
 use warp::{Filter, reject::Rejection, reply::Reply};
 
-const PORT: u16 = 3030;
+const TOKEN_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const KEYRING_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
+    // Every missing or invalid setting is reported at once, before anything
+    // else gets to use the config
+    let config = match rsweb_config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let state = match AppState::new(config).await {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let config = state.config.clone();
+
+    // Purge expired refresh tokens and password resets in the background
+    tokio::spawn(sweep_expired_tokens(state.clone()));
+    // Deliver queued mail
+    tokio::spawn(rsweb_mail::queue::run_worker(
+        state.cache.clone(),
+        state.mailer.clone(),
+        config.mail.worker_id.clone(),
+    ));
+    // Pick up signing key rotations without a restart
+    tokio::spawn(reload_keyring(state.keys.clone()));
+    // Have the identity providers' keys ready for the first logins
+    tokio::spawn(rsweb_auth::oidc::prefetch_keys(state.clone()));
+
     // Serve static files (like router.js)
     let static_files = warp::path("static").and(warp::fs::dir("./static"));
 
-    // Frontend application routes
+    // Frontend application routes. Both groups are boxed, chaining them as
+    // one deeply nested filter overflows the stack of debug builds.
     let app_routes = static_files
-        .or(rsweb_app::routes::authenticated())
+        .or(rsweb_app::routes::authenticated(state.clone()))
+        .or(rsweb_app::routes::security(state.clone()))
+        .or(rsweb_app::routes::security_mfa(state.clone()))
         .or(rsweb_app::routes::explore())
         .or(rsweb_app::routes::blog())
-        .or(rsweb_app::routes::login())
-        .or(rsweb_app::routes::root());
+        .or(rsweb_app::routes::login(state.clone()))
+        .or(rsweb_app::routes::login_mfa(state.clone()))
+        .or(rsweb_app::routes::forgot_password())
+        .or(rsweb_app::routes::reset_password(state.clone()))
+        .or(rsweb_app::routes::verify_email(state.clone()))
+        .or(rsweb_app::routes::root(state.clone()))
+        .boxed();
 
     // API routes
-    let api_routes = rsweb_api::routes::login().or(rsweb_api::routes::register());
+    let api_routes = rsweb_api::routes::login(state.clone())
+        .or(rsweb_api::routes::login_mfa(state.clone()))
+        .or(rsweb_api::routes::oauth_start(state.clone()))
+        .or(rsweb_api::routes::oauth_callback(state.clone()))
+        .or(rsweb_api::routes::register(state.clone()))
+        .or(rsweb_api::routes::logout(state.clone()))
+        .or(rsweb_api::routes::logout_all(state.clone()))
+        .or(rsweb_api::routes::forgot_password(state.clone()))
+        .or(rsweb_api::routes::reset_password(state.clone()))
+        .or(rsweb_api::routes::resend_verification(state.clone()))
+        .or(rsweb_api::routes::setup_totp(state.clone()))
+        .or(rsweb_api::routes::confirm_totp(state.clone()))
+        .or(rsweb_api::routes::disable_totp(state.clone()))
+        .or(rsweb_api::routes::webauthn_register_start(state.clone()))
+        .or(rsweb_api::routes::webauthn_register_finish(state.clone()))
+        .or(rsweb_api::routes::webauthn_login_start(state.clone()))
+        .or(rsweb_api::routes::webauthn_login_finish(state.clone()))
+        .or(rsweb_api::routes::webauthn_credentials(state.clone()))
+        .or(rsweb_api::routes::remove_webauthn_credential(state.clone()))
+        .or(rsweb_api::routes::sessions(state.clone()))
+        .or(rsweb_api::routes::revoke_session(state.clone()))
+        .or(rsweb_api::routes::jwks(state.clone()))
+        .or(rsweb_api::routes::openid_configuration(state))
+        .boxed();
 
     // Combine routes
     let routes = app_routes.or(api_routes).recover(handle_rejection);
 
-    let run_https = std::env::var("HTTPS").unwrap_or("false".to_string()) == "true";
-    if run_https {
-        println!("Listening on https://localhost:{}", PORT);
+    let server = &config.server;
+    if server.https {
+        println!("Listening on https://localhost:{}", server.port);
         warp::serve(routes)
             .tls()
-            .cert_path("cert.pem")
-            .key_path("cert.key")
-            .run(([127, 0, 0, 1], PORT))
+            .cert_path(&server.cert_path)
+            .key_path(&server.key_path)
+            .run(([127, 0, 0, 1], server.port))
             .await;
     } else {
-        println!("Listening on http://localhost:{}", PORT);
-        warp::serve(routes).run(([127, 0, 0, 1], PORT)).await;
+        println!("Listening on http://localhost:{}", server.port);
+        warp::serve(routes).run(([127, 0, 0, 1], server.port)).await;
+    }
+}
+
+async fn sweep_expired_tokens(state: AppState) {
+    let mut interval = tokio::time::interval(TOKEN_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match rsweb_auth::claims::refresh_tokens::purge_expired(&state).await {
+            Ok(0) => {}
+            Ok(n) => println!("Purged {} expired refresh tokens", n),
+            Err(e) => eprintln!("Failed to purge expired refresh tokens: {}", e),
+        }
+        match rsweb_auth::password_reset::purge_expired(&state).await {
+            Ok(0) => {}
+            Ok(n) => println!("Purged {} expired password resets", n),
+            Err(e) => eprintln!("Failed to purge expired password resets: {}", e),
+        }
+    }
+}
+
+async fn reload_keyring(key_store: Arc<KeyStore>) {
+    let mut interval = tokio::time::interval(KEYRING_RELOAD_INTERVAL);
+    // The first tick completes immediately and the keyring was just loaded
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(e) = key_store.reload().await {
+            eprintln!("Failed to reload keyring: {}", e);
+        }
     }
 }
 
@@ -62,6 +161,11 @@ async fn handle_rejection(
         r = Box::new(warp::redirect::see_other(warp::http::Uri::from_static(
             "/login",
         )));
+    } else if err.find::<rsweb_app::filters::Unverified>().is_some() {
+        r = Box::new(warp::reply::with_status(
+            "Email address not verified",
+            warp::http::StatusCode::FORBIDDEN,
+        ));
     } else if err.find::<rsweb_app::filters::Authorized>().is_some() {
         // FIX: This redirect does not seem to actually do anything
         r = Box::new(warp::redirect::see_other(warp::http::Uri::from_static("/")));
@@ -78,6 +182,29 @@ async fn handle_rejection(
             "Invalid request body",
             warp::http::StatusCode::BAD_REQUEST,
         ));
+    } else if let Some(conflict) = err.find::<rsweb_api::filters::Conflict>() {
+        r = Box::new(warp::reply::with_status(
+            conflict.0,
+            warp::http::StatusCode::CONFLICT,
+        ));
+    } else if err
+        .find::<rsweb_api::filters::ServiceUnavailable>()
+        .is_some()
+    {
+        r = Box::new(warp::reply::with_status(
+            "Service unavailable",
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    } else if err.find::<rsweb_api::filters::InternalError>().is_some() {
+        r = Box::new(warp::reply::with_status(
+            "Internal server error",
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ));
+    } else if err.find::<rsweb_api::filters::Unverified>().is_some() {
+        r = Box::new(warp::reply::with_status(
+            "Email address not verified",
+            warp::http::StatusCode::FORBIDDEN,
+        ));
     } else if err.find::<rsweb_api::filters::Unauthorized>().is_some() {
         r = Box::new(warp::reply::with_status(
             "Unauthorized",